@@ -0,0 +1,147 @@
+//! Ray intersection with each kind of object, and building and traversing BVHs
+//!
+//! Every ray and object comes from a fixed seed, so runs on different
+//! commits test exactly the same rays against exactly the same scenes.
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use ray_tracer_rust::{
+    aabb::AABB,
+    bvh::BVHNode,
+    constant_medium::ConstantMedium,
+    hittable::{HitRecord, HittableObject},
+    material::Lambertian,
+    quad::Quad,
+    random,
+    ray::{Ray, MIN_HIT_DISTANCE},
+    sphere::Sphere,
+    vector::{Color, Float, Point3, Vec3},
+};
+use std::sync::Arc;
+
+const SEED: u64 = 0x6265_6e63_6821;
+const RAY_COUNT: usize = 1024;
+
+/// Rays from all around the unit cube at the origin, aimed at random points
+/// in a box a little bigger than it, so some hit and some miss
+fn rays(rng: &mut SmallRng) -> Vec<Ray> {
+    (0..RAY_COUNT)
+        .map(|_| {
+            let origin = Point3::new(
+                rng.gen_range(-5.0..5.0),
+                rng.gen_range(-5.0..5.0),
+                rng.gen_range(-5.0..5.0),
+            );
+            let target = Point3::new(
+                rng.gen_range(-1.5..1.5),
+                rng.gen_range(-1.5..1.5),
+                rng.gen_range(-1.5..1.5),
+            );
+
+            Ray::new(origin, target - origin, rng.gen())
+        })
+        .collect()
+}
+
+/// Small spheres scattered through the unit cube at the origin
+fn spheres(rng: &mut SmallRng, count: usize) -> Vec<Arc<dyn HittableObject>> {
+    let material = Arc::new(Lambertian::from_color(Color::splat(0.5)));
+
+    (0..count)
+        .map(|_| {
+            let center = Point3::new(
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+            );
+
+            Arc::new(Sphere::new(center, Vec3::ZERO, 0.02, material.clone()))
+                as Arc<dyn HittableObject>
+        })
+        .collect()
+}
+
+/// Tests every ray against `object`, counting the hits
+fn hit_all(object: &dyn HittableObject, rays: &[Ray]) -> usize {
+    rays.iter()
+        .filter(|ray| {
+            let mut record = HitRecord::new(ray);
+            object.hit(ray, &(MIN_HIT_DISTANCE..Float::INFINITY), &mut record)
+        })
+        .count()
+}
+
+fn primitives(c: &mut Criterion) {
+    let mut rng = SmallRng::seed_from_u64(SEED);
+    let rays = rays(&mut rng);
+    let material = Arc::new(Lambertian::from_color(Color::splat(0.5)));
+
+    let sphere = Sphere::new(Point3::ORIGIN, Vec3::ZERO, 1.0, material.clone());
+    let quad = Quad::new(
+        Point3::new(-1.0, -1.0, 0.0),
+        Vec3::new(2.0, 0.0, 0.0),
+        Vec3::new(0.0, 2.0, 0.0),
+        material,
+    );
+    let aabb = AABB::new(-1.0..1.0, -1.0..1.0, -1.0..1.0);
+
+    let mut group = c.benchmark_group("hit");
+
+    group.bench_function("sphere", |b| b.iter(|| hit_all(&sphere, black_box(&rays))));
+    group.bench_function("quad", |b| b.iter(|| hit_all(&quad, black_box(&rays))));
+    group.bench_function("aabb", |b| {
+        b.iter(|| {
+            black_box(&rays)
+                .iter()
+                .filter(|ray| aabb.hit(ray, MIN_HIT_DISTANCE..Float::INFINITY))
+                .count()
+        })
+    });
+
+    let boundary: Arc<dyn HittableObject> = Arc::new(Sphere::new(
+        Point3::ORIGIN,
+        Vec3::ZERO,
+        1.0,
+        Arc::new(Lambertian::from_color(Color::ONE)),
+    ));
+    let medium = ConstantMedium::from_color(boundary, 0.5, Color::ONE);
+
+    // The medium picks where rays scatter at random
+    random::seed(SEED);
+    group.bench_function("constant_medium", |b| {
+        b.iter(|| hit_all(&medium, black_box(&rays)))
+    });
+
+    group.finish();
+}
+
+fn bvh(c: &mut Criterion) {
+    let mut rng = SmallRng::seed_from_u64(SEED);
+    let rays = rays(&mut rng);
+    let objects = spheres(&mut rng, 10_000);
+
+    let mut group = c.benchmark_group("bvh");
+
+    group.sample_size(20);
+    group.bench_function("build", |b| {
+        b.iter_batched(|| objects.clone(), BVHNode::from, BatchSize::LargeInput)
+    });
+
+    let bvh = BVHNode::from(objects);
+
+    group.sample_size(100);
+    group.bench_function("hit", |b| b.iter(|| hit_all(&bvh, black_box(&rays))));
+    group.bench_function("occluded", |b| {
+        b.iter(|| {
+            black_box(&rays)
+                .iter()
+                .filter(|ray| bvh.occluded(ray, &(MIN_HIT_DISTANCE..Float::INFINITY)))
+                .count()
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, primitives, bvh);
+criterion_main!(benches);
@@ -0,0 +1,100 @@
+//! Low sample count renders of every built-in scene, and of the scenes
+//! with the most objects with camera rays traced in packets and one by one
+//!
+//! Scenes get built from the same seed and every sample is seeded from
+//! its pixel, so each run renders exactly the same image. Only the render
+//! is timed, not building the scene.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use ray_tracer_rust::{
+    accelerator::Accelerator,
+    camera::{Camera, RenderOptions, RenderSettings},
+    film::Film,
+    integrator::IntegratorKind,
+    random,
+    scene::build_scene,
+};
+use std::time::Duration;
+
+const SEED: u64 = 0;
+
+fn scenes(c: &mut Criterion) {
+    let render_settings = RenderSettings {
+        image_width: 96,
+        samples_per_pixel: 2,
+        max_depth: 10,
+    };
+    let options = RenderOptions {
+        seed: SEED,
+        ..Default::default()
+    };
+
+    let mut group = c.benchmark_group("render");
+    group.sample_size(10);
+    group.measurement_time(Duration::from_secs(10));
+
+    for id in 0..=u8::MAX {
+        random::seed(SEED);
+
+        let Some(scene) = build_scene(id, &Accelerator::default()) else {
+            break;
+        };
+
+        let camera = Camera::new(&scene.camera_settings, &render_settings);
+
+        group.bench_function(format!("scene_{}", id), |b| {
+            b.iter(|| {
+                let mut film = Film::new(camera.image_width(), camera.image_height());
+                camera.render_film(&scene, &mut film, &options);
+                film
+            })
+        });
+    }
+
+    group.finish();
+}
+
+/// The forest is one big BVH of instances, and the final scene a BVH of
+/// spheres next to volumes, whose rays the packets give up on. Normals only
+/// need the first hit, so finding it is most of the work.
+fn packets(c: &mut Criterion) {
+    let render_settings = RenderSettings {
+        image_width: 200,
+        samples_per_pixel: 4,
+        max_depth: 10,
+    };
+
+    let mut group = c.benchmark_group("packets");
+    group.sample_size(10);
+    group.measurement_time(Duration::from_secs(10));
+
+    for id in [8, 9] {
+        random::seed(SEED);
+
+        let scene = build_scene(id, &Accelerator::default()).unwrap();
+        let camera = Camera::new(&scene.camera_settings, &render_settings);
+        let integrator = IntegratorKind::Normals.build(&scene);
+
+        for (name, packets) in [("single", false), ("packets", true)] {
+            let options = RenderOptions {
+                seed: SEED,
+                integrator: Some(integrator.as_ref()),
+                packets,
+                ..Default::default()
+            };
+
+            group.bench_function(format!("scene_{}/{}", id, name), |b| {
+                b.iter(|| {
+                    let mut film = Film::new(camera.image_width(), camera.image_height());
+                    camera.render_film(&scene, &mut film, &options);
+                    film
+                })
+            });
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, scenes, packets);
+criterion_main!(benches);
@@ -0,0 +1,63 @@
+//! Sampling each kind of texture
+//!
+//! The points and texture coordinates come from a fixed seed,
+//! so every run samples the same ones.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use ray_tracer_rust::{
+    texture::{CheckerBoard, ImageTexture, NoiseTexture, SolidColor, Texture},
+    vector::{Color, Point3, Vec2},
+};
+
+const SEED: u64 = 0x7465_7874_7572;
+const SAMPLE_COUNT: usize = 1024;
+
+fn sample_all(texture: &dyn Texture, samples: &[(Vec2, Point3)]) -> Color {
+    samples
+        .iter()
+        .map(|(uv, point)| texture.sample(uv, point))
+        .sum()
+}
+
+fn textures(c: &mut Criterion) {
+    let mut rng = SmallRng::seed_from_u64(SEED);
+    let samples: Vec<(Vec2, Point3)> = (0..SAMPLE_COUNT)
+        .map(|_| {
+            let uv = Vec2::new(rng.gen(), rng.gen());
+            let point = Point3::new(
+                rng.gen_range(-10.0..10.0),
+                rng.gen_range(-10.0..10.0),
+                rng.gen_range(-10.0..10.0),
+            );
+
+            (uv, point)
+        })
+        .collect();
+
+    let solid = SolidColor::new(0.2, 0.4, 0.8);
+    let checker =
+        CheckerBoard::from_colors(0.32, Color::new(0.2, 0.3, 0.1), Color::new(0.9, 0.9, 0.9));
+    let image = ImageTexture::new("./images/earth-realistic.jpg");
+    let noise = NoiseTexture::new(0, 4.0, 10.0, 6, 1.0, 1.0);
+
+    let mut group = c.benchmark_group("texture");
+
+    group.bench_function("solid", |b| {
+        b.iter(|| sample_all(&solid, black_box(&samples)))
+    });
+    group.bench_function("checker", |b| {
+        b.iter(|| sample_all(&checker, black_box(&samples)))
+    });
+    group.bench_function("image", |b| {
+        b.iter(|| sample_all(&image, black_box(&samples)))
+    });
+    group.bench_function("noise", |b| {
+        b.iter(|| sample_all(&noise, black_box(&samples)))
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, textures);
+criterion_main!(benches);
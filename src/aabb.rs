@@ -1,21 +1,29 @@
 //! Axis-Aligned Bounding Box
 //! This should help speed up the rendering times dramatically
 
-use crate::ray::Ray;
-use crate::vector::Vec3;
+use crate::ray::{self, Ray};
+use crate::stats::{self, Counter};
+use crate::vector::{Float, Point3, Vec3};
 use std::ops::{Add, Range};
 
-const EPSILON: f64 = 0.0001;
+const EPSILON: Float = 0.0001;
+
+/// What to scale how far along a ray it leaves a box by in slab tests.
+/// Rounding can put the exit just before the entry for rays that only graze
+/// a box, and missing the box would mean missing everything in it, so exits
+/// get stretched by as much as the rounding could have moved them. See
+/// "Robust BVH Ray Traversal" by Thiago Ize, which PBRT follows.
+pub const EXIT_SCALE: Float = 1.0 + 2.0 * ray::gamma(3);
 
 #[derive(Clone, Debug, Default)]
 pub struct AABB {
-    pub x: Range<f64>,
-    pub y: Range<f64>,
-    pub z: Range<f64>,
+    pub x: Range<Float>,
+    pub y: Range<Float>,
+    pub z: Range<Float>,
 }
 
 impl AABB {
-    pub fn new(x: Range<f64>, y: Range<f64>, z: Range<f64>) -> Self {
+    pub fn new(x: Range<Float>, y: Range<Float>, z: Range<Float>) -> Self {
         // NOTE: Not sure if this it totally necessary
         let x = match x.start < x.end {
             true => x,
@@ -38,12 +46,12 @@ impl AABB {
         new_aabb
     }
 
-    pub fn from_points(a: &Vec3, b: &Vec3) -> Self {
+    pub fn from_points(a: &Point3, b: &Point3) -> Self {
         Self::new(a.x..b.x, a.y..b.y, a.z..b.z)
     }
 
     pub fn new_universe() -> Self {
-        let infinite_range = f64::NEG_INFINITY..f64::INFINITY;
+        let infinite_range = Float::NEG_INFINITY..Float::INFINITY;
 
         Self::new(
             infinite_range.clone(),
@@ -55,33 +63,35 @@ impl AABB {
     pub fn combine_bounds(a: &Self, b: &Self) -> Self {
         Self {
             x: Range {
-                start: f64::min(a.x.start, b.x.start),
-                end: f64::max(a.x.end, b.x.end),
+                start: Float::min(a.x.start, b.x.start),
+                end: Float::max(a.x.end, b.x.end),
             },
             y: Range {
-                start: f64::min(a.y.start, b.y.start),
-                end: f64::max(a.y.end, b.y.end),
+                start: Float::min(a.y.start, b.y.start),
+                end: Float::max(a.y.end, b.y.end),
             },
             z: Range {
-                start: f64::min(a.z.start, b.z.start),
-                end: f64::max(a.z.end, b.z.end),
+                start: Float::min(a.z.start, b.z.start),
+                end: Float::max(a.z.end, b.z.end),
             },
         }
     }
 
-    pub fn hit(&self, ray: &Ray, mut range: Range<f64>) -> bool {
+    pub fn hit(&self, ray: &Ray, mut range: Range<Float>) -> bool {
+        stats::count(Counter::AabbTests);
+
         for (i, axis) in self.axes().enumerate() {
-            let adinv: f64 = ray.direction()[i].recip();
+            let adinv: Float = ray.direction()[i].recip();
 
             let t0 = (axis.start - ray.origin()[i]) * adinv;
             let t1 = (axis.end - ray.origin()[i]) * adinv;
 
             if t0 < t1 {
-                range.start = f64::max(range.start, t0);
-                range.end = f64::min(range.end, t1);
+                range.start = Float::max(range.start, t0);
+                range.end = Float::min(range.end, t1 * EXIT_SCALE);
             } else {
-                range.start = f64::max(range.start, t1);
-                range.end = f64::min(range.end, t0);
+                range.start = Float::max(range.start, t1);
+                range.end = Float::min(range.end, t0 * EXIT_SCALE);
             }
 
             if range.end <= range.start {
@@ -99,7 +109,7 @@ impl AABB {
         }
     }
 
-    pub fn axis(&self, i: usize) -> Option<Range<f64>> {
+    pub fn axis(&self, i: usize) -> Option<Range<Float>> {
         match i {
             0 => Some(self.x.clone()),
             1 => Some(self.y.clone()),
@@ -123,6 +133,25 @@ impl AABB {
         }
     }
 
+    pub fn centroid(&self) -> Point3 {
+        Point3::new(
+            (self.x.start + self.x.end) * 0.5,
+            (self.y.start + self.y.end) * 0.5,
+            (self.z.start + self.z.end) * 0.5,
+        )
+    }
+
+    /// Whether the box has an end along every axis
+    pub fn is_finite(&self) -> bool {
+        self.axes()
+            .all(|axis| axis.start.is_finite() && axis.end.is_finite())
+    }
+
+    pub fn surface_area(&self) -> Float {
+        let size = self.get_sizes();
+        2.0 * (size.x * size.y + size.y * size.z + size.z * size.x)
+    }
+
     fn get_sizes(&self) -> Vec3 {
         let x_size = self.x.end - self.x.start;
         let y_size = self.y.end - self.y.start;
@@ -157,9 +186,9 @@ pub struct AabbAxisIterator<'a> {
 }
 
 impl<'a> Iterator for AabbAxisIterator<'a> {
-    type Item = Range<f64>;
+    type Item = Range<Float>;
 
-    fn next(&mut self) -> Option<Range<f64>> {
+    fn next(&mut self) -> Option<Range<Float>> {
         let result = self.aabb.axis(self.index);
         self.index += 1;
         result
@@ -0,0 +1,101 @@
+//! Picking which acceleration structure scenes put their objects in
+
+use crate::bvh::BVHNode;
+use crate::bvh_cache::BvhCache;
+use crate::grid::Grid;
+use crate::hittable::HittableObject;
+use crate::kdtree::KdTree;
+
+use std::{
+    fmt,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum AcceleratorKind {
+    #[default]
+    Bvh,
+    Grid,
+    KdTree,
+}
+
+impl AcceleratorKind {
+    pub const ALL: [AcceleratorKind; 3] = [
+        AcceleratorKind::Bvh,
+        AcceleratorKind::Grid,
+        AcceleratorKind::KdTree,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AcceleratorKind::Bvh => "bvh",
+            AcceleratorKind::Grid => "grid",
+            AcceleratorKind::KdTree => "kd-tree",
+        }
+    }
+}
+
+/// How scenes build the acceleration structures they keep their objects in
+#[derive(Clone, Default)]
+pub struct Accelerator {
+    pub kind: AcceleratorKind,
+    /// Where BVHs get kept between runs, if anywhere
+    pub cache: Option<Arc<BvhCache>>,
+    /// How long building structures has taken so far, without
+    /// the textures, images and objects that go into them
+    pub build_time: Arc<Mutex<Duration>>,
+}
+
+impl Accelerator {
+    /// A BVH over `objects`, whichever kind of structure was picked,
+    /// for things that need one in particular, like instances
+    pub fn bvh(&self, objects: Vec<Arc<dyn HittableObject>>) -> BVHNode {
+        self.timed(|| match &self.cache {
+            Some(cache) => cache.bvh(objects),
+            None => BVHNode::from(objects),
+        })
+    }
+
+    pub fn build(&self, objects: Vec<Arc<dyn HittableObject>>) -> Arc<dyn HittableObject> {
+        match self.kind {
+            // Already timed
+            AcceleratorKind::Bvh => Arc::new(self.bvh(objects)),
+            AcceleratorKind::Grid => self.timed(|| Arc::new(Grid::new(objects))),
+            AcceleratorKind::KdTree => self.timed(|| Arc::new(KdTree::new(objects))),
+        }
+    }
+
+    fn timed<T>(&self, build: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let built = build();
+        *self.build_time.lock().unwrap() += start.elapsed();
+
+        built
+    }
+}
+
+impl fmt::Display for AcceleratorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for AcceleratorKind {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|kind| kind.name() == text)
+            .ok_or_else(|| {
+                let names: Vec<&str> = Self::ALL.iter().map(|kind| kind.name()).collect();
+                format!(
+                    "unknown accelerator `{}`, use one of {}",
+                    text,
+                    names.join(", ")
+                )
+            })
+    }
+}
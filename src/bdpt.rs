@@ -0,0 +1,430 @@
+//! Bidirectional path tracing
+//!
+//! Every sample traces one path from the camera and one from a light,
+//! then joins every vertex of the first to every vertex of the second.
+//! Each way of building a path gets weighted by how likely it was to find it
+//! compared to all the other ways, so light that only gets in through small
+//! openings or off a bright ceiling is found from whichever end finds it best.
+//!
+//! Paths are never joined directly to the camera, so every strategy here
+//! traces at least one ray from the camera. That keeps every sample inside
+//! its own pixel, at the cost of caustics seen directly from the camera
+//! converging no faster than they do for `PathTracer`.
+
+use crate::hittable::HitRecord;
+use crate::integrator::{light_emission_pdf, Integrator, SceneView};
+use crate::ray::{self, Ray, MIN_HIT_DISTANCE};
+use crate::stats::{self, Counter};
+use crate::vector::{Color, Float, Normal3, Point3, Vec3};
+
+/// How far short of the other end a connecting shadow ray
+/// may be blocked and still count as unblocked, relative to its length
+const CONNECTION_EPSILON: Float = 1e-6;
+
+enum VertexKind<'a> {
+    /// The starting point of a light path, on the surface of a light
+    Light { emission: Color },
+    /// Anywhere a path hit something
+    Hit(HitRecord<'a>),
+}
+
+struct Vertex<'a> {
+    kind: VertexKind<'a>,
+    point: Point3,
+    /// How far `point` might be off the surface it's on along each axis
+    error: Vec3,
+    normal: Normal3,
+    /// The path's contribution up to this vertex, divided by how likely it was
+    throughput: Color,
+    /// The probability density of the path's own direction picking this vertex,
+    /// per unit area, or zero right after a specular bounce
+    pdf_forward: Float,
+    /// The same, if the path had been traced from its other end
+    pdf_reverse: Float,
+    /// Whether the vertex scatters specularly, so paths can't be joined at it
+    specular: bool,
+}
+
+impl Vertex<'_> {
+    /// How much area the vertex's surface shows to light going along `direction`
+    fn cosine(&self, direction: &Vec3) -> Float {
+        match &self.kind {
+            VertexKind::Hit(record) if record.mat.is_volumetric() => 1.0,
+            _ => Float::abs(self.normal.dot(direction.normalize())),
+        }
+    }
+
+    /// Turn a density per unit solid angle, as seen from `from`,
+    /// into a density per unit area at this vertex
+    fn area_density(&self, pdf: Float, from: &Point3) -> Float {
+        let direction = self.point - *from;
+        pdf * self.cosine(&direction) / direction.length_squared()
+    }
+
+    /// How much of the light following the path to this vertex gets
+    /// scattered towards `to`, including the cosine term on the side of `to`
+    fn scattering(&self, to: &Point3) -> Color {
+        match &self.kind {
+            VertexKind::Light { .. } => Vec3::ZERO,
+            VertexKind::Hit(record) => record.mat.scattering(record, &(*to - self.point)),
+        }
+    }
+
+    /// The probability density, per unit solid angle,
+    /// of a path through this vertex carrying on towards `to`
+    fn scattering_pdf(&self, to: &Point3) -> Float {
+        let direction = *to - self.point;
+
+        match &self.kind {
+            VertexKind::Light { .. } => light_emission_pdf(&self.normal, &direction),
+            VertexKind::Hit(record) => record.mat.scattering_pdf(record, &direction),
+        }
+    }
+
+    fn emission(&self) -> Color {
+        match &self.kind {
+            VertexKind::Light { emission } => *emission,
+            VertexKind::Hit(record) => record.mat.emitted(&record.uv, &record.point),
+        }
+    }
+}
+
+/// Extends `vertices` by following `ray` from one bounce to the next,
+/// until it escapes, stops scattering, or `vertices` holds `max_vertices`.
+/// Returns the throughput of the ray that escaped the scene, if one did.
+fn random_walk<'a>(
+    scene: &SceneView<'a>,
+    mut ray: Ray,
+    mut throughput: Color,
+    mut pdf: Float,
+    vertices: &mut Vec<Vertex<'a>>,
+    max_vertices: usize,
+) -> Option<Color> {
+    while vertices.len() < max_vertices {
+        let Some(mut record) = scene.hit(&ray) else {
+            return Some(throughput);
+        };
+
+        let mat = record.mat;
+
+        let mut vertex = Vertex {
+            point: record.point,
+            error: record.error,
+            normal: record.normal,
+            kind: VertexKind::Hit(record),
+            throughput,
+            pdf_forward: 0.0,
+            pdf_reverse: 0.0,
+            specular: mat.is_specular(),
+        };
+
+        vertex.pdf_forward = vertex.area_density(pdf, &ray.origin());
+
+        // The vertex before this one, as seen from this one
+        if let Some(previous) = vertices.last_mut() {
+            let pdf_reverse = match mat.is_specular() {
+                true => 0.0,
+                false => mat.scattering_pdf(&record, &(previous.point - record.point)),
+            };
+
+            previous.pdf_reverse = previous.area_density(pdf_reverse, &record.point);
+        }
+
+        vertices.push(vertex);
+
+        if vertices.len() == max_vertices {
+            break;
+        }
+
+        let mut scattered = Ray::new(Point3::ORIGIN, Vec3::ZERO, ray.time());
+        let mut attenuation = Vec3::ZERO;
+
+        if !mat.scatter(&mut record, &mut attenuation, &mut scattered) {
+            break;
+        }
+
+        stats::count(Counter::SecondaryRays);
+
+        pdf = match mat.is_specular() {
+            true => 0.0,
+            false => mat.scattering_pdf(&record, &scattered.direction()),
+        };
+
+        throughput *= attenuation;
+        ray = scattered;
+    }
+
+    None
+}
+
+/// Trace a path from a random point on a random light
+fn light_path<'a>(
+    scene: &SceneView<'a>,
+    time: Float,
+    vertices: &mut Vec<Vertex<'a>>,
+    max_vertices: usize,
+) {
+    if max_vertices == 0 {
+        return;
+    }
+
+    let Some(emitted) = scene.emit_light_ray(time) else {
+        return;
+    };
+
+    let vertex = Vertex {
+        kind: VertexKind::Light {
+            emission: emitted.emission,
+        },
+        // Already moved off the light
+        point: emitted.ray.origin(),
+        error: Vec3::ZERO,
+        normal: emitted.normal,
+        throughput: Vec3::ONE / emitted.point_pdf,
+        pdf_forward: emitted.point_pdf,
+        pdf_reverse: 0.0,
+        specular: false,
+    };
+
+    let throughput = vertex.throughput * emitted.emission * vertex.cosine(&emitted.ray.direction())
+        / emitted.direction_pdf;
+
+    vertices.push(vertex);
+    stats::count(Counter::SecondaryRays);
+    random_walk(
+        scene,
+        emitted.ray,
+        throughput,
+        emitted.direction_pdf,
+        vertices,
+        max_vertices,
+    );
+}
+
+/// Whether nothing blocks the straight line between two vertices
+fn unoccluded(scene: &SceneView, from: &Vertex, to: &Vertex, time: Float) -> bool {
+    let direction = to.point - from.point;
+
+    stats::count(Counter::ShadowRays);
+
+    // Both ends start off their surfaces, so neither gets in the way
+    let origin = ray::offset_origin(&from.point, &from.error, &from.normal, &direction);
+    let target = ray::offset_origin(&to.point, &to.error, &to.normal, &-direction);
+    let ray = Ray::new(origin, target - origin, time);
+
+    !scene
+        .world
+        .occluded(&ray, &(MIN_HIT_DISTANCE..1.0 - CONNECTION_EPSILON))
+}
+
+/// One vertex of a whole path, in order from the light to the camera
+#[derive(Debug, Copy, Clone)]
+struct PathDensities {
+    /// Density of the vertex when picked by tracing from the light
+    from_light: Float,
+    /// Density of the vertex when picked by tracing from the camera
+    from_camera: Float,
+    specular: bool,
+}
+
+/// Specular bounces leave a density of zero on both sides,
+/// which is the same whichever way the path was traced
+fn remap_zero(pdf: Float) -> Float {
+    match pdf == 0.0 {
+        true => 1.0,
+        false => pdf,
+    }
+}
+
+/// The weight of the path made of the first `s` light vertices and the first `t`
+/// camera vertices (counting the camera) among every way of building it,
+/// following the power heuristic.
+/// `camera` doesn't hold the camera itself, and neither the camera nor the
+/// first vertex it sees ever take part in the weights, since every strategy
+/// traces those two from the camera.
+fn mis_weight(
+    scene: &SceneView,
+    light: &[Vertex],
+    camera: &[Vertex],
+    camera_origin: &Point3,
+    s: usize,
+    t: usize,
+    time: Float,
+) -> Float {
+    let n = s + t;
+
+    if n <= 2 {
+        return 1.0;
+    }
+
+    // The camera vertex at index `i` of the whole path
+    let camera_vertex = |i: usize| &camera[n - 2 - i];
+
+    let mut path: Vec<PathDensities> = (0..n - 1)
+        .map(|i| match i < s {
+            true => PathDensities {
+                from_light: light[i].pdf_forward,
+                from_camera: light[i].pdf_reverse,
+                specular: light[i].specular,
+            },
+            false => PathDensities {
+                from_light: camera_vertex(i).pdf_reverse,
+                from_camera: camera_vertex(i).pdf_forward,
+                specular: camera_vertex(i).specular,
+            },
+        })
+        .collect();
+
+    // Where the two paths were joined, densities depend on the other side
+    let last_camera = camera_vertex(s);
+    let camera_before = match t >= 3 {
+        true => camera_vertex(s + 1).point,
+        false => *camera_origin,
+    };
+
+    if s == 0 {
+        // The camera path found a light on its own
+        let direction = last_camera.point - camera_before;
+        let pdf = scene.light_pdf(&camera_before, &direction, time);
+        path[0].from_light = last_camera.area_density(pdf, &camera_before);
+
+        if t >= 4 {
+            let pdf = light_emission_pdf(&last_camera.normal, &(camera_before - last_camera.point));
+            path[1].from_light = camera_vertex(1).area_density(pdf, &last_camera.point);
+        }
+    } else {
+        let last_light = &light[s - 1];
+
+        let pdf = last_camera.scattering_pdf(&last_light.point);
+        path[s - 1].from_camera = last_light.area_density(pdf, &last_camera.point);
+
+        if s >= 2 {
+            let pdf = last_light.scattering_pdf(&light[s - 2].point);
+            path[s - 2].from_camera = light[s - 2].area_density(pdf, &last_light.point);
+        }
+
+        let pdf = last_light.scattering_pdf(&last_camera.point);
+        path[s].from_light = last_camera.area_density(pdf, &last_light.point);
+
+        if t >= 4 {
+            let pdf = last_camera.scattering_pdf(&camera_before);
+            path[s + 1].from_light = camera_vertex(s + 1).area_density(pdf, &last_camera.point);
+        }
+    }
+
+    // Strategy `k` joins vertices `k - 1` and `k`, which has to be possible
+    let can_join = |k: usize| k == 0 || !(path[k - 1].specular || path[k].specular);
+
+    // A light that can't be sampled leaves a density of zero at the
+    // start of the path, which rules out every strategy but `s == 0`
+    let light_density = |i: usize, vertex: &PathDensities| match i {
+        0 => vertex.from_light,
+        _ => remap_zero(vertex.from_light),
+    };
+
+    let mut sum = 0.0;
+
+    // Strategies with more light vertices
+    let mut ratio = 1.0;
+
+    for (i, vertex) in path.iter().enumerate().take(n - 2).skip(s) {
+        ratio *= light_density(i, vertex) / remap_zero(vertex.from_camera);
+
+        if can_join(i + 1) {
+            sum += ratio * ratio;
+        }
+    }
+
+    // Strategies with more camera vertices
+    let mut ratio = 1.0;
+
+    for (i, vertex) in path.iter().enumerate().take(s).rev() {
+        ratio *= remap_zero(vertex.from_camera) / light_density(i, vertex);
+
+        if can_join(i) {
+            sum += ratio * ratio;
+        }
+    }
+
+    1.0 / (1.0 + sum)
+}
+
+/// Bidirectional path tracing, see the module documentation
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Bidirectional;
+
+impl Integrator for Bidirectional {
+    fn radiance(&self, ray: &Ray, scene: &SceneView, _sample: u32) -> Color {
+        let time = ray.time();
+        let camera_origin = ray.origin();
+
+        let mut camera = Vec::with_capacity(scene.max_depth);
+        let mut light = Vec::with_capacity(scene.max_depth);
+
+        // The camera's own density never takes part in the weights,
+        // since paths are never joined straight to the camera
+        let escaped = random_walk(scene, *ray, Vec3::ONE, 1.0, &mut camera, scene.max_depth);
+
+        // The background only ever gets found from the camera
+        let mut color = match escaped {
+            Some(throughput) => throughput * scene.background_color,
+            None => Vec3::ZERO,
+        };
+
+        light_path(scene, time, &mut light, scene.max_depth.saturating_sub(1));
+
+        // `t` counts the camera, so `camera[t - 2]` is the last camera vertex
+        for t in 2..=(camera.len() + 1) {
+            let last_camera = &camera[t - 2];
+
+            for s in 0..=light.len() {
+                // Paths can't be longer than the camera path could have made them
+                if s + t - 1 > scene.max_depth {
+                    break;
+                }
+
+                let contribution = match s {
+                    0 => last_camera.throughput * last_camera.emission(),
+                    _ => {
+                        let last_light = &light[s - 1];
+
+                        if last_camera.specular || last_light.specular {
+                            continue;
+                        }
+
+                        let direction = last_light.point - last_camera.point;
+                        let distance_squared = direction.length_squared();
+
+                        let light_side = match s {
+                            1 => last_light.emission() * last_light.cosine(&direction),
+                            _ => last_light.scattering(&last_camera.point),
+                        };
+
+                        let contribution = last_light.throughput
+                            * light_side
+                            * last_camera.scattering(&last_light.point)
+                            * last_camera.throughput
+                            / distance_squared;
+
+                        if contribution.is_near_zero()
+                            || !unoccluded(scene, last_camera, last_light, time)
+                        {
+                            continue;
+                        }
+
+                        contribution
+                    }
+                };
+
+                if contribution.is_near_zero() {
+                    continue;
+                }
+
+                let weight = mis_weight(scene, &light, &camera, &camera_origin, s, t, time);
+                color += contribution * weight;
+            }
+        }
+
+        color
+    }
+}
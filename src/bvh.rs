@@ -1,93 +1,1573 @@
 //! BVH Tree
+//!
+//! The tree is built top down with the surface area heuristic: every node
+//! gets split wherever a ray that hits it is expected to have the least work
+//! left to do, judging by how likely the ray is to hit each side's box.
+//! Nodes with only a few objects left become leaves once splitting them
+//! no longer pays off. Big trees get built on several threads, both in
+//! sorting a node's objects into bins and in building its two children at
+//! once, and come out exactly like the tree a single thread would build.
+//!
+//! The finished tree lives in one array, in depth first order, so every
+//! node's first child comes right after it. For traversal, every few levels
+//! of it get squashed into nodes with four children, whose boxes a ray gets
+//! tested against all at once, with SSE2 when the `simd` feature is on.
+//! Traversal walks those with a small stack instead of recursing through
+//! trait objects, going into the nearest children first.
+//!
+//! Trees over objects that move can be refitted instead of rebuilt,
+//! and take objects in and out as they go. Either way the tree slowly gets
+//! worse than a fresh one, so once its expected cost has grown too much
+//! it gets rebuilt from scratch.
 
 use crate::{
-    aabb::AABB,
+    aabb::{AABB, EXIT_SCALE},
+    encoding::{self, invalid_data, read_f64, read_u32, read_u64, read_u8},
     hittable::{HitRecord, HittableObject},
-    ray::Ray,
+    material::Material,
+    packet::{self, RayPacket},
+    ray::{Ray, MIN_HIT_DISTANCE},
+    stats::{self, Counter},
+    vector::{self, Float},
 };
 
-use std::{cmp::Ordering, ops::Range, sync::Arc};
+use rayon::prelude::*;
+use std::{
+    collections::HashMap,
+    io::{self, Read, Write},
+    mem::{self, MaybeUninit},
+    ops::Range,
+    sync::Arc,
+};
+
+/// How many objects a leaf may hold unless asked otherwise
+pub const DEFAULT_MAX_LEAF_SIZE: usize = 4;
+
+/// How many evenly spaced candidate positions to try for each split, per axis
+const SAH_BINS: usize = 16;
+
+/// What visiting a node costs, relative to testing an object
+const TRAVERSAL_COST: Float = 1.0;
+
+/// How deep the tree can get, which is how many nodes traversal
+/// may have to come back to at once
+const MAX_TREE_DEPTH: usize = 64;
+
+/// Subtrees with at least this many objects get built on several threads
+const PARALLEL_BUILD_THRESHOLD: usize = 4096;
+
+/// How many objects each thread summarizes at a time
+const PARALLEL_CHUNK_SIZE: usize = 1024;
+
+/// How many times worse the expected cost of traversing a tree may get,
+/// from refitting and inserting, before it gets rebuilt
+const REBUILD_RATIO: Float = 1.5;
+
+/// How many children each node traversal walks through has
+const WIDTH: usize = 4;
+
+/// How many nodes traversal may have to come back to at once: every node
+/// it visits takes one off the stack and puts at most `WIDTH` back on
+const STACK_SIZE: usize = (MAX_TREE_DEPTH + 1) * WIDTH;
+
+/// Below this depth, nodes are always split down the middle,
+/// so even a billion objects fit in `MAX_TREE_DEPTH` levels
+const BALANCED_DEPTH: usize = MAX_TREE_DEPTH - 32;
+
+/// The children traversal still has to come back to. Nothing in it gets read
+/// before it's written, so it's left uninitialized instead of being zeroed for
+/// every ray.
+struct TraversalStack<T: Copy> {
+    entries: [MaybeUninit<T>; STACK_SIZE],
+    len: usize,
+}
+
+impl<T: Copy> TraversalStack<T> {
+    #[inline]
+    fn new() -> Self {
+        Self {
+            entries: [const { MaybeUninit::uninit() }; STACK_SIZE],
+            len: 0,
+        }
+    }
+
+    #[inline]
+    fn push(&mut self, entry: T) {
+        self.entries[self.len].write(entry);
+        self.len += 1;
+    }
+
+    /// Push `entry` underneath those entries pushed since the stack was
+    /// `since` long that `above` says go above it, so they come off first
+    #[inline]
+    fn push_below(&mut self, since: usize, entry: T, above: impl Fn(&T) -> bool) {
+        let mut slot = self.len;
+
+        // SAFETY: every slot below `len` has been written
+        while slot > since && above(unsafe { self.entries[slot - 1].assume_init_ref() }) {
+            self.entries[slot] = self.entries[slot - 1];
+            slot -= 1;
+        }
+
+        self.entries[slot].write(entry);
+        self.len += 1;
+    }
+
+    #[inline]
+    fn pop(&mut self) -> Option<T> {
+        self.len = self.len.checked_sub(1)?;
+
+        // SAFETY: every slot below the old `len` has been written
+        Some(unsafe { self.entries[self.len].assume_init() })
+    }
+}
+
+/// Everything besides the objects that decides which tree a build makes,
+/// so trees built with other settings are never taken for each other
+pub(crate) fn build_parameters(max_leaf_size: usize) -> [u64; 6] {
+    [
+        max_leaf_size.clamp(1, u16::MAX as usize) as u64,
+        SAH_BINS as u64,
+        vector::to_f64(TRAVERSAL_COST).to_bits(),
+        MAX_TREE_DEPTH as u64,
+        BALANCED_DEPTH as u64,
+        WIDTH as u64,
+    ]
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct FlatNode {
+    min: [Float; 3],
+    max: [Float; 3],
+    /// The index of the second child, or of a leaf's first object
+    offset: u32,
+    /// How many objects a leaf holds
+    count: u16,
+    /// Whether the node holds objects rather than children.
+    /// Leaves can end up empty once their objects get removed.
+    leaf: bool,
+}
+
+impl FlatNode {
+    fn new(aabb: &AABB) -> Self {
+        Self {
+            min: [aabb.x.start, aabb.y.start, aabb.z.start],
+            max: [aabb.x.end, aabb.y.end, aabb.z.end],
+            offset: 0,
+            count: 0,
+            leaf: false,
+        }
+    }
+
+    fn set_aabb(&mut self, aabb: &AABB) {
+        self.min = [aabb.x.start, aabb.y.start, aabb.z.start];
+        self.max = [aabb.x.end, aabb.y.end, aabb.z.end];
+    }
+
+    fn aabb(&self) -> AABB {
+        AABB {
+            x: self.min[0]..self.max[0],
+            y: self.min[1]..self.max[1],
+            z: self.min[2]..self.max[2],
+        }
+    }
+}
+
+/// A node of the tree that traversal walks, which tests a ray against
+/// the boxes of up to `WIDTH` children at once. Wide nodes are made from the
+/// binary tree by opening up children until there are `WIDTH` of them.
+#[derive(Debug, Clone)]
+struct WideNode {
+    /// The children's boxes, by axis and then by child.
+    /// Unused children get boxes no ray can go through.
+    min: [[Float; WIDTH]; 3],
+    max: [[Float; WIDTH]; 3],
+    /// The index of each child node, or of each leaf's first object
+    offsets: [u32; WIDTH],
+    /// How many objects each leaf holds
+    counts: [u16; WIDTH],
+    leaves: [bool; WIDTH],
+    /// How many of the children are used
+    lanes: u8,
+}
+
+impl WideNode {
+    fn child_box(&self, lane: usize) -> ([Float; 3], [Float; 3]) {
+        (
+            [self.min[0][lane], self.min[1][lane], self.min[2][lane]],
+            [self.max[0][lane], self.max[1][lane], self.max[2][lane]],
+        )
+    }
+
+    fn new() -> Self {
+        Self {
+            min: [[Float::INFINITY; WIDTH]; 3],
+            max: [[Float::INFINITY; WIDTH]; 3],
+            offsets: [0; WIDTH],
+            counts: [0; WIDTH],
+            leaves: [false; WIDTH],
+            lanes: 0,
+        }
+    }
+
+    /// How far along a ray from `origin` with the given inverse direction
+    /// it goes into each child's box within `range`, or infinity for
+    /// the boxes it misses
+    #[cfg(not(all(feature = "simd", target_arch = "x86_64")))]
+    #[inline]
+    fn hit(
+        &self,
+        origin: &[Float; 3],
+        inverse_direction: &[Float; 3],
+        range: &Range<Float>,
+    ) -> [Float; WIDTH] {
+        let mut start = [range.start; WIDTH];
+        let mut end = [range.end; WIDTH];
+
+        for axis in 0..3 {
+            for lane in 0..WIDTH {
+                let t0 = (self.min[axis][lane] - origin[axis]) * inverse_direction[axis];
+                let t1 = (self.max[axis][lane] - origin[axis]) * inverse_direction[axis];
+
+                start[lane] = Float::max(start[lane], Float::min(t0, t1));
+                end[lane] = Float::min(end[lane], Float::max(t0, t1) * EXIT_SCALE);
+            }
+        }
+
+        std::array::from_fn(|lane| match start[lane] < end[lane] {
+            true => start[lane],
+            false => Float::INFINITY,
+        })
+    }
+
+    /// How far along a ray from `origin` with the given inverse direction
+    /// it goes into each child's box within `range`, or infinity for
+    /// the boxes it misses. Tests two boxes per instruction with SSE2.
+    #[cfg(all(feature = "simd", not(feature = "f32"), target_arch = "x86_64"))]
+    #[inline]
+    fn hit(
+        &self,
+        origin: &[Float; 3],
+        inverse_direction: &[Float; 3],
+        range: &Range<Float>,
+    ) -> [Float; WIDTH] {
+        use std::arch::x86_64::*;
+
+        let mut entries = [Float::INFINITY; WIDTH];
+
+        // SSE2 is part of x86_64, so every x86_64 CPU has it
+        unsafe {
+            let exit_scale = _mm_set1_pd(EXIT_SCALE);
+
+            for lane in (0..WIDTH).step_by(2) {
+                let mut start = _mm_set1_pd(range.start);
+                let mut end = _mm_set1_pd(range.end);
+
+                for axis in 0..3 {
+                    let origin = _mm_set1_pd(origin[axis]);
+                    let inverse_direction = _mm_set1_pd(inverse_direction[axis]);
+
+                    let min = _mm_loadu_pd(self.min[axis][lane..].as_ptr());
+                    let max = _mm_loadu_pd(self.max[axis][lane..].as_ptr());
+                    let t0 = _mm_mul_pd(_mm_sub_pd(min, origin), inverse_direction);
+                    let t1 = _mm_mul_pd(_mm_sub_pd(max, origin), inverse_direction);
+
+                    // These return their second argument when either is NaN,
+                    // which a ray along the edge of a box can give,
+                    // so the range goes second to be left alone like `Float::max` does
+                    start = _mm_max_pd(_mm_min_pd(t0, t1), start);
+                    end = _mm_min_pd(_mm_mul_pd(_mm_max_pd(t0, t1), exit_scale), end);
+                }
+
+                let hit = _mm_cmplt_pd(start, end);
+                let entry = _mm_or_pd(
+                    _mm_and_pd(hit, start),
+                    _mm_andnot_pd(hit, _mm_set1_pd(Float::INFINITY)),
+                );
+
+                _mm_storeu_pd(entries[lane..].as_mut_ptr(), entry);
+            }
+        }
+
+        entries
+    }
+
+    /// How far along a ray from `origin` with the given inverse direction
+    /// it goes into each child's box within `range`, or infinity for
+    /// the boxes it misses. In single precision all four boxes fit in one
+    /// SSE register.
+    #[cfg(all(feature = "simd", feature = "f32", target_arch = "x86_64"))]
+    #[inline]
+    fn hit(
+        &self,
+        origin: &[Float; 3],
+        inverse_direction: &[Float; 3],
+        range: &Range<Float>,
+    ) -> [Float; WIDTH] {
+        use std::arch::x86_64::*;
+
+        let mut entries = [Float::INFINITY; WIDTH];
+
+        // SSE is part of x86_64, so every x86_64 CPU has it
+        unsafe {
+            let exit_scale = _mm_set1_ps(EXIT_SCALE);
+            let mut start = _mm_set1_ps(range.start);
+            let mut end = _mm_set1_ps(range.end);
+
+            for axis in 0..3 {
+                let origin = _mm_set1_ps(origin[axis]);
+                let inverse_direction = _mm_set1_ps(inverse_direction[axis]);
+
+                let min = _mm_loadu_ps(self.min[axis].as_ptr());
+                let max = _mm_loadu_ps(self.max[axis].as_ptr());
+                let t0 = _mm_mul_ps(_mm_sub_ps(min, origin), inverse_direction);
+                let t1 = _mm_mul_ps(_mm_sub_ps(max, origin), inverse_direction);
+
+                // The range goes second for the same NaN handling as above
+                start = _mm_max_ps(_mm_min_ps(t0, t1), start);
+                end = _mm_min_ps(_mm_mul_ps(_mm_max_ps(t0, t1), exit_scale), end);
+            }
+
+            let hit = _mm_cmplt_ps(start, end);
+            let entry = _mm_or_ps(
+                _mm_and_ps(hit, start),
+                _mm_andnot_ps(hit, _mm_set1_ps(Float::INFINITY)),
+            );
+
+            _mm_storeu_ps(entries.as_mut_ptr(), entry);
+        }
 
+        entries
+    }
+}
+
+/// A bounding volume hierarchy over a list of objects
 pub struct BVHNode {
-    left: Arc<dyn HittableObject>,
-    right: Arc<dyn HittableObject>,
+    nodes: Vec<FlatNode>,
+    /// The same tree with its levels squashed together, for traversal
+    wide: Vec<WideNode>,
+    /// The objects, in the order the leaves refer to them
+    objects: Vec<Arc<dyn HittableObject>>,
     aabb: AABB,
+    max_leaf_size: usize,
+    /// What the tree was expected to cost right after it was built
+    built_cost: Float,
+    /// Whether any of the objects has random hits
+    random_hits: bool,
+}
+
+/// A split between the objects whose centroids fall in bins up to and
+/// including `last_left_bin` along `axis`, and the rest
+#[derive(Debug, Copy, Clone)]
+struct Split {
+    axis: usize,
+    last_left_bin: usize,
+    cost: Float,
+}
+
+/// Where centroids fall along each axis, for sorting objects into bins
+#[derive(Debug, Clone)]
+struct CentroidBounds {
+    min: [Float; 3],
+    max: [Float; 3],
+}
+
+impl CentroidBounds {
+    fn new(objects: &[Arc<dyn HittableObject>]) -> Self {
+        let mut min = [Float::INFINITY; 3];
+        let mut max = [Float::NEG_INFINITY; 3];
+
+        for object in objects {
+            let centroid = object.bounding_box().centroid();
+
+            for axis in 0..3 {
+                min[axis] = Float::min(min[axis], centroid[axis]);
+                max[axis] = Float::max(max[axis], centroid[axis]);
+            }
+        }
+
+        Self { min, max }
+    }
+
+    fn merge(self, other: Self) -> Self {
+        Self {
+            min: [0, 1, 2].map(|axis| Float::min(self.min[axis], other.min[axis])),
+            max: [0, 1, 2].map(|axis| Float::max(self.max[axis], other.max[axis])),
+        }
+    }
+
+    fn extent(&self, axis: usize) -> Float {
+        self.max[axis] - self.min[axis]
+    }
+
+    fn bin(&self, object: &Arc<dyn HittableObject>, axis: usize) -> usize {
+        let centroid = object.bounding_box().centroid()[axis];
+        let position = (centroid - self.min[axis]) / self.extent(axis);
+
+        ((position * SAH_BINS as Float) as usize).min(SAH_BINS - 1)
+    }
+
+    /// The axis the centroids are most spread out along
+    fn longest_axis(&self) -> usize {
+        (0..3)
+            .max_by(|&a, &b| self.extent(a).total_cmp(&self.extent(b)))
+            .unwrap()
+    }
+}
+
+/// How many objects fall into each bin along one axis, and the box around them
+#[derive(Clone, Default)]
+struct Bins {
+    counts: [usize; SAH_BINS],
+    bounds: [Option<AABB>; SAH_BINS],
+}
+
+impl Bins {
+    fn new(objects: &[Arc<dyn HittableObject>], centroids: &CentroidBounds, axis: usize) -> Self {
+        let mut bins = Self::default();
+
+        for object in objects {
+            let bin = centroids.bin(object, axis);
+            bins.counts[bin] += 1;
+            bins.bounds[bin] = combine(bins.bounds[bin].take(), &Some(object.bounding_box()));
+        }
+
+        bins
+    }
+
+    fn merge(mut self, other: Self) -> Self {
+        for (bin, other_bounds) in other.bounds.iter().enumerate() {
+            self.counts[bin] += other.counts[bin];
+            self.bounds[bin] = combine(self.bounds[bin].take(), &other_bounds.as_ref());
+        }
+
+        self
+    }
+}
+
+fn bounds<'a>(boxes: impl Iterator<Item = &'a AABB>) -> AABB {
+    boxes
+        .fold(None, |total: Option<AABB>, aabb| {
+            combine(total, &Some(aabb))
+        })
+        .unwrap_or_default()
+}
+
+/// Summarize chunks of `objects` and merge the summaries,
+/// on several threads if there are enough objects to make it worth it.
+/// Only for summaries that come out the same whichever way they're merged.
+fn summarize<T: Send>(
+    objects: &[Arc<dyn HittableObject>],
+    summary: impl Fn(&[Arc<dyn HittableObject>]) -> T + Sync + Send,
+    merge: impl Fn(T, T) -> T + Sync + Send,
+) -> T {
+    match objects.len() >= PARALLEL_BUILD_THRESHOLD {
+        true => objects
+            .par_chunks(PARALLEL_CHUNK_SIZE)
+            .map(summary)
+            .reduce_with(merge)
+            .unwrap(),
+        false => summary(objects),
+    }
+}
+
+/// Move the objects `goes_left` picks to the front,
+/// and return how many of them there are
+fn partition(
+    objects: &mut [Arc<dyn HittableObject>],
+    goes_left: impl Fn(&Arc<dyn HittableObject>) -> bool,
+) -> usize {
+    let mut left_count = 0;
+
+    for i in 0..objects.len() {
+        if goes_left(&objects[i]) {
+            objects.swap(i, left_count);
+            left_count += 1;
+        }
+    }
+
+    left_count
 }
 
 impl BVHNode {
-    pub fn new(objects: &Vec<Arc<dyn HittableObject>>, start: usize, end: usize) -> Self {
-        let mut aabb = AABB::default();
+    pub fn from(objects: Vec<Arc<dyn HittableObject>>) -> Self {
+        Self::with_max_leaf_size(objects, DEFAULT_MAX_LEAF_SIZE)
+    }
+
+    /// Build a tree whose leaves hold at most `max_leaf_size` objects.
+    /// Leaves may hold fewer if splitting them further is cheaper.
+    /// Large trees get built on several threads, into the same tree
+    /// a single thread would have built.
+    pub fn with_max_leaf_size(
+        mut objects: Vec<Arc<dyn HittableObject>>,
+        max_leaf_size: usize,
+    ) -> Self {
+        let max_leaf_size = max_leaf_size.clamp(1, u16::MAX as usize);
+        let nodes = Self::build_subtree(&mut objects, 0, 0, max_leaf_size);
 
-        for object in objects[start..end].iter() {
-            aabb = AABB::combine_bounds(&aabb, object.bounding_box());
+        Self::from_nodes(nodes, objects, max_leaf_size)
+    }
+
+    /// Put a tree together from its nodes, and its objects in the order
+    /// the leaves refer to them
+    fn from_nodes(
+        nodes: Vec<FlatNode>,
+        objects: Vec<Arc<dyn HittableObject>>,
+        max_leaf_size: usize,
+    ) -> Self {
+        let aabb = nodes[0].aabb();
+        let wide = widen(&nodes);
+
+        let mut tree = Self {
+            nodes,
+            wide,
+            objects,
+            aabb,
+            max_leaf_size,
+            built_cost: 0.0,
+            random_hits: false,
+        };
+
+        tree.random_hits = tree.objects.iter().any(|object| object.has_random_hits());
+
+        tree.built_cost = tree.cost();
+        tree
+    }
+
+    /// The objects in the tree, in no particular order
+    pub fn objects(&self) -> &[Arc<dyn HittableObject>] {
+        &self.objects
+    }
+
+    /// Swap `old` for `new`, say for a copy of it that moved.
+    /// Boxes are left alone, so call `refit` once everything has moved.
+    /// Returns whether `old` was in the tree.
+    pub fn replace(&mut self, old: &Arc<dyn HittableObject>, new: Arc<dyn HittableObject>) -> bool {
+        match self.position(old) {
+            Some(position) => {
+                self.objects[position] = new;
+                true
+            }
+            None => false,
         }
+    }
 
-        let axis = aabb.longest_axis_index();
+    /// Recompute every box from its objects' current boxes, bottom up,
+    /// keeping the shape of the tree. Objects that moved far can leave the
+    /// tree a lot slower to traverse, and then it gets rebuilt instead.
+    pub fn refit(&mut self) {
+        // Children always come after their parents, so going backwards
+        // every child is done before its parent
+        for index in (0..self.nodes.len()).rev() {
+            let node = &self.nodes[index];
 
-        let length = end - start;
+            let aabb = match node.leaf {
+                true => self
+                    .leaf_objects(node)
+                    .iter()
+                    .fold(nothing(), |total, object| {
+                        AABB::combine_bounds(&total, object.bounding_box())
+                    }),
+                false => AABB::combine_bounds(
+                    &self.nodes[index + 1].aabb(),
+                    &self.nodes[node.offset as usize].aabb(),
+                ),
+            };
 
-        let (left, right) = match length {
-            1 => (objects[start].clone(), objects[start].clone()),
-            2 => (objects[start].clone(), objects[start + 1].clone()),
-            _ => {
-                objects.clone()[start..end].sort_by(|a, b| Self::box_compare(a, b, axis));
+            self.nodes[index].set_aabb(&aabb);
+        }
+
+        self.aabb = match self.objects.is_empty() {
+            true => AABB::default(),
+            false => self.nodes[0].aabb(),
+        };
+
+        self.wide = widen(&self.nodes);
+        self.random_hits = self.objects.iter().any(|object| object.has_random_hits());
+
+        if self.cost() > self.built_cost * REBUILD_RATIO {
+            self.rebuild();
+        }
+    }
+
+    /// Add `object` to the leaf whose box it grows the least.
+    /// Leaves can end up bigger than a fresh tree would make them,
+    /// until the tree gets slow enough to be rebuilt.
+    pub fn insert(&mut self, object: Arc<dyn HittableObject>) {
+        let aabb = object.bounding_box().clone();
+        let growth = |node: &FlatNode| {
+            let before = node.aabb();
+            AABB::combine_bounds(&before, &aabb).surface_area() - before.surface_area()
+        };
+
+        let mut index = 0;
+
+        while !self.nodes[index].leaf {
+            let right = self.nodes[index].offset as usize;
+
+            index = match growth(&self.nodes[index + 1]) <= growth(&self.nodes[right]) {
+                true => index + 1,
+                false => right,
+            };
+        }
 
-                let mid = start + length / 2;
+        if self.nodes[index].count == u16::MAX {
+            self.objects.push(object);
+            self.rebuild();
+            return;
+        }
 
-                let left: Arc<dyn HittableObject> = Arc::new(Self::new(objects, start, mid));
-                let right: Arc<dyn HittableObject> = Arc::new(Self::new(objects, mid, end));
+        let position = (self.nodes[index].offset + self.nodes[index].count as u32) as usize;
+        self.objects.insert(position, object);
 
-                (left, right)
+        for (other, node) in self.nodes.iter_mut().enumerate() {
+            if other == index {
+                node.count += 1;
+            } else if node.leaf && node.offset as usize >= position {
+                node.offset += 1;
             }
+        }
+
+        self.refit();
+    }
+
+    /// Take `object` out of the tree, leaving the rest of the tree as it was
+    /// until it gets slow enough to be rebuilt.
+    /// Returns whether `object` was in the tree.
+    pub fn remove(&mut self, object: &Arc<dyn HittableObject>) -> bool {
+        let Some(position) = self.position(object) else {
+            return false;
         };
 
-        let aabb = AABB::combine_bounds(left.bounding_box(), right.bounding_box());
+        self.objects.remove(position);
+
+        for node in self.nodes.iter_mut().filter(|node| node.leaf) {
+            let first = node.offset as usize;
 
-        Self { left, right, aabb }
+            if (first..first + node.count as usize).contains(&position) {
+                node.count -= 1;
+            } else if first > position {
+                node.offset -= 1;
+            }
+        }
+
+        self.refit();
+        true
     }
 
-    pub fn from(objects: Vec<Arc<dyn HittableObject>>) -> Self {
-        let length = objects.len();
-        Self::new(&objects, 0, length)
+    /// Build the tree again from scratch, from the objects it holds now
+    pub fn rebuild(&mut self) {
+        let objects = std::mem::take(&mut self.objects);
+        *self = Self::with_max_leaf_size(objects, self.max_leaf_size);
+    }
+
+    /// How many objects, or nodes' worth of work, a ray that hits the tree
+    /// is expected to test, going by the surface area heuristic.
+    /// Refitting and inserting push it up, which is what triggers rebuilds.
+    pub fn cost(&self) -> Float {
+        let root_area = self.aabb.surface_area();
+
+        if !(root_area > 0.0 && root_area.is_finite()) {
+            return 0.0;
+        }
+
+        let total: Float = self
+            .nodes
+            .iter()
+            .filter(|node| !node.leaf || node.count > 0)
+            .map(|node| {
+                let area = node.aabb().surface_area();
+
+                match node.leaf {
+                    true => area * node.count as Float,
+                    false => area * TRAVERSAL_COST,
+                }
+            })
+            .sum();
+
+        total / root_area
+    }
+
+    /// Write out the tree's nodes, and where each object it holds was
+    /// in `objects`, the objects it was built from in the order they were given
+    pub(crate) fn write_nodes(
+        &self,
+        objects: &[Arc<dyn HittableObject>],
+        writer: &mut impl Write,
+    ) -> io::Result<()> {
+        // The same object can be in there more than once,
+        // so every object keeps a list of the places it was at
+        let mut positions: HashMap<*const u8, Vec<u32>> = HashMap::new();
+
+        for (position, object) in objects.iter().enumerate().rev() {
+            positions
+                .entry(Arc::as_ptr(object) as *const u8)
+                .or_default()
+                .push(position as u32);
+        }
+
+        encoding::write_u64(writer, self.max_leaf_size as u64)?;
+        encoding::write_u64(writer, self.nodes.len() as u64)?;
+
+        for object in self.objects.iter() {
+            let position = positions
+                .get_mut(&(Arc::as_ptr(object) as *const u8))
+                .and_then(Vec::pop)
+                .ok_or_else(|| invalid_data("the tree holds objects it wasn't built from"))?;
+
+            encoding::write_u32(writer, position)?;
+        }
+
+        for node in self.nodes.iter() {
+            for value in node.min.iter().chain(node.max.iter()) {
+                encoding::write_f64(writer, vector::to_f64(*value))?;
+            }
+
+            encoding::write_u32(writer, node.offset)?;
+            encoding::write_u32(writer, node.count as u32)?;
+            writer.write_all(&[node.leaf as u8])?;
+        }
+
+        Ok(())
+    }
+
+    /// Put back together a tree that `write_nodes` wrote out,
+    /// over the same `objects` in the same order.
+    /// Checks that the nodes make a tree that can be traversed safely.
+    pub(crate) fn read_nodes(
+        reader: &mut impl Read,
+        objects: Vec<Arc<dyn HittableObject>>,
+    ) -> io::Result<Self> {
+        let max_leaf_size = read_u64(reader)? as usize;
+        let node_count = read_u64(reader)? as usize;
+
+        // A tree never has more nodes than twice its objects
+        if node_count == 0 || node_count > 2 * objects.len().max(1) {
+            return Err(invalid_data("wrong number of BVH nodes"));
+        }
+
+        let mut taken = vec![false; objects.len()];
+        let mut ordered = Vec::with_capacity(objects.len());
+
+        for _ in 0..objects.len() {
+            let position = read_u32(reader)? as usize;
+
+            if position >= objects.len() || mem::replace(&mut taken[position], true) {
+                return Err(invalid_data(
+                    "BVH objects are not a reordering of the scene's",
+                ));
+            }
+
+            ordered.push(objects[position].clone());
+        }
+
+        let mut nodes = Vec::with_capacity(node_count);
+        let mut depths = vec![0; node_count];
+        let mut has_parent = vec![false; node_count];
+
+        for index in 0..node_count {
+            let mut values = [0.0; 6];
+
+            for value in values.iter_mut() {
+                *value = read_f64(reader)?;
+            }
+
+            let offset = read_u32(reader)?;
+            let count = read_u32(reader)?;
+            let leaf = read_u8(reader)? != 0;
+
+            let valid = match leaf {
+                true => {
+                    count <= u16::MAX as u32 && offset as usize + count as usize <= ordered.len()
+                }
+                // Children come after their parent, the first one right after
+                // it, and no node is the child of more than one parent
+                false => {
+                    index + 1 < node_count
+                        && (offset as usize) > index + 1
+                        && (offset as usize) < node_count
+                        && depths[index] < MAX_TREE_DEPTH
+                        && !has_parent[index + 1]
+                        && !has_parent[offset as usize]
+                }
+            };
+
+            if !valid {
+                return Err(invalid_data("BVH node out of place"));
+            }
+
+            if !leaf {
+                for child in [index + 1, offset as usize] {
+                    depths[child] = depths[index] + 1;
+                    has_parent[child] = true;
+                }
+            }
+
+            nodes.push(FlatNode {
+                min: [values[0] as Float, values[1] as Float, values[2] as Float],
+                max: [values[3] as Float, values[4] as Float, values[5] as Float],
+                offset,
+                count: count as u16,
+                leaf,
+            });
+        }
+
+        Ok(Self::from_nodes(nodes, ordered, max_leaf_size))
+    }
+
+    fn position(&self, object: &Arc<dyn HittableObject>) -> Option<usize> {
+        self.objects
+            .iter()
+            .position(|other| Arc::ptr_eq(other, object))
+    }
+
+    fn leaf_objects(&self, node: &FlatNode) -> &[Arc<dyn HittableObject>] {
+        let first = node.offset as usize;
+        &self.objects[first..first + node.count as usize]
+    }
+
+    /// Build the subtree for `objects`, which start at `first` in the whole list,
+    /// with its nodes numbered from zero. Big subtrees build both of their
+    /// children at once, small ones leave it to `build`.
+    fn build_subtree(
+        objects: &mut [Arc<dyn HittableObject>],
+        first: usize,
+        depth: usize,
+        max_leaf_size: usize,
+    ) -> Vec<FlatNode> {
+        if objects.len() < PARALLEL_BUILD_THRESHOLD {
+            let mut nodes = Vec::with_capacity(objects.len() * 2);
+            Self::build(objects, first, depth, max_leaf_size, &mut nodes);
+            return nodes;
+        }
+
+        let (aabb, split) = Self::split(objects, depth, max_leaf_size);
+        let mut node = FlatNode::new(&aabb);
+
+        let Some(left_count) = split else {
+            node.offset = first as u32;
+            node.count = objects.len() as u16;
+            node.leaf = true;
+            return vec![node];
+        };
+
+        let (left, right) = objects.split_at_mut(left_count);
+        let (left, right) = rayon::join(
+            || Self::build_subtree(left, first, depth + 1, max_leaf_size),
+            || Self::build_subtree(right, first + left_count, depth + 1, max_leaf_size),
+        );
+
+        node.offset = (1 + left.len()) as u32;
+
+        let mut nodes = Vec::with_capacity(1 + left.len() + right.len());
+        nodes.push(node);
+
+        // Both children were numbered from zero, so their links to
+        // other nodes move along with them. Leaves link to objects instead.
+        let right_start = 1 + left.len();
+
+        for (children, start) in [(left, 1), (right, right_start)] {
+            nodes.extend(children.into_iter().map(|mut child| {
+                if !child.leaf {
+                    child.offset += start as u32;
+                }
+                child
+            }));
+        }
+
+        nodes
+    }
+
+    /// Add the node for `objects`, which start at `first` in the whole list,
+    /// and everything below it to `nodes`, and return its index
+    fn build(
+        objects: &mut [Arc<dyn HittableObject>],
+        first: usize,
+        depth: usize,
+        max_leaf_size: usize,
+        nodes: &mut Vec<FlatNode>,
+    ) -> usize {
+        let (aabb, split) = Self::split(objects, depth, max_leaf_size);
+        let index = nodes.len();
+        nodes.push(FlatNode::new(&aabb));
+
+        let Some(left_count) = split else {
+            nodes[index].offset = first as u32;
+            nodes[index].count = objects.len() as u16;
+            nodes[index].leaf = true;
+            return index;
+        };
+
+        let (left, right) = objects.split_at_mut(left_count);
+        Self::build(left, first, depth + 1, max_leaf_size, nodes);
+        let right = Self::build(right, first + left_count, depth + 1, max_leaf_size, nodes);
+
+        nodes[index].offset = right as u32;
+
+        index
+    }
+
+    /// Work out the box around `objects`, and whether to split them.
+    /// For a split, moves the objects that go to the first child to the front,
+    /// and returns how many of them there are.
+    fn split(
+        objects: &mut [Arc<dyn HittableObject>],
+        depth: usize,
+        max_leaf_size: usize,
+    ) -> (AABB, Option<usize>) {
+        let (aabb, centroids) = summarize(
+            objects,
+            |chunk| {
+                let aabb = bounds(chunk.iter().map(|object| object.bounding_box()));
+                (aabb, CentroidBounds::new(chunk))
+            },
+            |(a, a_centroids), (b, b_centroids)| {
+                (AABB::combine_bounds(&a, &b), a_centroids.merge(b_centroids))
+            },
+        );
+
+        if objects.len() <= 1 {
+            return (aabb, None);
+        }
+
+        let leaf_cost = objects.len() as Float;
+
+        let split = match depth < BALANCED_DEPTH {
+            true => Self::find_split(objects, &aabb, &centroids),
+            false => None,
+        };
+
+        let split = match split {
+            Some(split) if objects.len() > max_leaf_size || split.cost < leaf_cost => {
+                let left_count = partition(objects, |object| {
+                    centroids.bin(object, split.axis) <= split.last_left_bin
+                });
+
+                Some(left_count)
+            }
+            None if objects.len() > max_leaf_size => {
+                // Nothing to go by, so just halve the objects
+                let axis = centroids.longest_axis();
+                let middle = objects.len() / 2;
+
+                objects.select_nth_unstable_by(middle, |a, b| {
+                    let a = a.bounding_box().centroid()[axis];
+                    let b = b.bounding_box().centroid()[axis];
+                    a.total_cmp(&b)
+                });
+
+                Some(middle)
+            }
+            _ => None,
+        };
+
+        (aabb, split)
+    }
+
+    /// The cheapest split of `objects` into two non-empty halves, if there is one
+    fn find_split(
+        objects: &[Arc<dyn HittableObject>],
+        aabb: &AABB,
+        centroids: &CentroidBounds,
+    ) -> Option<Split> {
+        let area = aabb.surface_area();
+
+        if !(area > 0.0 && area.is_finite()) {
+            return None;
+        }
+
+        let all_bins = summarize(
+            objects,
+            |chunk| [0, 1, 2].map(|axis| Bins::new(chunk, centroids, axis)),
+            |a, b| {
+                let [a_x, a_y, a_z] = a;
+                let [b_x, b_y, b_z] = b;
+                [a_x.merge(b_x), a_y.merge(b_y), a_z.merge(b_z)]
+            },
+        );
+
+        let mut best: Option<Split> = None;
+
+        for (axis, bins) in all_bins.iter().enumerate() {
+            if centroids.extent(axis) <= 0.0 {
+                continue;
+            }
+
+            // Sweep from the right first, so the left sweep can
+            // look up what's to the right of every split
+            let mut right_costs = [0.0; SAH_BINS];
+            let mut right_count = 0;
+            let mut right_bounds: Option<AABB> = None;
+
+            for bin in (1..SAH_BINS).rev() {
+                right_count += bins.counts[bin];
+                right_bounds = combine(right_bounds, &bins.bounds[bin].as_ref());
+                right_costs[bin] = right_count as Float * area_of(&right_bounds);
+            }
+
+            let mut left_count = 0;
+            let mut left_bounds: Option<AABB> = None;
+
+            for last_left_bin in 0..SAH_BINS - 1 {
+                left_count += bins.counts[last_left_bin];
+                left_bounds = combine(left_bounds, &bins.bounds[last_left_bin].as_ref());
+
+                if left_count == 0 || left_count == objects.len() {
+                    continue;
+                }
+
+                let cost = TRAVERSAL_COST
+                    + (left_count as Float * area_of(&left_bounds)
+                        + right_costs[last_left_bin + 1])
+                        / area;
+
+                if best.is_none_or(|best| cost < best.cost) {
+                    best = Some(Split {
+                        axis,
+                        last_left_bin,
+                        cost,
+                    });
+                }
+            }
+        }
+
+        best
     }
+}
+
+/// Squash the binary tree `nodes` into the wide tree traversal walks.
+/// The root becomes the only child of the first wide node,
+/// so trees that are a single leaf work like any other.
+fn widen(nodes: &[FlatNode]) -> Vec<WideNode> {
+    let mut wide = Vec::with_capacity(nodes.len() / 2 + 1);
+    widen_children(nodes, &[0], &mut wide);
+    wide
+}
 
-    fn box_compare(
-        a: &Arc<dyn HittableObject>,
-        b: &Arc<dyn HittableObject>,
-        axis: usize,
-    ) -> Ordering {
-        let a_axis = a.bounding_box().axis(axis).unwrap();
-        let b_axis = b.bounding_box().axis(axis).unwrap();
+/// Add a wide node with the binary nodes `children` as its children
+/// to `wide`, along with everything below it, and return its index
+fn widen_children(nodes: &[FlatNode], children: &[usize], wide: &mut Vec<WideNode>) -> u32 {
+    let index = wide.len();
+    wide.push(WideNode::new());
 
-        if a_axis.start < b_axis.start {
-            Ordering::Less
-        } else if b_axis.start < a_axis.start {
-            Ordering::Greater
-        } else {
-            Ordering::Equal
+    let mut node = WideNode::new();
+    let children = children
+        .iter()
+        .map(|&child| (child, &nodes[child]))
+        .filter(|(_, child)| !child.leaf || child.count > 0);
+
+    for (lane, (child_index, child)) in children.enumerate() {
+        for axis in 0..3 {
+            node.min[axis][lane] = child.min[axis];
+            node.max[axis][lane] = child.max[axis];
         }
+
+        node.offsets[lane] = match child.leaf {
+            true => child.offset,
+            false => widen_children(nodes, &open_up(nodes, child_index), wide),
+        };
+        node.counts[lane] = child.count;
+        node.leaves[lane] = child.leaf;
+        node.lanes += 1;
     }
+
+    wide[index] = node;
+    index as u32
+}
+
+/// Up to `WIDTH` binary nodes below the node at `index`, which between them
+/// hold everything it does. The biggest one with children keeps getting
+/// swapped for its children until there are enough.
+fn open_up(nodes: &[FlatNode], index: usize) -> Vec<usize> {
+    let mut children = vec![index + 1, nodes[index].offset as usize];
+
+    while children.len() < WIDTH {
+        let biggest = (0..children.len())
+            .filter(|&i| !nodes[children[i]].leaf)
+            .max_by(|&a, &b| {
+                let a = nodes[children[a]].aabb().surface_area();
+                let b = nodes[children[b]].aabb().surface_area();
+                a.total_cmp(&b)
+            });
+
+        let Some(biggest) = biggest else {
+            break;
+        };
+
+        let opened = children.swap_remove(biggest);
+        children.push(opened + 1);
+        children.push(nodes[opened].offset as usize);
+    }
+
+    children
+}
+
+fn combine(total: Option<AABB>, aabb: &Option<&AABB>) -> Option<AABB> {
+    match (total, aabb) {
+        (Some(total), Some(aabb)) => Some(AABB::combine_bounds(&total, aabb)),
+        (total, None) => total,
+        (None, Some(aabb)) => Some((*aabb).clone()),
+    }
+}
+
+/// A box around nothing, which leaves any box it gets combined with as it was
+fn nothing() -> AABB {
+    AABB {
+        x: Float::INFINITY..Float::NEG_INFINITY,
+        y: Float::INFINITY..Float::NEG_INFINITY,
+        z: Float::INFINITY..Float::NEG_INFINITY,
+    }
+}
+
+fn area_of(aabb: &Option<AABB>) -> Float {
+    aabb.as_ref().map_or(0.0, AABB::surface_area)
 }
 
 impl HittableObject for BVHNode {
-    fn hit(&self, ray: &Ray, range: &Range<f64>, record: &mut HitRecord) -> bool {
-        if !self.aabb.hit(ray, range.clone()) {
+    fn hit<'a>(&'a self, ray: &Ray, range: &Range<Float>, record: &mut HitRecord<'a>) -> bool {
+        let origin = [ray.origin().x, ray.origin().y, ray.origin().z];
+        let direction = [ray.direction().x, ray.direction().y, ray.direction().z];
+        let inverse_direction = direction.map(Float::recip);
+
+        let mut range = range.clone();
+        let mut hit_anything = false;
+
+        if self.objects.is_empty() {
             return false;
         }
 
-        let hit_left = self.left.hit(ray, range, record);
+        // Children the ray goes through that we still have to come back to,
+        // by their parent, which child they are, and where the ray enters them
+        let mut stack = TraversalStack::new();
+        let mut next = Some(0);
 
-        let range = match hit_left {
-            true => range.start..record.t,
-            false => range.clone(),
-        };
+        loop {
+            if let Some(index) = next.take() {
+                let node = &self.wide[index];
+                let lanes = node.lanes as usize;
+                stats::count(Counter::BvhNodesVisited);
+                stats::add(Counter::AabbTests, lanes as u64);
+
+                let entries = node.hit(&origin, &inverse_direction, &range);
+
+                // Farthest first, so the nearest child comes off the stack next
+                let first = stack.len;
+
+                for (lane, &entry) in entries[..lanes].iter().enumerate() {
+                    if entry < Float::INFINITY {
+                        stack.push_below(first, (index, lane, entry), |&(_, _, other)| {
+                            other < entry
+                        });
+                    }
+                }
+            }
+
+            let Some((parent, lane, entry)) = stack.pop() else {
+                return hit_anything;
+            };
+
+            // Something closer turned up since the ray went into this child
+            if entry > range.end {
+                continue;
+            }
+
+            let node = &self.wide[parent];
+            let first = node.offsets[lane] as usize;
+
+            match node.leaves[lane] {
+                true => {
+                    for object in self.objects[first..first + node.counts[lane] as usize].iter() {
+                        if object.hit(ray, &range, record) {
+                            hit_anything = true;
+                            range.end = record.t;
+                        }
+                    }
+                }
+                false => next = Some(first),
+            }
+        }
+    }
+
+    fn occluded(&self, ray: &Ray, range: &Range<Float>) -> bool {
+        let origin = [ray.origin().x, ray.origin().y, ray.origin().z];
+        let direction = [ray.direction().x, ray.direction().y, ray.direction().z];
+        let inverse_direction = direction.map(Float::recip);
+
+        if self.objects.is_empty() {
+            return false;
+        }
+
+        // Any hit will do, so nodes get looked inside in whatever order
+        // comes easiest, and the first hit ends the search
+        let mut stack = TraversalStack::new();
+        stack.push(0);
+
+        while let Some(index) = stack.pop() {
+            let node = &self.wide[index];
+            let lanes = node.lanes as usize;
+            stats::count(Counter::BvhNodesVisited);
+            stats::add(Counter::AabbTests, lanes as u64);
+
+            let entries = node.hit(&origin, &inverse_direction, range);
+
+            for (lane, &entry) in entries[..lanes].iter().enumerate() {
+                if entry == Float::INFINITY {
+                    continue;
+                }
+
+                let first = node.offsets[lane] as usize;
+
+                if !node.leaves[lane] {
+                    stack.push(first);
+                    continue;
+                }
 
-        let hit_right = self.right.hit(ray, &range, record);
+                let objects = &self.objects[first..first + node.counts[lane] as usize];
 
-        hit_left || hit_right
+                if objects.iter().any(|object| object.occluded(ray, range)) {
+                    return true;
+                }
+            }
+        }
+
+        false
+    }
+
+    fn hit_packet<'a>(&'a self, packet: &RayPacket, records: &mut [Option<HitRecord<'a>>]) {
+        if self.objects.is_empty() {
+            return;
+        }
+
+        if !packet.is_coherent() {
+            packet::hit_separately(self, packet, records);
+            return;
+        }
+
+        let mut candidate = HitRecord::new(&packet.rays()[0]);
+
+        // Children some of the rays may go through that we still have to
+        // come back to, by their parent and which child they are
+        let mut stack = TraversalStack::new();
+        let mut next = Some(0);
+
+        loop {
+            if let Some(index) = next.take() {
+                let node = &self.wide[index];
+                let lanes = node.lanes as usize;
+                stats::count(Counter::BvhNodesVisited);
+                stats::add(Counter::AabbTests, lanes as u64);
+
+                let end = records
+                    .iter()
+                    .map(packet::hit_distance)
+                    .fold(Float::NEG_INFINITY, Float::max);
+
+                // Farthest first, so the nearest child comes off the stack next
+                let mut entries = [(0, 0.0); WIDTH];
+                let mut hits = 0;
+
+                for lane in 0..lanes {
+                    let (min, max) = node.child_box(lane);
+
+                    if let Some(entry) = packet.enters(&min, &max, end) {
+                        let mut slot = hits;
+
+                        while slot > 0 && entries[slot - 1].1 < entry {
+                            entries[slot] = entries[slot - 1];
+                            slot -= 1;
+                        }
+
+                        entries[slot] = (lane, entry);
+                        hits += 1;
+                    }
+                }
+
+                for &(lane, _) in entries[..hits].iter() {
+                    stack.push((index, lane));
+                }
+            }
+
+            let Some((parent, lane)) = stack.pop() else {
+                return;
+            };
+            let node = &self.wide[parent];
+            let (min, max) = node.child_box(lane);
+            let first = node.offsets[lane] as usize;
+
+            // Only now that the packet as a whole may go through the child
+            // do its rays get tested one by one
+            let mut rays = (0..packet.len()).filter(|&i| {
+                if packet.gave_up(i) {
+                    return false;
+                }
+
+                stats::count(Counter::AabbTests);
+                let range = MIN_HIT_DISTANCE..packet::hit_distance(&records[i]);
+                packet.hits(i, &min, &max, &range)
+            });
+
+            if !node.leaves[lane] {
+                if rays.next().is_some() {
+                    next = Some(first);
+                }
+
+                continue;
+            }
+
+            let rays: Vec<usize> = rays.collect();
+            let objects = &self.objects[first..first + node.counts[lane] as usize];
+
+            if self.random_hits && objects.iter().any(|object| object.has_random_hits()) {
+                for i in rays {
+                    packet.give_up(i);
+                }
+
+                continue;
+            }
+
+            for i in rays {
+                let ray = &packet.rays()[i];
+                candidate.in_ray = *ray;
+                let mut range = MIN_HIT_DISTANCE..packet::hit_distance(&records[i]);
+                let mut hit = false;
+
+                for object in objects {
+                    if object.hit(ray, &range, &mut candidate) {
+                        hit = true;
+                        range.end = candidate.t;
+                    }
+                }
+
+                if hit {
+                    records[i] = Some(candidate);
+                }
+            }
+        }
     }
 
     fn bounding_box(&self) -> &AABB {
         &self.aabb
     }
+
+    fn materials<'a>(&'a self, materials: &mut Vec<&'a dyn Material>) {
+        for object in self.objects.iter() {
+            object.materials(materials);
+        }
+    }
+
+    fn has_random_hits(&self) -> bool {
+        self.random_hits
+    }
+
+    fn accelerator_memory(&self) -> usize {
+        mem::size_of::<Self>()
+            + self.nodes.len() * mem::size_of::<FlatNode>()
+            + self.wide.len() * mem::size_of::<WideNode>()
+            + self.objects.len() * mem::size_of::<Arc<dyn HittableObject>>()
+            + self
+                .objects
+                .iter()
+                .map(|object| object.accelerator_memory())
+                .sum::<usize>()
+    }
+
+    fn bvh_boxes(&self, depth: usize, boxes: &mut Vec<AABB>) {
+        if self.objects.is_empty() {
+            return;
+        }
+
+        let mut stack = vec![(0, 0)];
+
+        while let Some((index, node_depth)) = stack.pop() {
+            let node = &self.nodes[index];
+
+            if node.leaf && node.count == 0 {
+                continue;
+            } else if node_depth == depth {
+                boxes.push(node.aabb());
+            } else if node.leaf {
+                for object in self.leaf_objects(node) {
+                    object.bvh_boxes(depth - node_depth - 1, boxes);
+                }
+            } else {
+                stack.push((node.offset as usize, node_depth + 1));
+                stack.push((index + 1, node_depth + 1));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::sphere::Sphere;
+    use crate::vector::{Color, Point3, Vec3};
+
+    use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+    /// `count` small spheres scattered through a cube, the same ones every time
+    fn spheres(count: usize, seed: u64) -> Vec<Arc<dyn HittableObject>> {
+        let mut rng = SmallRng::seed_from_u64(seed);
+        let material = Arc::new(Lambertian::from_color(Color::new(0.5, 0.5, 0.5)));
+
+        (0..count)
+            .map(|_| {
+                let center = Point3::new(
+                    rng.gen_range(-50.0..50.0),
+                    rng.gen_range(-50.0..50.0),
+                    rng.gen_range(-50.0..50.0),
+                );
+                let radius = rng.gen_range(0.1..1.0);
+
+                Arc::new(Sphere::new(center, Vec3::ZERO, radius, material.clone()))
+                    as Arc<dyn HittableObject>
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parallel_build_matches_serial_build() {
+        let objects = spheres(3 * PARALLEL_BUILD_THRESHOLD, 1);
+
+        let pool = |threads| {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap()
+        };
+
+        // Several threads, however many cores the machine running the tests has
+        let parallel = pool(4).install(|| BVHNode::from(objects.clone()));
+
+        // With a single thread, nothing runs at the same time,
+        // and building the whole tree with `build` never splits it up
+        let (serial_nodes, serial_objects) = pool(1).install(|| {
+            let mut objects = objects.clone();
+            let mut nodes = Vec::new();
+            BVHNode::build(&mut objects, 0, 0, DEFAULT_MAX_LEAF_SIZE, &mut nodes);
+            (nodes, objects)
+        });
+
+        assert_eq!(parallel.nodes, serial_nodes);
+        assert_eq!(parallel.objects.len(), serial_objects.len());
+        assert!(parallel
+            .objects
+            .iter()
+            .zip(serial_objects.iter())
+            .all(|(a, b)| Arc::ptr_eq(a, b)));
+    }
+
+    fn center(object: &Arc<dyn HittableObject>) -> Point3 {
+        let aabb = object.bounding_box();
+
+        Point3::new(
+            (aabb.x.start + aabb.x.end) / 2.0,
+            (aabb.y.start + aabb.y.end) / 2.0,
+            (aabb.z.start + aabb.z.end) / 2.0,
+        )
+    }
+
+    /// Check that `tree` has the same bounds as a tree freshly built over
+    /// the same objects, and that rays hit them both in the same places
+    fn assert_matches_fresh_tree(tree: &BVHNode) {
+        let objects = tree.objects().to_vec();
+        let fresh = BVHNode::from(objects.clone());
+        let bounds = |aabb: &AABB| [aabb.x.clone(), aabb.y.clone(), aabb.z.clone()];
+
+        assert_eq!(bounds(tree.bounding_box()), bounds(fresh.bounding_box()));
+
+        let mut rng = SmallRng::seed_from_u64(2);
+        let mut hits = 0;
+
+        // Rays from all over at objects, so most of them hit something,
+        // though not always the object they were aimed at
+        for _ in 0..2000 {
+            let origin = Point3::new(
+                rng.gen_range(-60.0..60.0),
+                rng.gen_range(-60.0..60.0),
+                rng.gen_range(-60.0..60.0),
+            );
+            let target = center(&objects[rng.gen_range(0..objects.len())]);
+            let ray = Ray::new(origin, target - origin, 0.0);
+            let range = MIN_HIT_DISTANCE..Float::INFINITY;
+
+            let mut record = HitRecord::new(&ray);
+            let mut fresh_record = HitRecord::new(&ray);
+            let hit = tree.hit(&ray, &range, &mut record);
+
+            assert_eq!(hit, fresh.hit(&ray, &range, &mut fresh_record));
+
+            if hit {
+                assert_eq!(record.t, fresh_record.t);
+                assert_eq!(record.point, fresh_record.point);
+                assert_eq!(record.normal, fresh_record.normal);
+                hits += 1;
+            }
+        }
+
+        assert!(hits > 1000);
+    }
+
+    #[test]
+    fn refit_matches_fresh_tree() {
+        let objects = spheres(500, 3);
+        let mut tree = BVHNode::from(objects.clone());
+        let built_cost = tree.built_cost;
+
+        let mut rng = SmallRng::seed_from_u64(4);
+        let material = Arc::new(Lambertian::from_color(Color::new(0.5, 0.5, 0.5)));
+
+        for object in objects.iter().step_by(3) {
+            let aabb = object.bounding_box();
+            let radius = (aabb.x.end - aabb.x.start) / 2.0;
+            let offset = Vec3::new(
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+            );
+            let moved = Sphere::new(
+                center(object) + offset,
+                Vec3::ZERO,
+                radius,
+                material.clone(),
+            );
+
+            assert!(tree.replace(object, Arc::new(moved)));
+        }
+
+        tree.refit();
+
+        // Moving objects this little shouldn't have made it rebuild the tree
+        assert_eq!(tree.built_cost, built_cost);
+        assert_matches_fresh_tree(&tree);
+    }
+
+    #[test]
+    fn insert_matches_fresh_tree() {
+        let objects = spheres(500, 5);
+        let (first, rest) = objects.split_at(100);
+        let mut tree = BVHNode::from(first.to_vec());
+
+        for object in rest {
+            tree.insert(object.clone());
+        }
+
+        assert_eq!(tree.objects().len(), objects.len());
+        assert_matches_fresh_tree(&tree);
+    }
+
+    #[test]
+    fn remove_matches_fresh_tree() {
+        let objects = spheres(500, 6);
+        let mut tree = BVHNode::from(objects.clone());
+
+        for object in objects.iter().step_by(2) {
+            assert!(tree.remove(object));
+        }
+
+        assert!(!tree.remove(&objects[0]));
+        assert_eq!(tree.objects().len(), objects.len() / 2);
+        assert_matches_fresh_tree(&tree);
+    }
 }
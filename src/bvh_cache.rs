@@ -0,0 +1,239 @@
+//! Keeping built BVHs on disk between runs
+//!
+//! Rendering the same big scene again with a different camera or sample count
+//! builds exactly the same BVHs again, since a build only looks at the objects'
+//! bounding boxes and the builder's settings. A cache directory keeps every
+//! tree built through it in a file named after a hash of both, and the next
+//! time the same boxes come along, the tree gets read back from the file
+//! instead of being built.
+//!
+//! Besides the tree, each file holds the boxes of all the objects it refers to,
+//! in the order they were given. The tree only gets used if those match the
+//! objects exactly, so a file from an older version of the format, a hash
+//! collision or a damaged file is caught as stale, and the tree gets built
+//! again and the file replaced.
+//!
+//! Only the trees are cached, not the objects in them. Objects are trait
+//! objects holding materials and textures, with no format to write them in,
+//! so they still come from the code that builds the scene, which is quick next
+//! to building the trees. Files aren't memory-mapped either: traversal walks
+//! wide nodes made from the tree's nodes when it's put together, so a mapped
+//! file would get copied anyway, and files are read into memory instead.
+//!
+//! Renders can share a cache directory. Every file gets written under a name
+//! of its own first and only then renamed into place, so one render never sees
+//! another's file half written.
+
+use crate::bvh::{self, BVHNode};
+use crate::encoding::{self, invalid_data, read_f64, read_u32, read_u64};
+use crate::hittable::HittableObject;
+use crate::vector;
+
+use std::{
+    fs,
+    io::{self, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+const MAGIC: &[u8; 4] = b"RTBV";
+const VERSION: u32 = 2;
+
+/// What happened to the trees asked of a cache so far
+#[derive(Debug, Clone, Default)]
+pub struct CacheSummary {
+    /// Trees read from the cache
+    pub loaded: usize,
+    /// Trees that had to be built, and were added to the cache
+    pub built: usize,
+    /// How many of the built trees replaced stale ones
+    pub stale: usize,
+    /// Files that couldn't be read or written
+    pub errors: Vec<String>,
+}
+
+/// A directory of built BVHs
+pub struct BvhCache {
+    directory: PathBuf,
+    summary: Mutex<CacheSummary>,
+}
+
+impl BvhCache {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+            summary: Mutex::new(CacheSummary::default()),
+        }
+    }
+
+    pub fn summary(&self) -> CacheSummary {
+        self.summary.lock().unwrap().clone()
+    }
+
+    /// A BVH over `objects`, read from the cache if it has a tree for them,
+    /// or else built and added to it
+    pub fn bvh(&self, objects: Vec<Arc<dyn HittableObject>>) -> BVHNode {
+        let key = content_hash(&objects);
+        let path = self.directory.join(format!("{:016x}.bvh", key));
+
+        match load(&path, key, &objects) {
+            Ok(Some(tree)) => {
+                self.summary.lock().unwrap().loaded += 1;
+                return tree;
+            }
+            Ok(None) => {}
+            // Cut short or not what it should be
+            Err(error)
+                if matches!(
+                    error.kind(),
+                    io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof
+                ) =>
+            {
+                self.summary.lock().unwrap().stale += 1;
+            }
+            Err(error) => self.error(&path, error),
+        }
+
+        let tree = BVHNode::from(objects.clone());
+
+        if let Err(error) = save(&path, key, &objects, &tree) {
+            self.error(&path, error);
+        }
+
+        self.summary.lock().unwrap().built += 1;
+        tree
+    }
+
+    fn error(&self, path: &Path, error: io::Error) {
+        let message = format!("{}: {}", path.display(), error);
+        self.summary.lock().unwrap().errors.push(message);
+    }
+}
+
+/// The corners of every object's box, in order
+fn corners(objects: &[Arc<dyn HittableObject>]) -> impl Iterator<Item = f64> + '_ {
+    objects.iter().flat_map(|object| {
+        let aabb = object.bounding_box();
+        [
+            vector::to_f64(aabb.x.start),
+            vector::to_f64(aabb.y.start),
+            vector::to_f64(aabb.z.start),
+            vector::to_f64(aabb.x.end),
+            vector::to_f64(aabb.y.end),
+            vector::to_f64(aabb.z.end),
+        ]
+    })
+}
+
+/// A 64-bit FNV-1a hash of everything that goes into building a tree over
+/// `objects`: the builder's settings, and the objects' boxes. Unlike the
+/// standard library's hashers, it stays the same between versions of Rust.
+fn content_hash(objects: &[Arc<dyn HittableObject>]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+
+    let parameters = bvh::build_parameters(bvh::DEFAULT_MAX_LEAF_SIZE)
+        .into_iter()
+        .flat_map(u64::to_le_bytes);
+    let count = (objects.len() as u64).to_le_bytes();
+    let corners = corners(objects).flat_map(f64::to_le_bytes);
+
+    for byte in parameters.chain(count).chain(corners) {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+
+    hash
+}
+
+/// Read the tree for `objects` from the file at `path`, if there is one.
+/// Files that don't hold a tree for exactly these objects are invalid data.
+fn load(path: &Path, key: u64, objects: &[Arc<dyn HittableObject>]) -> io::Result<Option<BVHNode>> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(error) => return Err(error),
+    };
+
+    let mut reader: &[u8] = &bytes;
+
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+
+    if &magic != MAGIC {
+        return Err(invalid_data("not a BVH cache file"));
+    }
+
+    if read_u32(&mut reader)? != VERSION {
+        return Err(invalid_data("BVH cache from another version"));
+    }
+
+    if read_u64(&mut reader)? != key || read_u64(&mut reader)? != objects.len() as u64 {
+        return Err(invalid_data("BVH cache for other objects"));
+    }
+
+    for corner in corners(objects) {
+        if read_f64(&mut reader)?.to_bits() != corner.to_bits() {
+            return Err(invalid_data("BVH cache for other objects"));
+        }
+    }
+
+    let tree = BVHNode::read_nodes(&mut reader, objects.to_vec())?;
+
+    match reader.is_empty() {
+        true => Ok(Some(tree)),
+        false => Err(invalid_data("BVH cache file too long")),
+    }
+}
+
+/// Write `tree` over `objects` to the file at `path`, replacing any file
+/// there only once the new one is complete
+fn save(
+    path: &Path,
+    key: u64,
+    objects: &[Arc<dyn HittableObject>],
+    tree: &BVHNode,
+) -> io::Result<()> {
+    fs::create_dir_all(path.parent().unwrap_or(Path::new(".")))?;
+
+    // Unique to this write, even with other renders saving the same tree
+    let temp_path = path.with_extension(format!(
+        "{}-{:016x}.tmp",
+        std::process::id(),
+        rand::random::<u64>()
+    ));
+
+    let result =
+        write_file(&temp_path, key, objects, tree).and_then(|_| fs::rename(&temp_path, path));
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+
+    result
+}
+
+/// Write `tree` over `objects` to a new file at `path`
+fn write_file(
+    path: &Path,
+    key: u64,
+    objects: &[Arc<dyn HittableObject>],
+    tree: &BVHNode,
+) -> io::Result<()> {
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)?;
+    let mut writer = BufWriter::new(file);
+
+    writer.write_all(MAGIC)?;
+    encoding::write_u32(&mut writer, VERSION)?;
+    encoding::write_u64(&mut writer, key)?;
+    encoding::write_u64(&mut writer, objects.len() as u64)?;
+
+    for corner in corners(objects) {
+        encoding::write_f64(&mut writer, corner)?;
+    }
+
+    tree.write_nodes(objects, &mut writer)?;
+    writer.flush()
+}
@@ -1,25 +1,44 @@
 //! A module to manage the camera
 
-use crate::hittable::{HitRecord, HittableObject};
+use crate::film::{Film, FilmPixel};
+use crate::hittable::HitRecord;
+use crate::integrator::{Integrator, PathTracer, SceneView};
+use crate::packet::{KnownHit, RayPacket, PACKET_PIXELS, PACKET_SAMPLES};
+use crate::progress::{CancellationToken, Progress, ProgressObserver, RenderSummary};
+use crate::random;
 use crate::ray::Ray;
+use crate::scene::Scene;
+use crate::stats::{self, Counter, Stats};
 use crate::vector;
-use crate::vector::{Color, Pixel, Vec3};
+use crate::vector::{Color, Float, Point3, Vec3};
 
 use rayon::prelude::*;
 
-use progressing::{mapping::Bar as MappingBar, Baring};
-use rand::Rng;
-use std::{sync::Mutex, time};
+use rand::{rngs::SmallRng, Rng};
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time,
+};
+
+/// How many samples each pixel gets per rendering pass
+const SAMPLES_PER_PASS: u32 = 8;
+
+/// The least time between two progress reports in the middle of a pass
+const REPORT_INTERVAL_MILLIS: u64 = 250;
+
+/// With only a handful of samples, a pixel that hasn't found any light yet
+/// looks perfectly converged, so don't trust the error estimate before this.
+const MIN_SAMPLES_FOR_ERROR_ESTIMATE: u32 = 16;
 
 #[derive(Debug, Copy, Clone)]
 pub struct CameraSettings {
-    pub position: Vec3,
-    pub target_position: Vec3,
+    pub position: Point3,
+    pub target_position: Point3,
     pub up_direction: Vec3,
-    pub fov: f64,
-    pub defocus_angle: f64,
-    pub focus_dist: f64,
-    pub aspect_ratio: f64,
+    pub fov: Float,
+    pub defocus_angle: Float,
+    pub focus_dist: Float,
+    pub aspect_ratio: Float,
     pub background_color: Color,
 }
 
@@ -30,18 +49,149 @@ pub struct RenderSettings {
     pub max_depth: usize,
 }
 
+/// Reasons to stop a render before every pixel has `samples_per_pixel` samples
+#[derive(Debug, Copy, Clone, Default)]
+pub struct StoppingCriteria {
+    /// Stop before this much time has passed
+    pub time_budget: Option<time::Duration>,
+    /// Stop once the film's estimated mean relative error drops to this
+    pub max_relative_error: Option<f64>,
+}
+
+impl StoppingCriteria {
+    /// Whether the render only stops once it reaches its sample count
+    pub fn only_counts_samples(&self) -> bool {
+        self.time_budget.is_none() && self.max_relative_error.is_none()
+    }
+
+    fn error_reached(&self, film: &Film) -> bool {
+        let Some(max_error) = self.max_relative_error else {
+            return false;
+        };
+
+        if film.min_sample_count() < MIN_SAMPLES_FOR_ERROR_ESTIMATE {
+            return false;
+        }
+
+        film.mean_relative_error()
+            .is_some_and(|error| error <= max_error)
+    }
+}
+
+/// Everything about a render that isn't the camera or the scene
+#[derive(Clone, Default)]
+pub struct RenderOptions<'a> {
+    /// Seed for every sample, see `random::sample_seed`
+    pub seed: u64,
+    pub stopping: StoppingCriteria,
+    pub observers: Vec<&'a dyn ProgressObserver>,
+    pub cancellation: CancellationToken,
+    /// How to light the scene, a plain `PathTracer` if not set
+    pub integrator: Option<&'a dyn Integrator>,
+    /// Find where camera rays first hit the scene in packets, see `packet`.
+    /// The image comes out the same either way. Packets count their work for
+    /// the whole packet rather than ray by ray, so integrators that read the
+    /// counters always trace camera rays one at a time.
+    pub packets: bool,
+}
+
+/// Works out how far along a render is from whichever
+/// limits it has, and how quickly it's been taking samples.
+struct ProgressEstimator {
+    start: time::Instant,
+    initial_samples: u64,
+    num_pixels: u64,
+    target: u32,
+    stopping: StoppingCriteria,
+}
+
+impl ProgressEstimator {
+    fn new(film: &Film, target: u32, stopping: &StoppingCriteria) -> Self {
+        Self {
+            start: time::Instant::now(),
+            initial_samples: film.total_sample_count(),
+            num_pixels: film.num_pixels() as u64,
+            target,
+            stopping: *stopping,
+        }
+    }
+
+    fn progress(
+        &self,
+        pass: usize,
+        total_samples: u64,
+        mean_relative_error: Option<f64>,
+    ) -> Progress {
+        let elapsed = self.start.elapsed();
+        let samples_per_pixel = total_samples as f64 / self.num_pixels as f64;
+        let samples_per_second =
+            (total_samples - self.initial_samples) as f64 / elapsed.as_secs_f64();
+
+        // The render stops at whichever limit it reaches first,
+        // so go with the most optimistic estimate.
+        let mut fraction: Option<f64> = None;
+        let mut remaining_seconds: Option<f64> = None;
+
+        let mut consider = |limit_fraction: f64, limit_seconds: f64| {
+            fraction = Some(fraction.map_or(limit_fraction, |f| f.max(limit_fraction)));
+
+            if limit_seconds.is_finite() {
+                remaining_seconds =
+                    Some(remaining_seconds.map_or(limit_seconds, |s| s.min(limit_seconds)));
+            }
+        };
+
+        if self.target < u32::MAX {
+            let target_samples = self.target as f64 * self.num_pixels as f64;
+
+            consider(
+                samples_per_pixel / self.target as f64,
+                (target_samples - total_samples as f64) / samples_per_second,
+            );
+        }
+
+        if let Some(budget) = self.stopping.time_budget {
+            consider(
+                elapsed.as_secs_f64() / budget.as_secs_f64(),
+                budget.saturating_sub(elapsed).as_secs_f64(),
+            );
+        }
+
+        if let (Some(max_error), Some(error)) =
+            (self.stopping.max_relative_error, mean_relative_error)
+        {
+            // The error falls off with the square root of the sample count
+            let needed_samples_per_pixel = samples_per_pixel * (error / max_error).powi(2);
+
+            consider(
+                samples_per_pixel / needed_samples_per_pixel,
+                (needed_samples_per_pixel - samples_per_pixel) * self.num_pixels as f64
+                    / samples_per_second,
+            );
+        }
+
+        Progress {
+            pass,
+            samples_per_pixel,
+            fraction: fraction.map(|fraction| fraction.clamp(0.0, 1.0)),
+            elapsed,
+            eta: remaining_seconds.map(|seconds| time::Duration::from_secs_f64(seconds.max(0.0))),
+            mean_relative_error,
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct Camera {
     image_width: usize,
     image_height: usize,
-    position: Vec3,
+    position: Point3,
     samples_per_pixel: usize,
     max_depth: usize,
-    pixel_samples_scale: f64,
-    pixel00_location: Vec3,
+    pixel00_location: Point3,
     pixel_delta_u: Vec3,
     pixel_delta_v: Vec3,
-    defocus_angle: f64,
+    defocus_angle: Float,
     defocus_disk_u: Vec3,
     defocus_disk_v: Vec3,
     background_color: Color,
@@ -115,7 +265,7 @@ impl Camera {
     pub fn new(camera_settings: &CameraSettings, render_settings: &RenderSettings) -> Self {
         // Set the camer's image_height to an int no lower than 1
         let image_height =
-            (render_settings.image_width as f64 / camera_settings.aspect_ratio) as usize;
+            (render_settings.image_width as Float / camera_settings.aspect_ratio) as usize;
         let image_height = std::cmp::max(image_height, 1);
 
         // Set viewport dimensions
@@ -123,19 +273,19 @@ impl Camera {
         let h = (theta / 2.0).tan();
         let viewport_height = 2.0 * h * camera_settings.focus_dist;
         let viewport_width =
-            viewport_height * (render_settings.image_width as f64 / image_height as f64);
+            viewport_height * (render_settings.image_width as Float / image_height as Float);
 
-        let w = glm::normalize(camera_settings.position - camera_settings.target_position);
-        let u = glm::normalize(glm::cross(camera_settings.up_direction, w));
-        let v = glm::cross(w, u);
+        let w = (camera_settings.position - camera_settings.target_position).normalize();
+        let u = camera_settings.up_direction.cross(w).normalize();
+        let v = w.cross(u);
 
         // Create vectors to line the top and left borders
         let viewport_u = u * viewport_width;
         let viewport_v = -v * viewport_height;
 
         // Set the distance between the pixel centers in each direction
-        let pixel_delta_u = viewport_u / render_settings.image_width as f64;
-        let pixel_delta_v = viewport_v / image_height as f64;
+        let pixel_delta_u = viewport_u / render_settings.image_width as Float;
+        let pixel_delta_v = viewport_v / image_height as Float;
 
         // Get the upper left corner in viewport space
         let viewport_upper_left = camera_settings.position
@@ -155,7 +305,6 @@ impl Camera {
             image_width: render_settings.image_width,
             image_height,
             position: camera_settings.position,
-            pixel_samples_scale: 1.0 / render_settings.samples_per_pixel as f64,
             pixel00_location,
             pixel_delta_u,
             pixel_delta_v,
@@ -168,8 +317,8 @@ impl Camera {
         }
     }
 
-    fn get_ray(&self, x: f64, y: f64) -> Ray {
-        let mut rng = rand::thread_rng();
+    fn get_ray(&self, x: Float, y: Float) -> Ray {
+        let mut rng = random::rng();
 
         let offset = vector::random_unit_square_vec();
         let pixel_sample = self.pixel00_location
@@ -181,114 +330,334 @@ impl Camera {
             false => self.defocus_disk_sample(),
         };
 
-        let ray_time: f64 = rng.gen();
+        let ray_time: Float = rng.gen();
 
         Ray::new(ray_origin, pixel_sample - ray_origin, ray_time)
     }
 
-    pub fn render(&self, hittable: &dyn HittableObject) {
-        eprintln!("Rendering scene...");
-
-        let now = time::Instant::now();
+    pub fn image_width(&self) -> usize {
+        self.image_width
+    }
 
-        let num_pixels: usize = self.image_height * self.image_width;
+    pub fn image_height(&self) -> usize {
+        self.image_height
+    }
 
-        let mut progress = MappingBar::with_range(0, num_pixels).timed();
-        progress.set_len(20);
+    pub fn samples_per_pixel(&self) -> usize {
+        self.samples_per_pixel
+    }
 
-        let progress = Mutex::new(progress);
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
 
-        let update_progress = || {
-            let mut progress = progress.lock().unwrap();
-            progress.add(1_usize);
+    /// Render the scene from scratch and print it to stdout as a PPM image
+    pub fn render(&self, scene: &Scene, seed: u64) {
+        let mut film = Film::new(self.image_width, self.image_height);
 
-            if progress.has_progressed_significantly() {
-                progress.remember_significant_progress();
-                eprintln!("{}", progress);
-            }
+        let options = RenderOptions {
+            seed,
+            ..Default::default()
         };
 
-        let pixels: Vec<Pixel> = (0..num_pixels)
-            .into_par_iter()
-            .map(|i| {
-                let color = self.render_pixel(hittable, i);
-                update_progress();
-                color
-            })
-            .collect();
-
-        let mut elapsed = now.elapsed().as_secs();
-        let hours = elapsed / 3600;
-        elapsed %= 3600;
-
-        let minutes = elapsed / 60;
-        let seconds = elapsed % 60;
-
-        eprintln!(
-            "Scene rendered in {} hours, {} minutes, {} seconds",
-            hours, minutes, seconds
-        );
-
-        // Print the PPM header
-        println!("P3\n{} {}\n255\n", self.image_width, self.image_height);
-
-        // Print the PPM data
-        for pixel in pixels {
-            println!("{} {} {}", pixel.x, pixel.y, pixel.z);
-        }
+        self.render_film(scene, &mut film, &options);
+        film.write_ppm();
     }
 
-    pub fn render_pixel(&self, hittable: &dyn HittableObject, i: usize) -> Pixel {
-        let row_index = (i / self.image_width) as f64;
-        let column_index = (i % self.image_width) as f64;
+    /// Keep adding samples to the film until every pixel has
+    /// `samples_per_pixel` of them, until one of the stopping criteria is met,
+    /// or until the render is cancelled.
+    /// The work is done in passes of a few samples per pixel.
+    pub fn render_film(
+        &self,
+        scene: &Scene,
+        film: &mut Film,
+        options: &RenderOptions,
+    ) -> RenderSummary {
+        for observer in options.observers.iter() {
+            observer.on_start();
+        }
+
+        let target = u32::try_from(self.samples_per_pixel).unwrap_or(u32::MAX);
+        let estimator = ProgressEstimator::new(film, target, &options.stopping);
+        let first_pixel = film.first_pixel_index();
+        let stopping = &options.stopping;
+        let cancellation = &options.cancellation;
+        let integrator = options.integrator.unwrap_or(&PathTracer);
+        let packets = options.packets && !integrator.needs_stats();
+
+        let view = SceneView {
+            world: &*scene.world,
+            lights: &scene.lights,
+            background_color: self.background_color,
+            max_depth: self.max_depth,
+        };
 
-        let mut color = (0..self.samples_per_pixel)
-            .map(|_| self.get_ray(column_index, row_index))
-            .map(|r| self.ray_color(&r, hittable, self.max_depth))
-            .reduce(|acc, a| acc + a)
-            .unwrap()
-            * self.pixel_samples_scale;
+        let mut pass = 0;
+        let mut pass_duration_per_sample = None;
+        let mut mean_relative_error = film.mean_relative_error();
+        let mut render_stats = Stats::default();
 
-        // Use sqrt for gamma correction
-        color = vector::sqrt_vec(&color);
-        color = vector::clamp_vec3(&color, 0.0..0.999) * 256.0;
+        loop {
+            let samples_done = film.min_sample_count();
 
-        vector::color_to_pixel(&color)
-    }
+            if cancellation.is_cancelled() || samples_done >= target || stopping.error_reached(film)
+            {
+                break;
+            }
 
-    pub fn ray_color(&self, ray: &Ray, hittable: &dyn HittableObject, depth: usize) -> Color {
-        if depth == 0 {
-            return vector::zero_vec3();
+            let pass_samples = match stopping.time_budget {
+                Some(budget) => {
+                    let remaining_time = budget.saturating_sub(estimator.start.elapsed());
+
+                    // Take a single sample first to find out how long a pass takes,
+                    // then fit as many samples as we can into the remaining time.
+                    let affordable_samples = match pass_duration_per_sample {
+                        None => 1,
+                        Some(duration) => {
+                            let duration = f64::max(duration, f64::EPSILON);
+                            (remaining_time.as_secs_f64() / duration) as u32
+                        }
+                    };
+
+                    if remaining_time.is_zero() || affordable_samples == 0 {
+                        break;
+                    }
+
+                    u32::min(affordable_samples, SAMPLES_PER_PASS)
+                }
+                None => SAMPLES_PER_PASS,
+            };
+
+            pass += 1;
+
+            let pass_target = samples_done.saturating_add(pass_samples).min(target);
+            let pass_start = time::Instant::now();
+
+            integrator.prepare(&view, options.seed, samples_done..pass_target);
+
+            let total_samples = AtomicU64::new(film.total_sample_count());
+            let last_report_millis = AtomicU64::new(0);
+
+            let pass_stats = film
+                .pixels_mut()
+                .par_chunks_mut(self.image_width)
+                .enumerate()
+                .map_init(PacketBuffers::default, |buffers, (row, pixels)| {
+                    if cancellation.is_cancelled() {
+                        return Stats::default();
+                    }
+
+                    // Anything this thread counted outside of a row,
+                    // like building a BVH, isn't part of the render.
+                    stats::take_local();
+
+                    let mut row_samples = 0;
+
+                    let row_start = first_pixel + row * self.image_width;
+
+                    for pixel in pixels.iter() {
+                        row_samples += pass_target.saturating_sub(pixel.sample_count) as u64;
+                    }
+
+                    if packets {
+                        for (chunk, pixels) in pixels.chunks_mut(PACKET_PIXELS).enumerate() {
+                            self.render_packets(
+                                integrator,
+                                &view,
+                                buffers,
+                                options.seed,
+                                row_start + chunk * PACKET_PIXELS,
+                                pixels,
+                                pass_target,
+                            );
+                        }
+                    } else {
+                        for (column, pixel) in pixels.iter_mut().enumerate() {
+                            self.render_pixel_samples(
+                                integrator,
+                                &view,
+                                options.seed,
+                                row_start + column,
+                                pixel,
+                                pass_target,
+                            );
+                        }
+                    }
+
+                    let samples = total_samples.fetch_add(row_samples, Ordering::Relaxed);
+
+                    // Only one thread gets to report in each interval: whichever
+                    // claims it first. Another thread may have claimed a later
+                    // time since this one read the clock, so don't underflow.
+                    let now_millis = estimator.start.elapsed().as_millis() as u64;
+                    let last_millis = last_report_millis.load(Ordering::Relaxed);
+
+                    if now_millis.saturating_sub(last_millis) >= REPORT_INTERVAL_MILLIS
+                        && last_report_millis
+                            .compare_exchange(
+                                last_millis,
+                                now_millis,
+                                Ordering::Relaxed,
+                                Ordering::Relaxed,
+                            )
+                            .is_ok()
+                    {
+                        let progress =
+                            estimator.progress(pass, samples + row_samples, mean_relative_error);
+
+                        for observer in options.observers.iter() {
+                            observer.on_progress(&progress);
+                        }
+                    }
+
+                    stats::take_local()
+                })
+                .reduce(Stats::default, Stats::merge);
+
+            render_stats = render_stats.merge(pass_stats);
+
+            pass_duration_per_sample =
+                Some(pass_start.elapsed().as_secs_f64() / (pass_target - samples_done) as f64);
+            mean_relative_error = film.mean_relative_error();
+
+            let progress = estimator.progress(pass, film.total_sample_count(), mean_relative_error);
+
+            for observer in options.observers.iter() {
+                observer.on_progress(&progress);
+                observer.on_pass(&progress, film);
+            }
         }
 
-        let mut record = HitRecord::new(ray);
-
-        let range = 0.001..f64::INFINITY;
+        let summary = RenderSummary {
+            samples_per_pixel: film.min_sample_count(),
+            mean_relative_error,
+            elapsed: estimator.start.elapsed(),
+            cancelled: cancellation.is_cancelled(),
+            stats: render_stats,
+        };
 
-        if !hittable.hit(ray, &range, &mut record) {
-            return self.background_color;
-            // Sky background
-            // let unit_direction = glm::normalize(r.direction());
-            // let a = (unit_direction.y + 1.0) * 0.5;
-            // vector::one_vec3() * (1.0 - a) + Color::new(0.5, 0.7, 1.0) * a
+        for observer in options.observers.iter() {
+            observer.on_finish(&summary);
         }
 
-        let emission = record.mat.emitted(&record.uv, &record.point);
-
-        let mut scattered = Ray::new(vector::zero_vec3(), vector::zero_vec3(), ray.time());
-        let mut attenuation = vector::zero_vec3();
-        let mat = record.mat.clone();
+        summary
+    }
 
-        if !mat.scatter(&mut record, &mut attenuation, &mut scattered) {
-            return emission;
+    /// Add samples to a single pixel until it has `target` of them.
+    /// Every sample is seeded from its pixel and sample index,
+    /// so the sum comes out the same however the samples are split into passes.
+    fn render_pixel_samples(
+        &self,
+        integrator: &dyn Integrator,
+        scene: &SceneView,
+        seed: u64,
+        i: usize,
+        pixel: &mut FilmPixel,
+        target: u32,
+    ) {
+        let row_index = (i / self.image_width) as Float;
+        let column_index = (i % self.image_width) as Float;
+
+        while pixel.sample_count < target {
+            random::seed(random::sample_seed(seed, i, pixel.sample_count as usize));
+
+            let ray = self.get_ray(column_index, row_index);
+            stats::count(Counter::CameraRays);
+            pixel.add_sample(integrator.radiance(&ray, scene, pixel.sample_count));
         }
-        let scatter = self.ray_color(&scattered, hittable, depth - 1) * attenuation;
+    }
+
+    /// Add samples to pixels next to each other in a row until they all have
+    /// `target` of them, like `render_pixel_samples` does for each on its own,
+    /// but finding where their camera rays first hit the scene in packets
+    #[allow(clippy::too_many_arguments)]
+    fn render_packets<'a>(
+        &self,
+        integrator: &dyn Integrator,
+        scene: &SceneView<'a>,
+        buffers: &mut PacketBuffers<'a>,
+        seed: u64,
+        first: usize,
+        pixels: &mut [FilmPixel],
+        target: u32,
+    ) {
+        let row_index = (first / self.image_width) as Float;
+        let first_column = first % self.image_width;
+
+        let PacketBuffers {
+            samples,
+            generators,
+            packet,
+            records,
+        } = buffers;
+
+        loop {
+            samples.clear();
+            samples.extend(pixels.iter().enumerate().flat_map(|(offset, pixel)| {
+                let end = target.min(pixel.sample_count.saturating_add(PACKET_SAMPLES));
+                (pixel.sample_count..end).map(move |sample| (offset, sample))
+            }));
+
+            if samples.is_empty() {
+                return;
+            }
 
-        emission + scatter
+            // Each path carries on with the generator from where making its
+            // camera ray left it, so keep that for when the path gets traced
+            generators.clear();
+            packet.fill(samples.iter().map(|&(offset, sample)| {
+                random::seed(random::sample_seed(seed, first + offset, sample as usize));
+                let ray = self.get_ray((first_column + offset) as Float, row_index);
+                generators.push(random::save());
+                ray
+            }));
+
+            records.clear();
+            records.resize(packet.len(), None);
+            scene.world.hit_packet(packet, records);
+
+            let paths = samples.iter().zip(generators.drain(..)).zip(records.iter());
+
+            for (i, ((&(offset, sample), generator), record)) in paths.enumerate() {
+                let ray = packet.rays()[i];
+                random::restore(generator);
+                stats::count(Counter::CameraRays);
+
+                if packet.gave_up(i) {
+                    pixels[offset].add_sample(integrator.radiance(&ray, scene, sample));
+                    continue;
+                }
+
+                let world = KnownHit {
+                    world: scene.world,
+                    ray,
+                    record: *record,
+                };
+                let scene = SceneView {
+                    world: &world,
+                    ..*scene
+                };
+
+                pixels[offset].add_sample(integrator.radiance(&ray, &scene, sample));
+            }
+        }
     }
 
-    fn defocus_disk_sample(&self) -> Vec3 {
+    fn defocus_disk_sample(&self) -> Point3 {
         let p = vector::random_unit_disk_vec();
         self.position + self.defocus_disk_u * p.x + self.defocus_disk_v * p.y
     }
 }
+
+/// What `render_packets` fills in for every packet, kept by each thread
+/// from one packet to the next so they don't all allocate their own
+#[derive(Default)]
+struct PacketBuffers<'a> {
+    /// The pixel and sample index of every ray in the packet
+    samples: Vec<(usize, u32)>,
+    /// Where the generator got to making each of those rays
+    generators: Vec<SmallRng>,
+    packet: RayPacket,
+    records: Vec<Option<HitRecord<'a>>>,
+}
@@ -0,0 +1,113 @@
+//! Save and restore partially finished renders
+//!
+//! A checkpoint file holds everything needed to pick a render back up:
+//! the accumulated film and the settings that decide what each sample
+//! looks like. Because every sample is seeded from its pixel and sample index,
+//! a resumed render ends up identical to one that was never interrupted.
+
+use crate::encoding::{self, invalid_data, read_string, read_u32, read_u64, read_u8};
+use crate::film::Film;
+use crate::integrator::IntegratorKind;
+
+use std::{
+    fs,
+    io::{self, BufReader, BufWriter, Read, Seek, Write},
+    path::Path,
+};
+
+const MAGIC: &[u8; 4] = b"RTCK";
+const VERSION: u32 = 4;
+
+/// The settings a render has to keep to be resumable
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub scene: u8,
+    pub seed: u64,
+    pub max_depth: usize,
+    pub integrator: IntegratorKind,
+}
+
+impl Checkpoint {
+    /// Write the checkpoint to disk.
+    /// The data goes to a temporary file first, which then replaces the old
+    /// checkpoint, so a crash mid-write never leaves us without a checkpoint.
+    pub fn save(&self, path: &Path, film: &Film) -> io::Result<()> {
+        let temp_path = path.with_extension("tmp");
+
+        {
+            let mut writer = BufWriter::new(fs::File::create(&temp_path)?);
+
+            writer.write_all(MAGIC)?;
+            writer.write_all(&VERSION.to_le_bytes())?;
+            writer.write_all(&[self.scene])?;
+            encoding::write_string(&mut writer, &self.integrator.to_string())?;
+            encoding::write_u64(&mut writer, self.seed)?;
+            encoding::write_u64(&mut writer, self.max_depth as u64)?;
+            encoding::write_u64(&mut writer, film.width() as u64)?;
+            encoding::write_u64(&mut writer, film.height() as u64)?;
+            encoding::write_pixels(&mut writer, film.pixels())?;
+
+            writer.flush()?;
+            writer.get_ref().sync_all()?;
+        }
+
+        fs::rename(temp_path, path)
+    }
+
+    pub fn load(path: &Path) -> io::Result<(Self, Film)> {
+        let file = fs::File::open(path)?;
+        let file_length = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+
+        if &magic != MAGIC {
+            return Err(invalid_data("not a checkpoint file"));
+        }
+
+        let version = read_u32(&mut reader)?;
+
+        if version != VERSION {
+            return Err(invalid_data(&format!(
+                "unsupported checkpoint version {}",
+                version
+            )));
+        }
+
+        let scene = read_u8(&mut reader)?;
+        let integrator: IntegratorKind = read_string(&mut reader)?
+            .parse()
+            .map_err(|error: String| invalid_data(&error))?;
+        let seed = read_u64(&mut reader)?;
+        let max_depth = read_u64(&mut reader)? as usize;
+        let width = read_u64(&mut reader)? as usize;
+        let height = read_u64(&mut reader)? as usize;
+
+        // The pixels are all that's left, so a damaged size can't make us
+        // allocate more than the file could hold
+        let remaining = file_length.saturating_sub(reader.stream_position()?);
+        let count = width
+            .checked_mul(height)
+            .filter(|count| {
+                count
+                    .checked_mul(encoding::PIXEL_SIZE)
+                    .is_some_and(|size| size as u64 == remaining)
+            })
+            .ok_or_else(|| invalid_data("film size does not match the file's length"))?;
+
+        let pixels = encoding::read_pixels(&mut reader, count)?;
+
+        let film = Film::from_pixels(width, height, pixels)
+            .ok_or_else(|| invalid_data("film size does not match its dimensions"))?;
+
+        let checkpoint = Self {
+            scene,
+            seed,
+            max_depth,
+            integrator,
+        };
+
+        Ok((checkpoint, film))
+    }
+}
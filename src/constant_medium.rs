@@ -3,25 +3,28 @@
 use crate::{
     hittable::{HitRecord, HittableObject},
     material::{Isotropic, Material},
+    random,
     ray::Ray,
+    stats::{self, Counter},
     texture::Texture,
-    vector::{Color, Vec3},
+    vector::{Color, Float, Normal3, Vec3},
 };
 
-use glm;
 use rand::Rng;
 use std::{ops::Range, sync::Arc};
 
-const EPSILON: f64 = 0.00001;
-
 pub struct ConstantMedium {
     boundary: Arc<dyn HittableObject>,
-    negative_inverse_density: f64,
+    negative_inverse_density: Float,
     phase_function: Arc<dyn Material>,
 }
 
 impl ConstantMedium {
-    pub fn new(boundary: Arc<dyn HittableObject>, density: f64, texture: Arc<dyn Texture>) -> Self {
+    pub fn new(
+        boundary: Arc<dyn HittableObject>,
+        density: Float,
+        texture: Arc<dyn Texture>,
+    ) -> Self {
         Self {
             boundary,
             negative_inverse_density: -density.recip(),
@@ -29,64 +32,92 @@ impl ConstantMedium {
         }
     }
 
-    pub fn from_color(boundary: Arc<dyn HittableObject>, density: f64, albedo: Color) -> Self {
+    pub fn from_color(boundary: Arc<dyn HittableObject>, density: Float, albedo: Color) -> Self {
         Self {
             boundary,
             negative_inverse_density: -density.recip(),
             phase_function: Arc::new(Isotropic::from_color(albedo)),
         }
     }
-}
 
-impl HittableObject for ConstantMedium {
-    fn hit(&self, ray: &Ray, range: &Range<f64>, record: &mut HitRecord) -> bool {
-        let mut rng = rand::thread_rng();
+    /// How far along `ray` it scatters off the medium within `range`, if it does
+    fn scatter_distance(&self, ray: &Ray, range: &Range<Float>) -> Option<Float> {
+        stats::count(Counter::ConstantMediumTests);
+
+        let mut rng = random::rng();
         let mut record1 = HitRecord::new(ray);
         let mut record2 = HitRecord::new(ray);
 
-        let universe = f64::NEG_INFINITY..f64::INFINITY;
+        let universe = Float::NEG_INFINITY..Float::INFINITY;
 
         if !self.boundary.hit(ray, &universe, &mut record1) {
-            return false;
+            return None;
         }
 
-        let range_after_entrance = (record1.t + EPSILON)..f64::INFINITY;
+        // Anything past where the ray went in, which will be where it comes out
+        let range_after_entrance = record1.t.next_up()..Float::INFINITY;
 
         if !self.boundary.hit(ray, &range_after_entrance, &mut record2) {
-            return false;
+            return None;
         }
 
-        record1.t = f64::max(record1.t, range.start);
-        record2.t = f64::min(record2.t, range.end);
+        record1.t = Float::max(record1.t, range.start);
+        record2.t = Float::min(record2.t, range.end);
 
         if record1.t >= record2.t {
-            return false;
+            return None;
         }
 
-        record1.t = f64::max(record1.t, 0.0);
+        record1.t = Float::max(record1.t, 0.0);
 
-        let ray_length = glm::length(ray.direction());
+        let ray_length = ray.direction().length();
         let distance_inside_boundary = (record2.t - record1.t) * ray_length;
 
-        let hit_distance_coefficient = rng.gen::<f64>().ln();
+        let hit_distance_coefficient = rng.gen::<Float>().ln();
         let hit_distance = self.negative_inverse_density * hit_distance_coefficient;
 
         if hit_distance > distance_inside_boundary {
-            return false;
+            return None;
         }
 
-        record.t = record1.t + hit_distance / ray_length;
+        Some(record1.t + hit_distance / ray_length)
+    }
+}
+
+impl HittableObject for ConstantMedium {
+    fn hit<'a>(&'a self, ray: &Ray, range: &Range<Float>, record: &mut HitRecord<'a>) -> bool {
+        let Some(t) = self.scatter_distance(ray, range) else {
+            return false;
+        };
+
+        record.t = t;
         record.point = ray.at(record.t);
-        record.mat = self.phase_function.clone();
+
+        // Somewhere inside the volume rather than on a surface,
+        // so rays can leave from the point itself
+        record.error = Vec3::ZERO;
+        record.mat = &*self.phase_function;
 
         // Both of these are completely arbitrary
-        record.normal = Vec3::new(1.0, 0.0, 0.0);
+        record.normal = Normal3::new(1.0, 0.0, 0.0);
         record.front_face = true;
 
         true
     }
 
+    fn occluded(&self, ray: &Ray, range: &Range<Float>) -> bool {
+        self.scatter_distance(ray, range).is_some()
+    }
+
     fn bounding_box(&self) -> &crate::aabb::AABB {
         self.boundary.bounding_box()
     }
+
+    fn materials<'a>(&'a self, materials: &mut Vec<&'a dyn Material>) {
+        materials.push(&*self.phase_function);
+    }
+
+    fn has_random_hits(&self) -> bool {
+        true
+    }
 }
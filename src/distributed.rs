@@ -0,0 +1,420 @@
+//! Rendering a single image on many machines
+//!
+//! A coordinator splits the image into tiles of a few rows each and hands them
+//! out to workers that connect to it over TCP. Every worker builds the same
+//! scene from its id and seed, renders its tiles with the usual per-sample
+//! seeds and sends back the accumulated pixels. The merged image is exactly
+//! what a single machine would have rendered.
+//!
+//! While a worker renders a tile, it keeps telling the coordinator it's still
+//! at it, however long the tile takes. Workers that drop out, or go quiet for
+//! too long, have their tile handed to someone else, and new workers can join
+//! at any point. Each tile comes back with the worker's statistics for it,
+//! which add up to the whole render's.
+
+use crate::camera::{Camera, RenderOptions, RenderSettings};
+use crate::encoding::{self, invalid_data, read_string, read_u32, read_u64, read_u8};
+use crate::film::Film;
+use crate::integrator::{Integrator, IntegratorKind};
+use crate::progress::{Progress, ProgressObserver, RenderSummary};
+use crate::random;
+use crate::scene::Scene;
+use crate::stats::{self, Stats};
+
+use std::{
+    collections::VecDeque,
+    io::{self, BufReader, BufWriter, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    ops::Range,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+const MAGIC: &[u8; 4] = b"RTDW";
+const VERSION: u32 = 4;
+
+const MESSAGE_DONE: u8 = 0;
+const MESSAGE_TILE: u8 = 1;
+/// From a worker that's still rendering its tile
+const MESSAGE_WORKING: u8 = 2;
+
+/// How often a worker says it's still rendering its tile
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long to wait between checks for new workers or finished tiles
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How long a worker keeps trying to reach a coordinator that isn't up yet
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Everything a worker needs to know to render its share of the image
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RenderJob {
+    pub scene: u8,
+    pub seed: u64,
+    pub image_width: usize,
+    pub samples_per_pixel: usize,
+    pub max_depth: usize,
+    pub integrator: IntegratorKind,
+    /// Whether workers count statistics and send them back with their tiles
+    pub stats: bool,
+}
+
+impl RenderJob {
+    fn write_tile(&self, writer: &mut impl Write, rows: &Range<usize>) -> io::Result<()> {
+        writer.write_all(&[MESSAGE_TILE, self.scene])?;
+        encoding::write_string(writer, &self.integrator.to_string())?;
+        encoding::write_u64(writer, self.seed)?;
+        encoding::write_u64(writer, self.image_width as u64)?;
+        encoding::write_u64(writer, self.samples_per_pixel as u64)?;
+        encoding::write_u64(writer, self.max_depth as u64)?;
+        writer.write_all(&[self.stats as u8])?;
+        encoding::write_u64(writer, rows.start as u64)?;
+        encoding::write_u64(writer, rows.end as u64)?;
+        writer.flush()
+    }
+
+    /// Read the rest of a tile message, after its message type
+    fn read_tile(reader: &mut impl Read) -> io::Result<(Self, Range<usize>)> {
+        let scene = read_u8(reader)?;
+        let integrator: IntegratorKind = read_string(reader)?
+            .parse()
+            .map_err(|error: String| invalid_data(&error))?;
+
+        let job = Self {
+            scene,
+            seed: read_u64(reader)?,
+            image_width: read_u64(reader)? as usize,
+            samples_per_pixel: read_u64(reader)? as usize,
+            max_depth: read_u64(reader)? as usize,
+            integrator,
+            stats: read_u8(reader)? != 0,
+        };
+
+        let rows = (read_u64(reader)? as usize)..(read_u64(reader)? as usize);
+
+        Ok((job, rows))
+    }
+}
+
+fn write_tile_result(writer: &mut impl Write, tile: &Film, stats: &Stats) -> io::Result<()> {
+    writer.write_all(&[MESSAGE_TILE])?;
+    encoding::write_u64(writer, tile.rows().start as u64)?;
+    encoding::write_u64(writer, tile.rows().end as u64)?;
+    encoding::write_pixels(writer, tile.pixels())?;
+    stats.write(writer)?;
+    writer.flush()
+}
+
+/// Read a worker's result for the tile covering `expected` rows, skipping
+/// the messages it sends while it's still working on it. The rows are checked
+/// before anything gets allocated for the pixels, so a worker can't make
+/// the coordinator allocate more than the tile.
+fn read_tile_result(
+    reader: &mut impl Read,
+    width: usize,
+    expected: &Range<usize>,
+) -> io::Result<(Film, Stats)> {
+    loop {
+        match read_u8(reader)? {
+            MESSAGE_WORKING => continue,
+            MESSAGE_TILE => break,
+            _ => return Err(invalid_data("unknown message from worker")),
+        }
+    }
+
+    let rows = (read_u64(reader)? as usize)..(read_u64(reader)? as usize);
+
+    if rows != *expected {
+        return Err(invalid_data("worker sent back the wrong tile"));
+    }
+
+    let pixels = encoding::read_pixels(reader, width * rows.len())?;
+    let stats = Stats::read(reader)?;
+
+    let tile = Film::tile_from_pixels(width, rows, pixels)
+        .ok_or_else(|| invalid_data("tile size does not match its rows"))?;
+
+    Ok((tile, stats))
+}
+
+/// Accept workers on `listener` and have them render every row of `film`,
+/// `tile_rows` rows at a time. A worker that isn't heard from for
+/// `tile_timeout` is dropped, and its tile goes to another worker.
+/// Returns once every tile is back, after telling the workers to stop.
+pub fn coordinate(
+    listener: TcpListener,
+    job: &RenderJob,
+    film: &mut Film,
+    tile_rows: usize,
+    tile_timeout: Duration,
+    observers: &[&dyn ProgressObserver],
+) -> io::Result<RenderSummary> {
+    let start = Instant::now();
+    let rows = film.rows();
+
+    let tiles: VecDeque<Range<usize>> = rows
+        .clone()
+        .step_by(tile_rows.max(1))
+        .map(|first_row| first_row..usize::min(first_row + tile_rows.max(1), rows.end))
+        .collect();
+
+    let tile_count = tiles.len();
+    let queue = Mutex::new(tiles);
+    let remaining = AtomicUsize::new(tile_count);
+    let (sender, receiver) = mpsc::channel::<(Film, Stats)>();
+    let mut render_stats = Stats::default();
+
+    listener.set_nonblocking(true)?;
+
+    for observer in observers.iter() {
+        observer.on_start();
+    }
+
+    thread::scope(|scope| {
+        let queue = &queue;
+        let remaining = &remaining;
+
+        scope.spawn(move || {
+            while remaining.load(Ordering::Relaxed) > 0 {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        let sender = sender.clone();
+
+                        scope.spawn(move || {
+                            // A worker that misbehaves or disconnects simply
+                            // stops getting tiles, and its tile goes back in the queue.
+                            let _ =
+                                serve_worker(stream, job, tile_timeout, queue, remaining, sender);
+                        });
+                    }
+                    Err(error) if error.kind() == io::ErrorKind::WouldBlock => {
+                        thread::sleep(POLL_INTERVAL);
+                    }
+                    Err(_) => thread::sleep(POLL_INTERVAL),
+                }
+            }
+        });
+
+        for tiles_done in 1..=tile_count {
+            let (tile, tile_stats) = receiver
+                .recv()
+                .expect("tile results can't stop before every tile is done");
+
+            film.merge_tile(&tile);
+            render_stats = render_stats.merge(tile_stats);
+            remaining.fetch_sub(1, Ordering::Relaxed);
+
+            let fraction = tiles_done as f64 / tile_count as f64;
+            let elapsed = start.elapsed();
+
+            let progress = Progress {
+                pass: 1,
+                samples_per_pixel: job.samples_per_pixel as f64 * fraction,
+                fraction: Some(fraction),
+                elapsed,
+                eta: Some(elapsed.mul_f64((1.0 - fraction) / fraction)),
+                mean_relative_error: None,
+            };
+
+            for observer in observers.iter() {
+                observer.on_progress(&progress);
+            }
+        }
+    });
+
+    let summary = RenderSummary {
+        samples_per_pixel: film.min_sample_count(),
+        mean_relative_error: film.mean_relative_error(),
+        elapsed: start.elapsed(),
+        cancelled: false,
+        stats: render_stats,
+    };
+
+    for observer in observers.iter() {
+        observer.on_finish(&summary);
+    }
+
+    Ok(summary)
+}
+
+fn serve_worker(
+    stream: TcpStream,
+    job: &RenderJob,
+    tile_timeout: Duration,
+    queue: &Mutex<VecDeque<Range<usize>>>,
+    remaining: &AtomicUsize,
+    sender: mpsc::Sender<(Film, Stats)>,
+) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+
+    // A worker that hangs would otherwise hold on to its tile forever.
+    // Working workers say so every few seconds, which starts the wait again,
+    // so never wait less than a couple of those.
+    let timeout = tile_timeout.max(HEARTBEAT_INTERVAL * 2);
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+
+    if &magic != MAGIC || read_u32(&mut reader)? != VERSION {
+        return Err(invalid_data("not a compatible worker"));
+    }
+
+    loop {
+        let next_tile = queue.lock().unwrap().pop_front();
+
+        let Some(rows) = next_tile else {
+            // Other workers might still drop their tiles,
+            // so only stop once every tile has made it back.
+            if remaining.load(Ordering::Relaxed) == 0 {
+                writer.write_all(&[MESSAGE_DONE])?;
+                return writer.flush();
+            }
+
+            thread::sleep(POLL_INTERVAL);
+            continue;
+        };
+
+        let result = job
+            .write_tile(&mut writer, &rows)
+            .and_then(|_| read_tile_result(&mut reader, job.image_width, &rows));
+
+        match result {
+            Ok(tile) => sender
+                .send(tile)
+                .map_err(|_| io::Error::other("coordinator stopped"))?,
+            Err(error) => {
+                queue.lock().unwrap().push_back(rows);
+                return Err(error);
+            }
+        }
+    }
+}
+
+/// Connect to a coordinator and render tiles for it until it says we're done.
+/// `build_scene` turns a scene id into the scene to render;
+/// it runs with the random number generator seeded from the job,
+/// so it sees the same random scene layout as every other machine.
+/// Returns how many tiles this worker rendered.
+pub fn run_worker(
+    address: impl ToSocketAddrs,
+    build_scene: impl Fn(u8) -> Option<Scene>,
+) -> io::Result<usize> {
+    let stream = connect_with_retry(address)?;
+
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let writer = Mutex::new(BufWriter::new(stream));
+
+    {
+        let mut writer = writer.lock().unwrap();
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.flush()?;
+    }
+
+    let mut cached_scene: Option<(RenderJob, Scene, Box<dyn Integrator>)> = None;
+    let mut tiles_rendered = 0;
+
+    loop {
+        match read_u8(&mut reader)? {
+            MESSAGE_DONE => return Ok(tiles_rendered),
+            MESSAGE_TILE => {}
+            _ => return Err(invalid_data("unknown message from coordinator")),
+        }
+
+        let (job, rows) = RenderJob::read_tile(&mut reader)?;
+
+        let scene_is_cached = matches!(
+            &cached_scene,
+            Some((cached_job, _, _)) if cached_job.scene == job.scene
+                && cached_job.seed == job.seed
+                && cached_job.integrator == job.integrator
+        );
+
+        if !scene_is_cached {
+            random::seed(job.seed);
+
+            let scene = build_scene(job.scene)
+                .ok_or_else(|| invalid_data(&format!("unknown scene {}", job.scene)))?;
+            let integrator = job.integrator.build(&scene);
+
+            cached_scene = Some((job, scene, integrator));
+        }
+
+        let (_, scene, integrator) = cached_scene.as_ref().unwrap();
+
+        let camera = Camera::new(
+            &scene.camera_settings,
+            &RenderSettings {
+                image_width: job.image_width,
+                samples_per_pixel: job.samples_per_pixel,
+                max_depth: job.max_depth,
+            },
+        );
+
+        if rows.end > camera.image_height() {
+            return Err(invalid_data("tile is outside of the image"));
+        }
+
+        let mut tile = Film::tile(job.image_width, rows);
+
+        let options = RenderOptions {
+            seed: job.seed,
+            integrator: Some(integrator.as_ref()),
+            ..Default::default()
+        };
+
+        // The coordinator says whether it wants the counters
+        stats::set_enabled(job.stats || integrator.needs_stats());
+
+        let summary = thread::scope(|scope| {
+            let (stop, stopped) = mpsc::channel::<()>();
+            let writer = &writer;
+
+            // Keep the coordinator from giving up on us however long the tile takes
+            scope.spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(HEARTBEAT_INTERVAL)
+                {
+                    let mut writer = writer.lock().unwrap();
+
+                    if writer
+                        .write_all(&[MESSAGE_WORKING])
+                        .and_then(|_| writer.flush())
+                        .is_err()
+                    {
+                        return;
+                    }
+                }
+            });
+
+            let summary = camera.render_film(scene, &mut tile, &options);
+            drop(stop);
+            summary
+        });
+
+        write_tile_result(&mut *writer.lock().unwrap(), &tile, &summary.stats)?;
+        tiles_rendered += 1;
+    }
+}
+
+fn connect_with_retry(address: impl ToSocketAddrs) -> io::Result<TcpStream> {
+    let start = Instant::now();
+
+    loop {
+        match TcpStream::connect(&address) {
+            Ok(stream) => return Ok(stream),
+            Err(error) if start.elapsed() >= CONNECT_TIMEOUT => return Err(error),
+            Err(_) => thread::sleep(POLL_INTERVAL * 4),
+        }
+    }
+}
@@ -0,0 +1,102 @@
+//! Little-endian binary encoding shared by checkpoint files,
+//! the distributed rendering protocol and the BVH cache
+
+use crate::film::FilmPixel;
+use crate::vector::{self, Color, Float};
+
+use std::io::{self, Read, Write};
+
+/// The longest text that gets read back, so a corrupt length
+/// can't ask for gigabytes. The only text is an integrator's name
+const MAX_STRING_LENGTH: usize = 256;
+
+pub(crate) fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+pub(crate) fn read_u8(reader: &mut impl Read) -> io::Result<u8> {
+    let mut bytes = [0; 1];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes[0])
+}
+
+pub(crate) fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+pub(crate) fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+pub(crate) fn read_f64(reader: &mut impl Read) -> io::Result<f64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(f64::from_le_bytes(bytes))
+}
+
+pub(crate) fn read_string(reader: &mut impl Read) -> io::Result<String> {
+    let length = read_u32(reader)? as usize;
+    if length > MAX_STRING_LENGTH {
+        return Err(invalid_data("text is too long"));
+    }
+
+    let mut bytes = vec![0; length];
+    reader.read_exact(&mut bytes)?;
+
+    String::from_utf8(bytes).map_err(|_| invalid_data("text is not valid UTF-8"))
+}
+
+pub(crate) fn write_u32(writer: &mut impl Write, value: u32) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+pub(crate) fn write_u64(writer: &mut impl Write, value: u64) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+pub(crate) fn write_f64(writer: &mut impl Write, value: f64) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+pub(crate) fn write_string(writer: &mut impl Write, value: &str) -> io::Result<()> {
+    debug_assert!(value.len() <= MAX_STRING_LENGTH);
+    writer.write_all(&(value.len() as u32).to_le_bytes())?;
+    writer.write_all(value.as_bytes())
+}
+
+/// How many bytes `write_pixels` writes for each pixel
+pub(crate) const PIXEL_SIZE: usize = 4 * 8 + 4;
+
+pub(crate) fn write_pixels(writer: &mut impl Write, pixels: &[FilmPixel]) -> io::Result<()> {
+    for pixel in pixels {
+        write_f64(writer, vector::to_f64(pixel.sum.x))?;
+        write_f64(writer, vector::to_f64(pixel.sum.y))?;
+        write_f64(writer, vector::to_f64(pixel.sum.z))?;
+        writer.write_all(&pixel.luminance_sum_squares.to_le_bytes())?;
+        writer.write_all(&pixel.sample_count.to_le_bytes())?;
+    }
+
+    Ok(())
+}
+
+pub(crate) fn read_pixels(reader: &mut impl Read, count: usize) -> io::Result<Vec<FilmPixel>> {
+    let mut pixels = Vec::with_capacity(count);
+
+    for _ in 0..count {
+        let x = read_f64(reader)?;
+        let y = read_f64(reader)?;
+        let z = read_f64(reader)?;
+
+        pixels.push(FilmPixel {
+            sum: Color::new(x as Float, y as Float, z as Float),
+            luminance_sum_squares: read_f64(reader)?,
+            sample_count: read_u32(reader)?,
+        });
+    }
+
+    Ok(pixels)
+}
@@ -0,0 +1,200 @@
+//! Accumulated radiance for every pixel of a render
+
+use crate::vector;
+use crate::vector::{Color, Float, Pixel};
+
+use std::ops::Range;
+
+/// Added to a pixel's brightness before dividing by it,
+/// so that nearly black pixels don't dominate the relative error.
+const RELATIVE_ERROR_EPSILON: f64 = 0.01;
+
+/// Everything we've gathered about a single pixel so far
+#[derive(Debug, Copy, Clone)]
+pub struct FilmPixel {
+    pub sum: Color,
+    pub luminance_sum_squares: f64,
+    pub sample_count: u32,
+}
+
+impl FilmPixel {
+    pub fn new() -> Self {
+        Self {
+            sum: Color::ZERO,
+            luminance_sum_squares: 0.0,
+            sample_count: 0,
+        }
+    }
+
+    pub fn add_sample(&mut self, color: Color) {
+        let luminance = vector::to_f64(vector::luminance(&color));
+
+        self.sum += color;
+        self.luminance_sum_squares += luminance * luminance;
+        self.sample_count += 1;
+    }
+
+    /// The average radiance that reached the pixel
+    pub fn mean(&self) -> Color {
+        match self.sample_count {
+            0 => Color::ZERO,
+            count => self.sum / count as Float,
+        }
+    }
+
+    /// An estimate of how far the pixel's mean brightness is from the
+    /// converged result, relative to that brightness.
+    /// We need at least two samples to say anything about the variance.
+    pub fn relative_error(&self) -> Option<f64> {
+        if self.sample_count < 2 {
+            return None;
+        }
+
+        let count = self.sample_count as f64;
+        let mean = vector::to_f64(vector::luminance(&self.sum)) / count;
+        let variance = (self.luminance_sum_squares / count - mean * mean) * count / (count - 1.0);
+        let standard_error = (f64::max(variance, 0.0) / count).sqrt();
+
+        Some(standard_error / (mean + RELATIVE_ERROR_EPSILON))
+    }
+}
+
+impl Default for FilmPixel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The pixels of a whole image, or of a tile made of some of its rows
+#[derive(Debug, Clone)]
+pub struct Film {
+    width: usize,
+    height: usize,
+    first_row: usize,
+    pixels: Vec<FilmPixel>,
+}
+
+impl Film {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self::tile(width, 0..height)
+    }
+
+    /// A film that only covers some of the image's rows
+    pub fn tile(width: usize, rows: Range<usize>) -> Self {
+        Self {
+            width,
+            height: rows.len(),
+            first_row: rows.start,
+            pixels: vec![FilmPixel::new(); width * rows.len()],
+        }
+    }
+
+    pub fn from_pixels(width: usize, height: usize, pixels: Vec<FilmPixel>) -> Option<Self> {
+        Self::tile_from_pixels(width, 0..height, pixels)
+    }
+
+    pub fn tile_from_pixels(
+        width: usize,
+        rows: Range<usize>,
+        pixels: Vec<FilmPixel>,
+    ) -> Option<Self> {
+        match pixels.len() == width * rows.len() {
+            true => Some(Self {
+                width,
+                height: rows.len(),
+                first_row: rows.start,
+                pixels,
+            }),
+            false => None,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// The rows of the image this film covers
+    pub fn rows(&self) -> Range<usize> {
+        self.first_row..(self.first_row + self.height)
+    }
+
+    /// Where this film's first pixel sits in the whole image
+    pub fn first_pixel_index(&self) -> usize {
+        self.first_row * self.width
+    }
+
+    /// Copy a tile's pixels into the matching rows of this film
+    pub fn merge_tile(&mut self, tile: &Film) {
+        let start = tile.first_pixel_index() - self.first_pixel_index();
+        self.pixels[start..(start + tile.num_pixels())].copy_from_slice(tile.pixels());
+    }
+
+    pub fn num_pixels(&self) -> usize {
+        self.width * self.height
+    }
+
+    pub fn pixels(&self) -> &[FilmPixel] {
+        &self.pixels
+    }
+
+    pub fn pixels_mut(&mut self) -> &mut [FilmPixel] {
+        &mut self.pixels
+    }
+
+    /// The fewest samples taken by any pixel
+    pub fn min_sample_count(&self) -> u32 {
+        self.pixels
+            .iter()
+            .map(|pixel| pixel.sample_count)
+            .min()
+            .unwrap_or(0)
+    }
+
+    /// Samples taken across every pixel
+    pub fn total_sample_count(&self) -> u64 {
+        self.pixels
+            .iter()
+            .map(|pixel| pixel.sample_count as u64)
+            .sum()
+    }
+
+    /// The relative error averaged over every pixel,
+    /// or `None` if there aren't enough samples to estimate it yet.
+    pub fn mean_relative_error(&self) -> Option<f64> {
+        let total = self
+            .pixels
+            .iter()
+            .map(|pixel| pixel.relative_error())
+            .sum::<Option<f64>>()?;
+
+        Some(total / self.num_pixels() as f64)
+    }
+
+    /// The average radiance that reached a pixel
+    pub fn pixel_color(&self, i: usize) -> Color {
+        self.pixels[i].mean()
+    }
+
+    pub fn pixel(&self, i: usize) -> Pixel {
+        // Use sqrt for gamma correction
+        let color = self.pixel_color(i).sqrt();
+        let color = color.clamp(0.0, 0.999) * 256.0;
+
+        Pixel::from_color(&color)
+    }
+
+    pub fn write_ppm(&self) {
+        // Print the PPM header
+        println!("P3\n{} {}\n255\n", self.width, self.height);
+
+        // Print the PPM data
+        for i in 0..self.num_pixels() {
+            let pixel = self.pixel(i);
+            println!("{} {} {}", pixel.x, pixel.y, pixel.z);
+        }
+    }
+}
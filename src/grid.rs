@@ -0,0 +1,451 @@
+//! Multi-level uniform grid
+//!
+//! The box around the objects gets cut into equal cells, about as many as
+//! there are objects times `DENSITY`, and every object goes into each cell its
+//! box overlaps. A ray steps through the cells it goes through in order, and
+//! can stop as soon as it hits something before the end of the cell it's in.
+//!
+//! Scenes where the objects are spread out evenly, like a floor of boxes,
+//! suit a grid well. Where a few cells end up crowded, say because one huge
+//! object stretches the grid over lots of empty space, those cells get a finer
+//! grid of their own, around just the part of the cell their objects take up.
+//!
+//! Objects can be in several cells, and so get tested more than once by the
+//! same ray. Objects whose hits are random, and ones without a finite box,
+//! stay out of the cells and get tested by every ray instead.
+
+use crate::{
+    aabb::{AABB, EXIT_SCALE},
+    hittable::{HitRecord, HittableObject},
+    material::Material,
+    ray::Ray,
+    stats::{self, Counter},
+    vector::Float,
+};
+
+use std::{mem, ops::Range, sync::Arc};
+
+/// How many cells a grid gets per object in it
+const DENSITY: Float = 4.0;
+
+/// The most cells a grid gets along any axis
+const MAX_RESOLUTION: usize = 128;
+
+/// Cells with more objects than this get a finer grid of their own
+const MAX_CELL_OBJECTS: usize = 16;
+
+/// How many grids deep cells can get split
+const MAX_LEVELS: usize = 3;
+
+/// Crowded cells only get a finer grid if a point in them is, on average,
+/// inside the boxes of at most this share of their objects
+const MAX_CELL_OVERLAP: Float = 0.25;
+
+#[derive(Debug, Copy, Clone)]
+enum Cell {
+    /// The objects at `start..start + count` in the grid's `indices`
+    Objects { start: u32, count: u32 },
+    /// A finer grid, by its index in the grid's `levels`
+    Level(u32),
+}
+
+/// One grid of cells, either the whole grid or one that splits a crowded cell
+#[derive(Debug, Clone)]
+struct Level {
+    min: [Float; 3],
+    max: [Float; 3],
+    cell_size: [Float; 3],
+    resolution: [usize; 3],
+    /// By x first, then y, then z
+    cells: Vec<Cell>,
+}
+
+impl Level {
+    fn new(min: [Float; 3], max: [Float; 3], objects: usize) -> Self {
+        let extent = [0, 1, 2].map(|axis| max[axis] - min[axis]);
+        let resolution = resolution(&extent, objects);
+
+        Self {
+            min,
+            max,
+            cell_size: [0, 1, 2].map(|axis| extent[axis] / resolution[axis] as Float),
+            resolution,
+            cells: Vec::new(),
+        }
+    }
+
+    /// Which cell along `axis` the plane at `position` along it goes through,
+    /// or the nearest one if it's outside the grid
+    fn cell(&self, axis: usize, position: Float) -> usize {
+        let cell = ((position - self.min[axis]) / self.cell_size[axis]) as isize;
+        cell.clamp(0, self.resolution[axis] as isize - 1) as usize
+    }
+
+    fn index(&self, cell: &[usize; 3]) -> usize {
+        (cell[2] * self.resolution[1] + cell[1]) * self.resolution[0] + cell[0]
+    }
+
+    fn cell_bounds(&self, cell: &[usize; 3]) -> ([Float; 3], [Float; 3]) {
+        (
+            [0, 1, 2].map(|axis| self.min[axis] + cell[axis] as Float * self.cell_size[axis]),
+            [0, 1, 2].map(|axis| self.min[axis] + (cell[axis] + 1) as Float * self.cell_size[axis]),
+        )
+    }
+}
+
+/// How many cells to cut a box of the given `extent` into along each axis,
+/// for about `DENSITY` cells per object, all of them about cube shaped.
+/// Axes too thin for even one such cell get only one.
+fn resolution(extent: &[Float; 3], objects: usize) -> [usize; 3] {
+    let cells = DENSITY * objects.max(1) as Float;
+    let mut thin = [false; 3];
+
+    loop {
+        let axes: Vec<usize> = (0..3).filter(|&axis| !thin[axis]).collect();
+
+        if axes.is_empty() {
+            return [1; 3];
+        }
+
+        let size: Float = axes.iter().map(|&axis| extent[axis]).product();
+        let cells_per_unit = (cells / size).powf(1.0 / axes.len() as Float);
+
+        match axes
+            .iter()
+            .find(|&&axis| extent[axis] * cells_per_unit < 1.0)
+        {
+            Some(&axis) => thin[axis] = true,
+            None => {
+                return [0, 1, 2].map(|axis| match thin[axis] {
+                    true => 1,
+                    false => {
+                        ((extent[axis] * cells_per_unit).round() as usize).clamp(1, MAX_RESOLUTION)
+                    }
+                })
+            }
+        }
+    }
+}
+
+/// The corners of `aabb`
+fn corners(aabb: &AABB) -> ([Float; 3], [Float; 3]) {
+    (
+        [aabb.x.start, aabb.y.start, aabb.z.start],
+        [aabb.x.end, aabb.y.end, aabb.z.end],
+    )
+}
+
+pub struct Grid {
+    objects: Vec<Arc<dyn HittableObject>>,
+    /// The objects that aren't in any cell, which every ray gets tested against
+    loose: Vec<u32>,
+    /// The whole grid first, then the finer ones inside its cells
+    levels: Vec<Level>,
+    /// Which objects are in each cell
+    indices: Vec<u32>,
+    aabb: AABB,
+    random_hits: bool,
+}
+
+impl Grid {
+    pub fn new(objects: Vec<Arc<dyn HittableObject>>) -> Self {
+        let aabb = objects
+            .iter()
+            .map(|object| object.bounding_box().clone())
+            .reduce(|total, aabb| AABB::combine_bounds(&total, &aabb))
+            .unwrap_or_default();
+
+        let (loose, placed): (Vec<u32>, Vec<u32>) = (0..objects.len() as u32).partition(|&i| {
+            let object = &objects[i as usize];
+            object.has_random_hits() || !object.bounding_box().is_finite()
+        });
+
+        let mut grid = Self {
+            random_hits: objects.iter().any(|object| object.has_random_hits()),
+            objects,
+            loose,
+            levels: Vec::new(),
+            indices: Vec::new(),
+            aabb,
+        };
+
+        let boxes: Vec<([Float; 3], [Float; 3])> = grid
+            .objects
+            .iter()
+            .map(|object| corners(object.bounding_box()))
+            .collect();
+
+        if !placed.is_empty() {
+            let min = [0, 1, 2].map(|axis| {
+                placed.iter().fold(Float::INFINITY, |min, &i| {
+                    min.min(boxes[i as usize].0[axis])
+                })
+            });
+            let max = [0, 1, 2].map(|axis| {
+                placed.iter().fold(Float::NEG_INFINITY, |max, &i| {
+                    max.max(boxes[i as usize].1[axis])
+                })
+            });
+
+            grid.build_level(&boxes, &placed, min, max, 1);
+        }
+
+        grid
+    }
+
+    /// Build a grid over the box from `min` to `max` for the `objects`,
+    /// and return where it went in `levels`
+    fn build_level(
+        &mut self,
+        boxes: &[([Float; 3], [Float; 3])],
+        objects: &[u32],
+        min: [Float; 3],
+        max: [Float; 3],
+        depth: usize,
+    ) -> u32 {
+        let mut level = Level::new(min, max, objects.len());
+        let mut cells = vec![Vec::new(); level.resolution.iter().product()];
+
+        for &i in objects {
+            let (object_min, object_max) = &boxes[i as usize];
+            let first = [0, 1, 2].map(|axis| level.cell(axis, object_min[axis]));
+            let last = [0, 1, 2].map(|axis| level.cell(axis, object_max[axis]));
+
+            for z in first[2]..=last[2] {
+                for y in first[1]..=last[1] {
+                    for x in first[0]..=last[0] {
+                        cells[level.index(&[x, y, z])].push(i);
+                    }
+                }
+            }
+        }
+
+        let index = self.levels.len() as u32;
+        self.levels.push(level.clone());
+
+        for z in 0..level.resolution[2] {
+            for y in 0..level.resolution[1] {
+                for x in 0..level.resolution[0] {
+                    let cell = [x, y, z];
+                    let cell_objects = mem::take(&mut cells[level.index(&cell)]);
+                    let cell_box = level.cell_bounds(&cell);
+
+                    let finer = match cell_objects.len() > MAX_CELL_OBJECTS && depth < MAX_LEVELS {
+                        true => crowded_bounds(boxes, &cell_objects, &cell_box),
+                        false => None,
+                    };
+
+                    level.cells.push(match finer {
+                        Some((min, max)) => {
+                            Cell::Level(self.build_level(boxes, &cell_objects, min, max, depth + 1))
+                        }
+                        None => {
+                            let start = self.indices.len() as u32;
+                            self.indices.extend_from_slice(&cell_objects);
+
+                            Cell::Objects {
+                                start,
+                                count: cell_objects.len() as u32,
+                            }
+                        }
+                    });
+                }
+            }
+        }
+
+        self.levels[index as usize] = level;
+        index
+    }
+
+    /// Walk the cells of the grid at `index` in `levels` that the ray goes
+    /// through, nearest first, looking for hits closer than `closest`
+    fn hit_level<'a>(
+        &'a self,
+        index: u32,
+        ray: &Ray,
+        start: Float,
+        closest: &mut Float,
+        record: &mut HitRecord<'a>,
+    ) -> bool {
+        let level = &self.levels[index as usize];
+        let origin = [ray.origin().x, ray.origin().y, ray.origin().z];
+        let direction = [ray.direction().x, ray.direction().y, ray.direction().z];
+
+        // Where the ray is inside the grid's box
+        let mut enter = start;
+        let mut exit = *closest;
+
+        for axis in 0..3 {
+            let inverse_direction = direction[axis].recip();
+            let t0 = (level.min[axis] - origin[axis]) * inverse_direction;
+            let t1 = (level.max[axis] - origin[axis]) * inverse_direction;
+
+            enter = Float::max(enter, Float::min(t0, t1));
+            exit = Float::min(exit, Float::max(t0, t1) * EXIT_SCALE);
+        }
+
+        if enter > exit {
+            return false;
+        }
+
+        // Which cell the ray is in, which way it steps along each axis, and
+        // how far along it it gets to the next cell and between cells
+        let mut cell = [0; 3];
+        let mut step = [0; 3];
+        let mut next = [Float::INFINITY; 3];
+        let mut between = [Float::INFINITY; 3];
+
+        for axis in 0..3 {
+            cell[axis] = level.cell(axis, origin[axis] + direction[axis] * enter);
+
+            let (cell_min, cell_max) = (
+                level.min[axis] + cell[axis] as Float * level.cell_size[axis],
+                level.min[axis] + (cell[axis] + 1) as Float * level.cell_size[axis],
+            );
+
+            if direction[axis] > 0.0 {
+                step[axis] = 1;
+                next[axis] = (cell_max - origin[axis]) / direction[axis];
+                between[axis] = level.cell_size[axis] / direction[axis];
+            } else if direction[axis] < 0.0 {
+                step[axis] = -1;
+                next[axis] = (cell_min - origin[axis]) / direction[axis];
+                between[axis] = -level.cell_size[axis] / direction[axis];
+            }
+        }
+
+        let mut hit_anything = false;
+
+        loop {
+            stats::count(Counter::GridCellsVisited);
+
+            match level.cells[level.index(&cell)] {
+                Cell::Objects {
+                    start: first,
+                    count,
+                } => {
+                    let first = first as usize;
+
+                    for &i in &self.indices[first..first + count as usize] {
+                        if self.objects[i as usize].hit(ray, &(start..*closest), record) {
+                            hit_anything = true;
+                            *closest = record.t;
+                        }
+                    }
+                }
+                Cell::Level(finer) => {
+                    hit_anything |= self.hit_level(finer, ray, start, closest, record);
+                }
+            }
+
+            let axis = (0..3).min_by(|&a, &b| next[a].total_cmp(&next[b])).unwrap();
+
+            // Nothing in the cells further along can be any closer
+            if *closest <= next[axis] || next[axis] > exit {
+                return hit_anything;
+            }
+
+            match cell[axis].checked_add_signed(step[axis]) {
+                Some(moved) if moved < level.resolution[axis] => cell[axis] = moved,
+                _ => return hit_anything,
+            }
+
+            next[axis] += between[axis];
+        }
+    }
+}
+
+/// The part of the cell from `cell_box` that the `objects` in it take up,
+/// or `None` if most of them take up most of that anyway,
+/// so a finer grid would mostly hold the same objects in every cell
+fn crowded_bounds(
+    boxes: &[([Float; 3], [Float; 3])],
+    objects: &[u32],
+    cell_box: &([Float; 3], [Float; 3]),
+) -> Option<([Float; 3], [Float; 3])> {
+    let (cell_min, cell_max) = cell_box;
+    let mut min = [Float::INFINITY; 3];
+    let mut max = [Float::NEG_INFINITY; 3];
+
+    for &i in objects {
+        let (object_min, object_max) = &boxes[i as usize];
+
+        for axis in 0..3 {
+            min[axis] = min[axis].min(object_min[axis].max(cell_min[axis]));
+            max[axis] = max[axis].max(object_max[axis].min(cell_max[axis]));
+        }
+    }
+
+    // How many of the objects a point somewhere in there is inside the box of
+    let overlapping: Float = objects
+        .iter()
+        .map(|&i| {
+            let (object_min, object_max) = &boxes[i as usize];
+
+            (0..3)
+                .map(|axis| {
+                    let inside = object_max[axis].min(max[axis]) - object_min[axis].max(min[axis]);
+                    match max[axis] > min[axis] {
+                        true => inside / (max[axis] - min[axis]),
+                        false => 1.0,
+                    }
+                })
+                .product::<Float>()
+        })
+        .sum();
+
+    match overlapping > objects.len() as Float * MAX_CELL_OVERLAP {
+        true => None,
+        false => Some((min, max)),
+    }
+}
+
+impl HittableObject for Grid {
+    fn hit<'a>(&'a self, ray: &Ray, range: &Range<Float>, record: &mut HitRecord<'a>) -> bool {
+        let mut closest = range.end;
+        let mut hit_anything = false;
+
+        for &i in &self.loose {
+            if self.objects[i as usize].hit(ray, &(range.start..closest), record) {
+                hit_anything = true;
+                closest = record.t;
+            }
+        }
+
+        if !self.levels.is_empty() {
+            hit_anything |= self.hit_level(0, ray, range.start, &mut closest, record);
+        }
+
+        hit_anything
+    }
+
+    fn bounding_box(&self) -> &AABB {
+        &self.aabb
+    }
+
+    fn materials<'a>(&'a self, materials: &mut Vec<&'a dyn Material>) {
+        for object in self.objects.iter() {
+            object.materials(materials);
+        }
+    }
+
+    fn has_random_hits(&self) -> bool {
+        self.random_hits
+    }
+
+    fn accelerator_memory(&self) -> usize {
+        mem::size_of::<Self>()
+            + self.objects.len() * mem::size_of::<Arc<dyn HittableObject>>()
+            + (self.loose.len() + self.indices.len()) * mem::size_of::<u32>()
+            + self
+                .levels
+                .iter()
+                .map(|level| mem::size_of::<Level>() + level.cells.len() * mem::size_of::<Cell>())
+                .sum::<usize>()
+            + self
+                .objects
+                .iter()
+                .map(|object| object.accelerator_memory())
+                .sum::<usize>()
+    }
+}
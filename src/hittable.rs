@@ -2,38 +2,51 @@
 
 use crate::aabb::AABB;
 use crate::material::{DebugMaterial, Material};
-use crate::ray::Ray;
-use crate::vector;
-use crate::vector::{Vec2, Vec3};
-use glm;
+use crate::packet::{self, RayPacket};
+use crate::ray::{self, Ray, MIN_HIT_DISTANCE};
+use crate::stats::{self, Counter};
+use crate::vector::{Float, Normal3, Point3, Vec2, Vec3};
 use std::{ops::Range, sync::Arc};
 
-#[derive(Clone)]
-pub struct HitRecord {
+/// Where a ray hit an object. The material is borrowed from the object,
+/// so records are cheap to make and copy while looking for the closest hit.
+#[derive(Clone, Copy)]
+pub struct HitRecord<'a> {
     pub in_ray: Ray,
-    pub point: Vec3,
-    pub normal: Vec3,
-    pub t: f64,
+    pub point: Point3,
+    /// How far `point` might be off the real surface along each axis,
+    /// from rounding
+    pub error: Vec3,
+    pub normal: Normal3,
+    pub t: Float,
     pub front_face: bool,
-    pub mat: Arc<dyn Material>,
+    pub mat: &'a dyn Material,
     pub uv: Vec2,
 }
 
-impl HitRecord {
+impl HitRecord<'_> {
     pub fn new(in_ray: &Ray) -> Self {
         Self {
             in_ray: *in_ray,
-            point: vector::zero_vec3(),
-            normal: vector::zero_vec3(),
+            point: Point3::ORIGIN,
+            error: Vec3::ZERO,
+            normal: Normal3::default(),
             t: 0.0,
             front_face: false,
-            mat: Arc::new(DebugMaterial),
+            mat: &DebugMaterial,
             uv: Vec2::new(0.0, 0.0),
         }
     }
 
-    pub fn set_normal(&mut self, ray: &Ray, outward_normal: &Vec3) {
-        self.front_face = glm::dot(ray.direction(), *outward_normal) < 0.0;
+    /// A ray leaving the hit point along `direction`, starting far enough off
+    /// the surface that it can't hit it again
+    pub fn spawn_ray(&self, direction: Vec3) -> Ray {
+        let origin = ray::offset_origin(&self.point, &self.error, &self.normal, &direction);
+        Ray::new(origin, direction, self.in_ray.time())
+    }
+
+    pub fn set_normal(&mut self, ray: &Ray, outward_normal: &Normal3) {
+        self.front_face = ray.direction().dot(*outward_normal) < 0.0;
 
         self.normal = match self.front_face {
             true => *outward_normal,
@@ -42,15 +55,100 @@ impl HitRecord {
     }
 }
 
+/// A point picked on the surface of an object
+#[derive(Clone, Copy)]
+pub struct SurfaceSample<'a> {
+    pub point: Point3,
+    /// How far `point` might be off the real surface along each axis
+    pub error: Vec3,
+    pub normal: Normal3,
+    pub uv: Vec2,
+    pub mat: &'a dyn Material,
+    /// The probability density of picking this point, per unit area
+    pub pdf: Float,
+}
+
 pub trait HittableObject: Send + Sync {
-    fn hit(&self, r: &Ray, range: &Range<f64>, record: &mut HitRecord) -> bool;
+    fn hit<'a>(&'a self, r: &Ray, range: &Range<Float>, record: &mut HitRecord<'a>) -> bool;
     fn bounding_box(&self) -> &AABB;
+
+    /// Whether `ray` hits the object anywhere in `range`, for when it doesn't
+    /// matter where, like with shadow rays. Objects that can stop at the first
+    /// hit they find, rather than looking for the closest, do so.
+    fn occluded(&self, ray: &Ray, range: &Range<Float>) -> bool {
+        self.hit(ray, range, &mut HitRecord::new(ray))
+    }
+
+    /// Pick a random point on the surface, as the object is at `time`.
+    /// Only objects that can be sampled as lights need to support this.
+    fn sample_surface(&self, _time: Float) -> Option<SurfaceSample<'_>> {
+        None
+    }
+
+    /// The area `sample_surface` picks points from
+    fn surface_area(&self) -> Float {
+        0.0
+    }
+
+    /// The probability density of `sample_surface` picking the point
+    /// a ray from `origin` along `direction` would hit,
+    /// per unit solid angle as seen from `origin`.
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3, time: Float) -> Float {
+        let area = self.surface_area();
+
+        if area <= 0.0 {
+            return 0.0;
+        }
+
+        let ray = Ray::new(*origin, *direction, time);
+        let mut record = HitRecord::new(&ray);
+
+        if !self.hit(&ray, &(MIN_HIT_DISTANCE..Float::INFINITY), &mut record) {
+            return 0.0;
+        }
+
+        let length = direction.length();
+        let distance = record.t * length;
+        let cosine = Float::abs(direction.dot(record.normal)) / length;
+
+        distance * distance / (cosine * area)
+    }
+
+    /// Whether hitting the object takes random numbers, like scattering
+    /// inside a volume does. Rays that might hit such objects get traced
+    /// on their own rather than in packets, see `packet`.
+    fn has_random_hits(&self) -> bool {
+        false
+    }
+
+    /// Find where every ray in `packet` first hits the object, keeping only
+    /// hits closer than the ones already in `records`. Objects that can trace
+    /// a packet faster than one ray at a time, like BVHs, do so.
+    fn hit_packet<'a>(&'a self, packet: &RayPacket, records: &mut [Option<HitRecord<'a>>]) {
+        packet::hit_separately(self, packet, records);
+    }
+
+    /// Add the bounding boxes of the BVH nodes `depth` levels below
+    /// the top of every BVH in this object to `boxes`
+    fn bvh_boxes(&self, _depth: usize, _boxes: &mut Vec<AABB>) {}
+
+    /// Add the material of every object in this one to `materials`,
+    /// in the same order every time the same scene gets built
+    fn materials<'a>(&'a self, _materials: &mut Vec<&'a dyn Material>) {}
+
+    /// How many bytes the acceleration structures in this object take up,
+    /// not counting the objects in them. BVHs that instances share aren't
+    /// counted, since they don't belong to any one instance.
+    fn accelerator_memory(&self) -> usize {
+        0
+    }
 }
 
 #[derive(Default)]
 pub struct HittableList {
     objects: Vec<Arc<dyn HittableObject>>,
     aabb: AABB,
+    random_hits: bool,
 }
 
 impl HittableList {
@@ -59,8 +157,14 @@ impl HittableList {
         // the borrow checker happy.
         let object_aabb = object.bounding_box().clone();
 
+        // The empty list's box is just the origin, which shouldn't count
+        self.aabb = match self.objects.is_empty() {
+            true => object_aabb,
+            false => AABB::combine_bounds(&self.aabb, &object_aabb),
+        };
+
+        self.random_hits |= object.has_random_hits();
         self.objects.push(object);
-        self.aabb = AABB::combine_bounds(&self.aabb, &object_aabb);
     }
 
     pub fn objects(&self) -> Vec<Arc<dyn HittableObject>> {
@@ -69,27 +173,72 @@ impl HittableList {
 }
 
 impl HittableObject for HittableList {
-    fn hit(&self, ray: &Ray, range: &Range<f64>, record: &mut HitRecord) -> bool {
-        let mut temp_record = HitRecord::new(ray);
+    fn hit<'a>(&'a self, ray: &Ray, range: &Range<Float>, record: &mut HitRecord<'a>) -> bool {
+        stats::count(Counter::HittableListTests);
+
         let mut hit_anything = false;
         let mut range = range.clone();
 
+        // Objects only fill in the record when they're hit,
+        // so it always holds the closest hit so far
         for object in self.objects.iter() {
-            if object.hit(ray, &range, &mut temp_record) {
+            if object.hit(ray, &range, record) {
                 hit_anything = true;
-                range.end = temp_record.t;
-
-                // TODO: could this be moved out of the loop?
-                *record = temp_record.clone();
+                range.end = record.t;
             }
         }
 
         hit_anything
     }
 
+    fn occluded(&self, ray: &Ray, range: &Range<Float>) -> bool {
+        stats::count(Counter::HittableListTests);
+
+        self.objects
+            .iter()
+            .any(|object| object.occluded(ray, range))
+    }
+
+    fn hit_packet<'a>(&'a self, packet: &RayPacket, records: &mut [Option<HitRecord<'a>>]) {
+        stats::add(Counter::HittableListTests, packet.len() as u64);
+
+        for object in self.objects.iter() {
+            object.hit_packet(packet, records);
+        }
+    }
+
     fn bounding_box(&self) -> &AABB {
         &self.aabb
     }
+
+    fn materials<'a>(&'a self, materials: &mut Vec<&'a dyn Material>) {
+        for object in self.objects.iter() {
+            object.materials(materials);
+        }
+    }
+
+    fn has_random_hits(&self) -> bool {
+        self.random_hits
+    }
+
+    fn accelerator_memory(&self) -> usize {
+        self.objects
+            .iter()
+            .map(|object| object.accelerator_memory())
+            .sum()
+    }
+
+    fn bvh_boxes(&self, depth: usize, boxes: &mut Vec<AABB>) {
+        for object in self.objects.iter() {
+            object.bvh_boxes(depth, boxes);
+        }
+    }
+}
+
+/// How far a point that was up to `error` off might be off
+/// once it's been moved to `point`
+fn translated_error(point: &Point3, error: &Vec3) -> Vec3 {
+    *error * (1.0 + ray::gamma(1)) + point.abs() * ray::gamma(1)
 }
 
 pub struct Translate {
@@ -107,72 +256,118 @@ impl Translate {
             aabb,
         }
     }
+
+    /// `ray` as the object sees it, before it gets moved
+    fn offset_ray(&self, ray: &Ray) -> Ray {
+        Ray::new(ray.origin() - self.offset, ray.direction(), ray.time())
+    }
 }
 
 impl HittableObject for Translate {
-    fn hit(&self, ray: &Ray, range: &Range<f64>, record: &mut HitRecord) -> bool {
-        let offset_ray = Ray::new(ray.origin() - self.offset, ray.direction(), ray.time());
+    fn hit<'a>(&'a self, ray: &Ray, range: &Range<Float>, record: &mut HitRecord<'a>) -> bool {
+        stats::count(Counter::TranslateTests);
 
-        if !self.object.hit(&offset_ray, range, record) {
+        if !self.object.hit(&self.offset_ray(ray), range, record) {
             return false;
         }
 
-        record.point = record.point + self.offset;
+        record.point += self.offset;
+        record.error = translated_error(&record.point, &record.error);
 
         true
     }
 
+    fn occluded(&self, ray: &Ray, range: &Range<Float>) -> bool {
+        stats::count(Counter::TranslateTests);
+
+        self.object.occluded(&self.offset_ray(ray), range)
+    }
+
     fn bounding_box(&self) -> &AABB {
         &self.aabb
     }
+
+    fn materials<'a>(&'a self, materials: &mut Vec<&'a dyn Material>) {
+        self.object.materials(materials);
+    }
+
+    fn sample_surface(&self, time: Float) -> Option<SurfaceSample<'_>> {
+        let mut sample = self.object.sample_surface(time)?;
+        sample.point += self.offset;
+        sample.error = translated_error(&sample.point, &sample.error);
+
+        Some(sample)
+    }
+
+    fn surface_area(&self) -> Float {
+        self.object.surface_area()
+    }
+
+    fn has_random_hits(&self) -> bool {
+        self.object.has_random_hits()
+    }
+
+    fn accelerator_memory(&self) -> usize {
+        self.object.accelerator_memory()
+    }
+
+    fn bvh_boxes(&self, depth: usize, boxes: &mut Vec<AABB>) {
+        let first = boxes.len();
+        self.object.bvh_boxes(depth, boxes);
+
+        for aabb in boxes[first..].iter_mut() {
+            *aabb = aabb.clone() + &self.offset;
+        }
+    }
 }
 
 pub struct RotateY {
     object: Arc<dyn HittableObject>,
-    sin_theta: f64,
-    cos_theta: f64,
+    sin_theta: Float,
+    cos_theta: Float,
     aabb: AABB,
 }
 
 impl RotateY {
-    pub fn new(object: Arc<dyn HittableObject>, angle: f64) -> Self {
+    pub fn new(object: Arc<dyn HittableObject>, angle: Float) -> Self {
         let radians = angle.to_radians();
-        let sin_theta = radians.sin();
-        let cos_theta = radians.cos();
-        let aabb = object.bounding_box().clone();
 
-        let mut min = Vec3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
-        let mut max = Vec3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
+        let mut rotated = Self {
+            object,
+            sin_theta: radians.sin(),
+            cos_theta: radians.cos(),
+            aabb: AABB::default(),
+        };
+
+        rotated.aabb = rotated.bounds(rotated.object.bounding_box());
+        rotated
+    }
+
+    /// A box around `aabb` once it's rotated
+    fn bounds(&self, aabb: &AABB) -> AABB {
+        let mut min = Point3::splat(Float::INFINITY);
+        let mut max = Point3::splat(Float::NEG_INFINITY);
 
         for i in 0..2 {
             for j in 0..2 {
                 for k in 0..2 {
-                    let x = i as f64 * aabb.x.end + (1 - i) as f64 * aabb.x.start;
-                    let y = j as f64 * aabb.y.end + (1 - j) as f64 * aabb.y.start;
-                    let z = k as f64 * aabb.z.end + (1 - k) as f64 * aabb.z.start;
+                    let x = i as Float * aabb.x.end + (1 - i) as Float * aabb.x.start;
+                    let y = j as Float * aabb.y.end + (1 - j) as Float * aabb.y.start;
+                    let z = k as Float * aabb.z.end + (1 - k) as Float * aabb.z.start;
 
-                    let new_x = cos_theta * x + sin_theta * z;
-                    let new_z = -sin_theta * x + cos_theta * z;
+                    let corner = self.rotate(Vec3::new(x, y, z)).to_point3();
 
-                    let test_vec = Vec3::new(new_x, y, new_z);
-
-                    min = vector::min_vec3(&min, &test_vec);
-                    max = vector::max_vec3(&max, &test_vec);
+                    min = min.min(corner);
+                    max = max.max(corner);
                 }
             }
         }
 
-        Self {
-            object,
-            sin_theta,
-            cos_theta,
-            aabb: AABB::from_points(&min, &max),
-        }
+        AABB::from_points(&min, &max)
     }
-}
 
-impl HittableObject for RotateY {
-    fn hit(&self, ray: &Ray, range: &Range<f64>, record: &mut HitRecord) -> bool {
+    /// `ray` as the object sees it, before it gets rotated
+    fn rotated_ray(&self, ray: &Ray) -> Ray {
         let mut origin = ray.origin();
         let mut direction = ray.direction();
 
@@ -182,26 +377,89 @@ impl HittableObject for RotateY {
         direction.x = self.cos_theta * ray.direction().x - self.sin_theta * ray.direction().z;
         direction.z = self.sin_theta * ray.direction().x + self.cos_theta * ray.direction().z;
 
-        let rotated_ray = Ray::new(origin, direction, ray.time());
+        Ray::new(origin, direction, ray.time())
+    }
 
-        if !self.object.hit(&rotated_ray, range, record) {
+    /// Where `v` ends up once the object is rotated
+    fn rotate(&self, v: Vec3) -> Vec3 {
+        Vec3::new(
+            self.cos_theta * v.x + self.sin_theta * v.z,
+            v.y,
+            -self.sin_theta * v.x + self.cos_theta * v.z,
+        )
+    }
+
+    /// How far `point` might be off once rotated, if it was up to `error` off
+    fn rotate_error(&self, point: &Point3, error: &Vec3) -> Vec3 {
+        let (sin, cos) = (self.sin_theta.abs(), self.cos_theta.abs());
+        let (point, error) = (point.abs(), *error * (1.0 + ray::gamma(3)));
+
+        Vec3::new(
+            cos * error.x + sin * error.z + (cos * point.x + sin * point.z) * ray::gamma(3),
+            error.y,
+            sin * error.x + cos * error.z + (sin * point.x + cos * point.z) * ray::gamma(3),
+        )
+    }
+}
+
+impl HittableObject for RotateY {
+    fn hit<'a>(&'a self, ray: &Ray, range: &Range<Float>, record: &mut HitRecord<'a>) -> bool {
+        stats::count(Counter::RotateYTests);
+
+        if !self.object.hit(&self.rotated_ray(ray), range, record) {
             return false;
         }
 
-        let mut point = record.point;
-        point.x = self.cos_theta * record.point.x + self.sin_theta * record.point.z;
-        point.z = -self.sin_theta * record.point.x + self.cos_theta * record.point.z;
-        record.point = point;
-
-        let mut normal = record.normal;
-        normal.x = self.cos_theta * record.normal.x + self.sin_theta * record.normal.z;
-        normal.z = -self.sin_theta * record.normal.x + self.cos_theta * record.normal.z;
-        record.normal = normal;
+        record.error = self.rotate_error(&record.point, &record.error);
+        record.point = self.rotate(record.point.to_vec3()).to_point3();
+        record.normal = self.rotate(record.normal.to_vec3()).into();
 
         true
     }
 
+    fn occluded(&self, ray: &Ray, range: &Range<Float>) -> bool {
+        stats::count(Counter::RotateYTests);
+
+        self.object.occluded(&self.rotated_ray(ray), range)
+    }
+
     fn bounding_box(&self) -> &AABB {
         &self.aabb
     }
+
+    fn materials<'a>(&'a self, materials: &mut Vec<&'a dyn Material>) {
+        self.object.materials(materials);
+    }
+
+    fn sample_surface(&self, time: Float) -> Option<SurfaceSample<'_>> {
+        let sample = self.object.sample_surface(time)?;
+
+        Some(SurfaceSample {
+            point: self.rotate(sample.point.to_vec3()).to_point3(),
+            error: self.rotate_error(&sample.point, &sample.error),
+            normal: self.rotate(sample.normal.to_vec3()).into(),
+            ..sample
+        })
+    }
+
+    fn surface_area(&self) -> Float {
+        self.object.surface_area()
+    }
+
+    fn has_random_hits(&self) -> bool {
+        self.object.has_random_hits()
+    }
+
+    fn accelerator_memory(&self) -> usize {
+        self.object.accelerator_memory()
+    }
+
+    fn bvh_boxes(&self, depth: usize, boxes: &mut Vec<AABB>) {
+        let first = boxes.len();
+        self.object.bvh_boxes(depth, boxes);
+
+        for aabb in boxes[first..].iter_mut() {
+            *aabb = self.bounds(aabb);
+        }
+    }
 }
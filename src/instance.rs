@@ -0,0 +1,192 @@
+//! Copies of one BVH placed all over a scene
+//!
+//! Wrapping the same objects in `Translate` and `RotateY` over and over
+//! shares the objects, but every copy still goes into the scene's BVH
+//! separately. An `Instance` instead points at a whole bottom-level BVH
+//! and says where to put it, so a top-level BVH over thousands of instances
+//! only needs one node per instance, and the shared BVH is only built and
+//! stored once.
+
+use crate::aabb::AABB;
+use crate::bvh::BVHNode;
+use crate::hittable::{HitRecord, HittableObject};
+use crate::material::Material;
+use crate::ray::{self, Ray};
+use crate::stats::{self, Counter};
+use crate::vector::{Float, Mat4, Normal3, Point3, Vec3};
+
+use std::{ops::Range, sync::Arc};
+
+/// Scales evenly, rotates, and then moves things. Keeps the inverse
+/// alongside, built up one step at a time, so it never needs working out.
+#[derive(Debug, Clone)]
+pub struct Transform {
+    matrix: Mat4,
+    inverse: Mat4,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+impl Transform {
+    /// The transform that leaves everything where it is
+    pub fn identity() -> Self {
+        Self {
+            matrix: Mat4::IDENTITY,
+            inverse: Mat4::IDENTITY,
+        }
+    }
+
+    /// Follow this transform by a rotation of `angle` degrees around `axis`,
+    /// counterclockwise when looking down the axis at the origin
+    pub fn rotate(self, axis: Vec3, angle: Float) -> Self {
+        Self {
+            matrix: Mat4::rotation(axis, angle) * self.matrix,
+            inverse: self.inverse * Mat4::rotation(axis, -angle),
+        }
+    }
+
+    /// Follow this transform by scaling everything by `factor`, away from the origin
+    pub fn scale(self, factor: Float) -> Self {
+        assert!(
+            factor > 0.0,
+            "instances can only be scaled by positive factors"
+        );
+
+        Self {
+            matrix: Mat4::scaling(Vec3::splat(factor)) * self.matrix,
+            inverse: self.inverse * Mat4::scaling(Vec3::splat(factor.recip())),
+        }
+    }
+
+    /// Follow this transform by moving everything by `offset`
+    pub fn translate(self, offset: Vec3) -> Self {
+        Self {
+            matrix: Mat4::translation(offset) * self.matrix,
+            inverse: self.inverse * Mat4::translation(-offset),
+        }
+    }
+
+    /// Where a point in the instanced object ends up
+    pub fn point(&self, point: Point3) -> Point3 {
+        self.matrix.transform_point(point)
+    }
+
+    /// Which way a surface in the instanced object faces once transformed
+    pub fn normal(&self, normal: Normal3) -> Normal3 {
+        self.inverse.transform_normal(normal).normalize()
+    }
+
+    /// How far `point` in the instanced object might be off once transformed,
+    /// if it was up to `error` off to begin with
+    pub fn point_error(&self, point: Point3, error: Vec3) -> Vec3 {
+        let abs = self.matrix.abs();
+
+        abs.transform_vector(error * (1.0 + ray::gamma(3)))
+            + abs.transform_point(point.abs().to_point3()).to_vec3() * ray::gamma(3)
+    }
+
+    /// `ray` as seen from inside the instanced object
+    pub fn inverse_ray(&self, ray: &Ray) -> Ray {
+        Ray::new(
+            self.inverse.transform_point(ray.origin()),
+            self.inverse.transform_vector(ray.direction()),
+            ray.time(),
+        )
+    }
+
+    /// The box around everything inside `aabb`, once transformed
+    pub fn bounds(&self, aabb: &AABB) -> AABB {
+        let mut min = Point3::splat(Float::INFINITY);
+        let mut max = Point3::splat(Float::NEG_INFINITY);
+
+        for i in 0..2 {
+            for j in 0..2 {
+                for k in 0..2 {
+                    let x = i as Float * aabb.x.end + (1 - i) as Float * aabb.x.start;
+                    let y = j as Float * aabb.y.end + (1 - j) as Float * aabb.y.start;
+                    let z = k as Float * aabb.z.end + (1 - k) as Float * aabb.z.start;
+
+                    let corner = self.point(Point3::new(x, y, z));
+
+                    min = min.min(corner);
+                    max = max.max(corner);
+                }
+            }
+        }
+
+        AABB::from_points(&min, &max)
+    }
+}
+
+/// A copy of a shared BVH, placed in the scene by a transform
+pub struct Instance {
+    object: Arc<BVHNode>,
+    transform: Transform,
+    aabb: AABB,
+}
+
+impl Instance {
+    pub fn new(object: Arc<BVHNode>, transform: Transform) -> Self {
+        let aabb = transform.bounds(object.bounding_box());
+
+        Self {
+            object,
+            transform,
+            aabb,
+        }
+    }
+
+    /// `ray` as the shared object sees it. The direction gets scaled along
+    /// with the origin, so distances along the ray stay the same.
+    fn local_ray(&self, ray: &Ray) -> Ray {
+        self.transform.inverse_ray(ray)
+    }
+}
+
+impl HittableObject for Instance {
+    fn hit<'a>(&'a self, ray: &Ray, range: &Range<Float>, record: &mut HitRecord<'a>) -> bool {
+        stats::count(Counter::InstanceTests);
+
+        if !self.object.hit(&self.local_ray(ray), range, record) {
+            return false;
+        }
+
+        record.in_ray = *ray;
+        record.error = self.transform.point_error(record.point, record.error);
+        record.point = self.transform.point(record.point);
+        record.normal = self.transform.normal(record.normal);
+
+        true
+    }
+
+    fn occluded(&self, ray: &Ray, range: &Range<Float>) -> bool {
+        stats::count(Counter::InstanceTests);
+
+        self.object.occluded(&self.local_ray(ray), range)
+    }
+
+    fn bounding_box(&self) -> &AABB {
+        &self.aabb
+    }
+
+    fn materials<'a>(&'a self, materials: &mut Vec<&'a dyn Material>) {
+        self.object.materials(materials);
+    }
+
+    fn has_random_hits(&self) -> bool {
+        self.object.has_random_hits()
+    }
+
+    fn bvh_boxes(&self, depth: usize, boxes: &mut Vec<AABB>) {
+        let first = boxes.len();
+        self.object.bvh_boxes(depth, boxes);
+
+        for aabb in boxes[first..].iter_mut() {
+            *aabb = self.transform.bounds(aabb);
+        }
+    }
+}
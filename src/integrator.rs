@@ -0,0 +1,776 @@
+//! Ways of working out how much light reaches the camera along a ray
+//!
+//! The camera decides which rays to trace through each pixel,
+//! and an integrator decides what those rays see.
+
+use crate::aabb::AABB;
+use crate::bdpt::Bidirectional;
+use crate::hittable::{HitRecord, HittableObject};
+use crate::material::Material;
+use crate::photon_map::PhotonMapping;
+use crate::random;
+use crate::ray::{self, Ray, MIN_HIT_DISTANCE};
+use crate::scene::Scene;
+use crate::spectral::SpectralPathTracer;
+use crate::stats::{self, Counter};
+use crate::vector;
+use crate::vector::{consts::PI, Color, Float, Normal3, Point3, Vec3};
+
+use rand::Rng;
+use std::{collections::HashMap, fmt, ops::Range, str::FromStr, sync::Arc};
+
+/// How far short of a sampled light point a shadow ray stops, relative to
+/// its length, so the light itself doesn't count as blocking it
+/// if rounding leaves the end of the ray behind it
+const SHADOW_EPSILON: Float = 1e-6;
+
+/// Everything an integrator gets to see of the scene it's rendering
+#[derive(Clone, Copy)]
+pub struct SceneView<'a> {
+    pub world: &'a dyn HittableObject,
+    pub lights: &'a [Arc<dyn HittableObject>],
+    pub background_color: Color,
+    /// The most rays a single path may be made of
+    pub max_depth: usize,
+}
+
+/// Light that reached a point from a randomly picked point on a light
+#[derive(Debug, Copy, Clone)]
+pub struct LightSample {
+    /// From the shaded point to the point on the light
+    pub direction: Vec3,
+    pub emission: Color,
+    /// The probability density of picking this direction, per unit solid angle
+    pub pdf: Float,
+}
+
+/// A ray leaving a randomly picked point on one of the lights
+#[derive(Debug, Copy, Clone)]
+pub struct EmittedRay {
+    pub ray: Ray,
+    /// The light's normal where the ray leaves it
+    pub normal: Normal3,
+    pub emission: Color,
+    /// The probability density of picking the ray's origin, per unit area
+    pub point_pdf: Float,
+    /// The probability density of picking the ray's direction, per unit solid angle
+    pub direction_pdf: Float,
+}
+
+impl<'a> SceneView<'a> {
+    /// The closest thing `ray` hits, if anything
+    pub fn hit(&self, ray: &Ray) -> Option<HitRecord<'a>> {
+        let mut record = HitRecord::new(ray);
+
+        match self
+            .world
+            .hit(ray, &(MIN_HIT_DISTANCE..Float::INFINITY), &mut record)
+        {
+            true => Some(record),
+            false => None,
+        }
+    }
+
+    /// Pick a point on one of the lights and check whether
+    /// it can be seen from where `record` hit
+    pub fn sample_light(&self, record: &HitRecord) -> Option<LightSample> {
+        if self.lights.is_empty() {
+            return None;
+        }
+
+        let light = &self.lights[random::rng().gen_range(0..self.lights.len())];
+        let sample = light.sample_surface(record.in_ray.time())?;
+
+        let direction = sample.point - record.point;
+        let distance = direction.length();
+        let cosine = Float::abs(direction.dot(sample.normal)) / distance;
+
+        if distance <= 0.0 || cosine < Float::EPSILON {
+            return None;
+        }
+
+        stats::count(Counter::ShadowRays);
+
+        // Both ends start off their surfaces, and only what's in front of
+        // the light can block it
+        let origin = ray::offset_origin(&record.point, &record.error, &record.normal, &direction);
+        let target = ray::offset_origin(&sample.point, &sample.error, &sample.normal, &-direction);
+        let shadow_ray = Ray::new(origin, target - origin, record.in_ray.time());
+
+        if self
+            .world
+            .occluded(&shadow_ray, &(MIN_HIT_DISTANCE..1.0 - SHADOW_EPSILON))
+        {
+            return None;
+        }
+
+        Some(LightSample {
+            direction,
+            emission: sample.mat.emitted(&sample.uv, &sample.point),
+            pdf: sample.pdf * distance * distance / cosine / self.lights.len() as Float,
+        })
+    }
+
+    /// The probability density of `sample_light` picking `direction`
+    /// from `origin`, per unit solid angle
+    pub fn light_pdf(&self, origin: &Point3, direction: &Vec3, time: Float) -> Float {
+        if self.lights.is_empty() {
+            return 0.0;
+        }
+
+        let total: Float = self
+            .lights
+            .iter()
+            .map(|light| light.pdf_value(origin, direction, time))
+            .sum();
+
+        total / self.lights.len() as Float
+    }
+
+    /// Pick a point on one of the lights and a direction for light to leave it in
+    pub fn emit_light_ray(&self, time: Float) -> Option<EmittedRay> {
+        if self.lights.is_empty() {
+            return None;
+        }
+
+        let light = &self.lights[random::rng().gen_range(0..self.lights.len())];
+        let sample = light.sample_surface(time)?;
+
+        // Pick a side of the light, then a cosine weighted direction on it
+        let side = match random::rng().gen::<bool>() {
+            true => sample.normal,
+            false => -sample.normal,
+        };
+
+        let direction = (side + vector::random_unit_sphere_vec()).normalize();
+        let direction_pdf = light_emission_pdf(&sample.normal, &direction);
+
+        if direction_pdf <= 0.0 {
+            return None;
+        }
+
+        Some(EmittedRay {
+            ray: Ray::new(
+                ray::offset_origin(&sample.point, &sample.error, &sample.normal, &direction),
+                direction,
+                time,
+            ),
+            normal: sample.normal,
+            emission: sample.mat.emitted(&sample.uv, &sample.point),
+            point_pdf: sample.pdf / self.lights.len() as Float,
+            direction_pdf,
+        })
+    }
+}
+
+/// The probability density, per unit solid angle, of `SceneView::emit_light_ray`
+/// picking `direction` for a point with the given `normal`.
+/// Lights shine from both sides, with a cosine falloff on each.
+pub fn light_emission_pdf(normal: &Normal3, direction: &Vec3) -> Float {
+    Float::abs(normal.dot(direction.normalize())) / (2.0 * PI)
+}
+
+/// How much two ways of sampling the same path should each count towards it
+pub fn power_heuristic(pdf: Float, other_pdf: Float) -> Float {
+    let pdf = pdf * pdf;
+    let other_pdf = other_pdf * other_pdf;
+
+    match pdf + other_pdf > 0.0 {
+        true => pdf / (pdf + other_pdf),
+        false => 0.0,
+    }
+}
+
+pub trait Integrator: Send + Sync {
+    /// Called before the camera takes samples number `samples` of any pixel,
+    /// for integrators that have work to do for the whole image first
+    fn prepare(&self, _scene: &SceneView, _seed: u64, _samples: Range<u32>) {}
+
+    /// Whether the integrator reads the statistics counters,
+    /// which only count while something turns counting on
+    fn needs_stats(&self) -> bool {
+        false
+    }
+
+    /// The light arriving at the camera along `ray`,
+    /// for sample number `sample` of its pixel
+    fn radiance(&self, ray: &Ray, scene: &SceneView, sample: u32) -> Color;
+}
+
+/// Follows a single scattered ray at every bounce until it finds a light,
+/// escapes the scene or runs out of bounces
+#[derive(Debug, Copy, Clone, Default)]
+pub struct PathTracer;
+
+impl PathTracer {
+    fn ray_color(&self, ray: &Ray, scene: &SceneView, depth: usize) -> Color {
+        if depth == 0 {
+            return Vec3::ZERO;
+        }
+
+        let Some(mut record) = scene.hit(ray) else {
+            return scene.background_color;
+            // Sky background
+            // let unit_direction = r.direction().normalize();
+            // let a = (unit_direction.y + 1.0) * 0.5;
+            // Vec3::ONE * (1.0 - a) + Color::new(0.5, 0.7, 1.0) * a
+        };
+
+        let emission = record.mat.emitted(&record.uv, &record.point);
+
+        let mut scattered = Ray::new(Point3::ORIGIN, Vec3::ZERO, ray.time());
+        let mut attenuation = Vec3::ZERO;
+        let mat = record.mat;
+
+        if !mat.scatter(&mut record, &mut attenuation, &mut scattered) {
+            return emission;
+        }
+
+        stats::count(Counter::SecondaryRays);
+        let scatter = self.ray_color(&scattered, scene, depth - 1) * attenuation;
+
+        emission + scatter
+    }
+}
+
+impl Integrator for PathTracer {
+    fn radiance(&self, ray: &Ray, scene: &SceneView, _sample: u32) -> Color {
+        self.ray_color(ray, scene, scene.max_depth)
+    }
+}
+
+/// A path tracer that also aims a shadow ray at a light from every
+/// diffuse surface it hits, and combines both ways of finding light
+/// with multiple importance sampling.
+/// Converges much faster than `PathTracer` in scenes lit by small lights.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct NextEventEstimation;
+
+impl Integrator for NextEventEstimation {
+    fn radiance(&self, ray: &Ray, scene: &SceneView, _sample: u32) -> Color {
+        let mut color = Vec3::ZERO;
+        let mut throughput = Vec3::ONE;
+        let mut ray = *ray;
+
+        // How likely the last bounce was to pick the current ray,
+        // or `None` if sampling a light could never have found it
+        let mut scattering_pdf: Option<Float> = None;
+
+        // The rays we may still trace, counting the current one
+        for depth in (1..=scene.max_depth).rev() {
+            let Some(mut record) = scene.hit(&ray) else {
+                color += throughput * scene.background_color;
+                break;
+            };
+
+            let emission = record.mat.emitted(&record.uv, &record.point);
+
+            if !emission.is_near_zero() {
+                let weight = match scattering_pdf {
+                    Some(pdf) => power_heuristic(
+                        pdf,
+                        scene.light_pdf(&ray.origin(), &ray.direction(), ray.time()),
+                    ),
+                    None => 1.0,
+                };
+
+                color += throughput * emission * weight;
+            }
+
+            let mat = record.mat;
+
+            // Only aim for a light if the path is allowed one more ray
+            if depth > 1 && !mat.is_specular() {
+                if let Some(light) = scene.sample_light(&record) {
+                    let scattering = mat.scattering(&record, &light.direction);
+                    let weight =
+                        power_heuristic(light.pdf, mat.scattering_pdf(&record, &light.direction));
+
+                    color += throughput * scattering * light.emission * (weight / light.pdf);
+                }
+            }
+
+            let mut scattered = Ray::new(Point3::ORIGIN, Vec3::ZERO, ray.time());
+            let mut attenuation = Vec3::ZERO;
+
+            if !mat.scatter(&mut record, &mut attenuation, &mut scattered) {
+                break;
+            }
+
+            scattering_pdf = match mat.is_specular() {
+                true => None,
+                false => Some(mat.scattering_pdf(&record, &scattered.direction())),
+            };
+
+            throughput *= attenuation;
+            stats::count(Counter::SecondaryRays);
+            ray = scattered;
+        }
+
+        color
+    }
+}
+
+/// Direct light on diffuse surfaces, and perfect-ish reflections and
+/// refractions through specular ones, but no light bouncing between
+/// diffuse surfaces. Quick to converge, but nowhere near physically correct.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Whitted;
+
+impl Whitted {
+    fn ray_color(&self, ray: &Ray, scene: &SceneView, depth: usize) -> Color {
+        if depth == 0 {
+            return Vec3::ZERO;
+        }
+
+        let Some(mut record) = scene.hit(ray) else {
+            return scene.background_color;
+        };
+
+        let emission = record.mat.emitted(&record.uv, &record.point);
+        let mat = record.mat;
+
+        let mut scattered = Ray::new(Point3::ORIGIN, Vec3::ZERO, ray.time());
+        let mut attenuation = Vec3::ZERO;
+
+        if mat.is_specular() {
+            if !mat.scatter(&mut record, &mut attenuation, &mut scattered) {
+                return emission;
+            }
+
+            stats::count(Counter::SecondaryRays);
+            return emission + self.ray_color(&scattered, scene, depth - 1) * attenuation;
+        }
+
+        if depth == 1 {
+            return emission;
+        }
+
+        let mut direct = Vec3::ZERO;
+
+        if let Some(light) = scene.sample_light(&record) {
+            direct = mat.scattering(&record, &light.direction) * light.emission / light.pdf;
+        }
+
+        // The background lights diffuse surfaces too,
+        // so let one scattered ray find out if it can see it
+        if mat.scatter(&mut record, &mut attenuation, &mut scattered) {
+            stats::count(Counter::SecondaryRays);
+
+            if scene.hit(&scattered).is_none() {
+                direct += attenuation * scene.background_color;
+            }
+        }
+
+        emission + direct
+    }
+}
+
+impl Integrator for Whitted {
+    fn radiance(&self, ray: &Ray, scene: &SceneView, _sample: u32) -> Color {
+        self.ray_color(ray, scene, scene.max_depth)
+    }
+}
+
+/// White where nothing nearby blocks the view of the sky,
+/// darker in creases and corners
+#[derive(Debug, Copy, Clone)]
+pub struct AmbientOcclusion {
+    /// How far away something can be and still block the sky
+    pub distance: Float,
+}
+
+impl Integrator for AmbientOcclusion {
+    fn radiance(&self, ray: &Ray, scene: &SceneView, _sample: u32) -> Color {
+        let Some(record) = scene.hit(ray) else {
+            return Vec3::ONE;
+        };
+
+        let direction = (record.normal + vector::random_unit_sphere_vec()).normalize();
+        let occlusion_ray = record.spawn_ray(direction);
+
+        stats::count(Counter::ShadowRays);
+
+        match scene
+            .world
+            .occluded(&occlusion_ray, &(MIN_HIT_DISTANCE..self.distance))
+        {
+            true => Vec3::ZERO,
+            false => Vec3::ONE,
+        }
+    }
+}
+
+/// Colors every surface by the direction it faces
+#[derive(Debug, Copy, Clone, Default)]
+pub struct NormalView;
+
+impl Integrator for NormalView {
+    fn radiance(&self, ray: &Ray, scene: &SceneView, _sample: u32) -> Color {
+        match scene.hit(ray) {
+            Some(record) => (record.normal + Vec3::ONE) * 0.5,
+            None => Vec3::ZERO,
+        }
+    }
+}
+
+/// Colors every surface by its texture coordinates,
+/// with u in the red channel and v in the green one
+#[derive(Debug, Copy, Clone, Default)]
+pub struct UvView;
+
+impl Integrator for UvView {
+    fn radiance(&self, ray: &Ray, scene: &SceneView, _sample: u32) -> Color {
+        match scene.hit(ray) {
+            Some(record) => Color::new(record.uv.x, record.uv.y, 0.0),
+            None => Vec3::ZERO,
+        }
+    }
+}
+
+/// Brighter the closer a surface is to the camera
+#[derive(Debug, Copy, Clone)]
+pub struct DepthView {
+    /// Surfaces this far away come out middle gray
+    pub reference_distance: Float,
+}
+
+impl Integrator for DepthView {
+    fn radiance(&self, ray: &Ray, scene: &SceneView, _sample: u32) -> Color {
+        let Some(record) = scene.hit(ray) else {
+            return Vec3::ZERO;
+        };
+
+        let distance = record.t * ray.direction().length();
+        let brightness = self.reference_distance / (self.reference_distance + distance);
+
+        Color::new(brightness, brightness, brightness)
+    }
+}
+
+/// Gives every material its own flat color
+#[derive(Debug, Clone, Default)]
+pub struct MaterialView {
+    /// The index of every material in the scene, by where it lives.
+    /// Materials shared between objects are the same allocation
+    pub ids: HashMap<usize, u64>,
+}
+
+impl MaterialView {
+    /// Number the materials in `world` in the order they show up in it,
+    /// so every run and every worker colors them the same way
+    pub fn new(world: &dyn HittableObject) -> Self {
+        let mut materials = Vec::new();
+        world.materials(&mut materials);
+
+        let mut ids = HashMap::new();
+        for material in materials {
+            let next = ids.len() as u64;
+            ids.entry(Self::address(material)).or_insert(next);
+        }
+
+        Self { ids }
+    }
+
+    fn address(material: &dyn Material) -> usize {
+        material as *const _ as *const () as usize
+    }
+}
+
+impl Integrator for MaterialView {
+    fn radiance(&self, ray: &Ray, scene: &SceneView, _sample: u32) -> Color {
+        let Some(record) = scene.hit(ray) else {
+            return Vec3::ZERO;
+        };
+
+        let Some(&id) = self.ids.get(&Self::address(record.mat)) else {
+            return Color::new(0.5, 0.5, 0.5);
+        };
+
+        // Spread neighbouring indices far apart, and take the well mixed high bits
+        let hash = (id + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        let channel = |shift: u32| ((hash >> shift) & 0xff) as Float / 255.0;
+
+        Color::new(channel(40), channel(48), channel(56))
+    }
+}
+
+/// Colors every pixel by how much work it took to find what the camera sees,
+/// from dark blue for almost none to red for `max_count` or more
+#[derive(Debug, Copy, Clone)]
+pub struct TraversalHeatmap {
+    /// The counters that add up to the work done
+    pub counters: &'static [Counter],
+    pub max_count: u64,
+}
+
+impl TraversalHeatmap {
+    pub const BVH_NODES: &'static [Counter] = &[Counter::BvhNodesVisited];
+    pub const PRIMITIVES: &'static [Counter] = &[Counter::SphereTests, Counter::QuadTests];
+
+    fn work(&self) -> u64 {
+        self.counters.iter().map(|&c| stats::local_count(c)).sum()
+    }
+
+    /// Trace `ray` and color it by how much work that took
+    fn trace(&self, ray: &Ray, scene: &SceneView) -> Color {
+        let before = self.work();
+        scene.hit(ray);
+        let count = self.work() - before;
+
+        // Every doubling of the work counts the same, so cheap pixels still differ
+        let heat = Float::ln_1p(count as Float) / Float::ln_1p(self.max_count as Float);
+
+        heat_color(heat.clamp(0.0, 1.0))
+    }
+}
+
+/// Blue, through cyan, green and yellow, to red as `heat` goes from 0 to 1
+fn heat_color(heat: Float) -> Color {
+    const STOPS: [(Float, Float, Float); 5] = [
+        (0.0, 0.0, 0.5),
+        (0.0, 0.8, 1.0),
+        (0.0, 0.9, 0.0),
+        (1.0, 0.9, 0.0),
+        (1.0, 0.0, 0.0),
+    ];
+
+    let position = heat * (STOPS.len() - 1) as Float;
+    let index = (position as usize).min(STOPS.len() - 2);
+    let t = position - index as Float;
+
+    let (from, to) = (STOPS[index], STOPS[index + 1]);
+
+    Color::new(
+        from.0 + (to.0 - from.0) * t,
+        from.1 + (to.1 - from.1) * t,
+        from.2 + (to.2 - from.2) * t,
+    )
+}
+
+impl Integrator for TraversalHeatmap {
+    /// The heatmap is made of the counters
+    fn needs_stats(&self) -> bool {
+        true
+    }
+
+    fn radiance(&self, ray: &Ray, scene: &SceneView, _sample: u32) -> Color {
+        self.trace(ray, scene)
+    }
+}
+
+/// Draws the edges of the BVH's boxes at one depth of the tree in white,
+/// over a heatmap of the BVH nodes each pixel visited
+#[derive(Debug, Clone)]
+pub struct BvhWireframe {
+    pub boxes: Vec<AABB>,
+    /// How thick the edges are, as an angle seen from the camera
+    pub line_width: Float,
+    pub heatmap: TraversalHeatmap,
+}
+
+impl BvhWireframe {
+    /// Whether `ray` passes over an edge of `aabb`,
+    /// either where it goes into the box or where it comes out
+    fn on_edge(&self, ray: &Ray, aabb: &AABB) -> bool {
+        let origin = ray.origin();
+        let direction = ray.direction();
+
+        let mut entry = Float::NEG_INFINITY;
+        let mut exit = Float::INFINITY;
+
+        for (i, axis) in aabb.axes().enumerate() {
+            let t0 = (axis.start - origin[i]) / direction[i];
+            let t1 = (axis.end - origin[i]) / direction[i];
+
+            entry = entry.max(t0.min(t1));
+            exit = exit.min(t0.max(t1));
+        }
+
+        if exit <= entry.max(0.0) {
+            return false;
+        }
+
+        [entry, exit].into_iter().filter(|t| *t > 0.0).any(|t| {
+            let point = origin + direction * t;
+            let width = self.line_width * t * direction.length();
+
+            // Edges are where the point is on two faces at once
+            let faces = aabb
+                .axes()
+                .enumerate()
+                .filter(|(i, axis)| {
+                    Float::min((point[*i] - axis.start).abs(), (point[*i] - axis.end).abs()) < width
+                })
+                .count();
+
+            faces >= 2
+        })
+    }
+}
+
+impl Integrator for BvhWireframe {
+    fn needs_stats(&self) -> bool {
+        self.heatmap.needs_stats()
+    }
+
+    fn radiance(&self, ray: &Ray, scene: &SceneView, _sample: u32) -> Color {
+        if self.boxes.iter().any(|aabb| self.on_edge(ray, aabb)) {
+            return Vec3::ONE;
+        }
+
+        self.heatmap.trace(ray, scene) * 0.6
+    }
+}
+
+/// The integrators that can be picked by name
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum IntegratorKind {
+    #[default]
+    PathTracer,
+    NextEventEstimation,
+    AmbientOcclusion,
+    Whitted,
+    Normals,
+    Uv,
+    Depth,
+    MaterialId,
+    Bidirectional,
+    PhotonMapping,
+    Spectral,
+    BvhNodes,
+    PrimitiveTests,
+    /// The edges of the BVH nodes `depth` levels down the tree
+    BvhBoxes {
+        depth: u8,
+    },
+}
+
+impl IntegratorKind {
+    pub const ALL: [IntegratorKind; 14] = [
+        IntegratorKind::PathTracer,
+        IntegratorKind::NextEventEstimation,
+        IntegratorKind::AmbientOcclusion,
+        IntegratorKind::Whitted,
+        IntegratorKind::Normals,
+        IntegratorKind::Uv,
+        IntegratorKind::Depth,
+        IntegratorKind::MaterialId,
+        IntegratorKind::Bidirectional,
+        IntegratorKind::PhotonMapping,
+        IntegratorKind::Spectral,
+        IntegratorKind::BvhNodes,
+        IntegratorKind::PrimitiveTests,
+        IntegratorKind::BvhBoxes { depth: 3 },
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            IntegratorKind::PathTracer => "path",
+            IntegratorKind::NextEventEstimation => "nee",
+            IntegratorKind::AmbientOcclusion => "ao",
+            IntegratorKind::Whitted => "whitted",
+            IntegratorKind::Normals => "normals",
+            IntegratorKind::Uv => "uv",
+            IntegratorKind::Depth => "depth",
+            IntegratorKind::MaterialId => "material",
+            IntegratorKind::Bidirectional => "bdpt",
+            IntegratorKind::PhotonMapping => "photon",
+            IntegratorKind::Spectral => "spectral",
+            IntegratorKind::BvhNodes => "bvh-nodes",
+            IntegratorKind::PrimitiveTests => "primitive-tests",
+            IntegratorKind::BvhBoxes { .. } => "bvh-boxes",
+        }
+    }
+
+    pub fn build(&self, scene: &Scene) -> Box<dyn Integrator> {
+        let settings = &scene.camera_settings;
+
+        // Roughly how big the interesting part of the scene is
+        let scene_scale = (settings.position - settings.target_position).length();
+
+        match self {
+            IntegratorKind::PathTracer => Box::new(PathTracer),
+            IntegratorKind::NextEventEstimation => Box::new(NextEventEstimation),
+            IntegratorKind::AmbientOcclusion => Box::new(AmbientOcclusion {
+                distance: scene_scale * 0.5,
+            }),
+            IntegratorKind::Whitted => Box::new(Whitted),
+            IntegratorKind::Normals => Box::new(NormalView),
+            IntegratorKind::Uv => Box::new(UvView),
+            IntegratorKind::Depth => Box::new(DepthView {
+                reference_distance: scene_scale,
+            }),
+            IntegratorKind::MaterialId => Box::new(MaterialView::new(&*scene.world)),
+            IntegratorKind::Bidirectional => Box::new(Bidirectional),
+            IntegratorKind::PhotonMapping => {
+                Box::new(PhotonMapping::new(100_000, scene_scale * 0.01))
+            }
+            IntegratorKind::Spectral => Box::new(SpectralPathTracer),
+            IntegratorKind::BvhNodes => Box::new(TraversalHeatmap {
+                counters: TraversalHeatmap::BVH_NODES,
+                max_count: 256,
+            }),
+            IntegratorKind::PrimitiveTests => Box::new(TraversalHeatmap {
+                counters: TraversalHeatmap::PRIMITIVES,
+                max_count: 64,
+            }),
+            IntegratorKind::BvhBoxes { depth } => {
+                let mut boxes = Vec::new();
+                scene.world.bvh_boxes(*depth as usize, &mut boxes);
+
+                Box::new(BvhWireframe {
+                    boxes,
+                    line_width: 0.002,
+                    heatmap: TraversalHeatmap {
+                        counters: TraversalHeatmap::BVH_NODES,
+                        max_count: 256,
+                    },
+                })
+            }
+        }
+    }
+}
+
+/// Integrators print as the name they're picked by,
+/// followed by their setting after a colon for those that have one
+impl fmt::Display for IntegratorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IntegratorKind::BvhBoxes { depth } => write!(f, "{}:{}", self.name(), depth),
+            _ => f.write_str(self.name()),
+        }
+    }
+}
+
+impl FromStr for IntegratorKind {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let (name, setting) = match text.split_once(':') {
+            Some((name, setting)) => (name, Some(setting)),
+            None => (text, None),
+        };
+
+        let kind = Self::ALL
+            .into_iter()
+            .find(|kind| kind.name() == name)
+            .ok_or_else(|| {
+                let names: Vec<&str> = Self::ALL.iter().map(|kind| kind.name()).collect();
+                format!(
+                    "unknown integrator `{}`, use one of {}",
+                    name,
+                    names.join(", ")
+                )
+            })?;
+
+        match (kind, setting) {
+            (kind, None) => Ok(kind),
+            (IntegratorKind::BvhBoxes { .. }, Some(depth)) => match depth.parse() {
+                Ok(depth) => Ok(IntegratorKind::BvhBoxes { depth }),
+                Err(_) => Err(format!("`{}` is not a BVH depth", depth)),
+            },
+            (kind, Some(_)) => Err(format!("integrator `{}` has no settings", kind.name())),
+        }
+    }
+}
@@ -0,0 +1,399 @@
+//! SAH kd-tree
+//!
+//! Every node splits its box in two with a plane across one of the axes,
+//! and objects that straddle the plane go into both sides. Unlike a BVH's,
+//! the children's boxes never overlap, so a ray can visit the leaves it goes
+//! through strictly in order and stop at the first one it hits something in.
+//!
+//! Candidate planes are the sides of the objects' boxes, and each node is
+//! split at whichever one makes the surface area heuristic expect the least
+//! work, with a bonus for cutting off empty space. Nodes become leaves
+//! once no split is expected to pay off.
+//!
+//! Objects whose hits are random, and ones without a finite box,
+//! stay out of the tree and get tested by every ray instead.
+
+use crate::{
+    aabb::{AABB, EXIT_SCALE},
+    hittable::{HitRecord, HittableObject},
+    material::Material,
+    ray::Ray,
+    stats::{self, Counter},
+    vector::Float,
+};
+
+use std::{mem, ops::Range, sync::Arc};
+
+/// What visiting a node costs, relative to testing an object
+const TRAVERSAL_COST: Float = 0.5;
+
+/// How much cheaper a split gets for leaving one side empty
+const EMPTY_BONUS: Float = 0.5;
+
+/// How deep the tree can get, however many objects there are
+const MAX_TREE_DEPTH: usize = 48;
+
+/// Marks a node as a leaf rather than one split along an axis
+const LEAF: u8 = 3;
+
+#[derive(Debug, Clone)]
+struct KdNode {
+    /// Where along its axis a node is split
+    split: Float,
+    /// The index of the child above the split, or of a leaf's first object in
+    /// the tree's `indices`. The child below the split comes right after.
+    offset: u32,
+    /// How many objects a leaf holds
+    count: u32,
+    /// The axis the node is split along, or `LEAF`
+    axis: u8,
+}
+
+/// The side of an object's box along some axis
+#[derive(Debug, Copy, Clone)]
+struct Edge {
+    position: Float,
+    starts: bool,
+}
+
+pub struct KdTree {
+    objects: Vec<Arc<dyn HittableObject>>,
+    /// The objects that aren't in the tree, which every ray gets tested against
+    loose: Vec<u32>,
+    nodes: Vec<KdNode>,
+    /// Which objects are in each leaf
+    indices: Vec<u32>,
+    /// The box the root node splits
+    min: [Float; 3],
+    max: [Float; 3],
+    aabb: AABB,
+    random_hits: bool,
+}
+
+/// The surface area of the box from `min` to `max`
+fn area(min: &[Float; 3], max: &[Float; 3]) -> Float {
+    let size = [0, 1, 2].map(|axis| max[axis] - min[axis]);
+    2.0 * (size[0] * size[1] + size[1] * size[2] + size[2] * size[0])
+}
+
+impl KdTree {
+    pub fn new(objects: Vec<Arc<dyn HittableObject>>) -> Self {
+        let aabb = objects
+            .iter()
+            .map(|object| object.bounding_box().clone())
+            .reduce(|total, aabb| AABB::combine_bounds(&total, &aabb))
+            .unwrap_or_default();
+
+        let (loose, placed): (Vec<u32>, Vec<u32>) = (0..objects.len() as u32).partition(|&i| {
+            let object = &objects[i as usize];
+            object.has_random_hits() || !object.bounding_box().is_finite()
+        });
+
+        let boxes: Vec<([Float; 3], [Float; 3])> = objects
+            .iter()
+            .map(|object| {
+                let aabb = object.bounding_box();
+                (
+                    [aabb.x.start, aabb.y.start, aabb.z.start],
+                    [aabb.x.end, aabb.y.end, aabb.z.end],
+                )
+            })
+            .collect();
+
+        let min = [0, 1, 2].map(|axis| {
+            placed.iter().fold(Float::INFINITY, |min, &i| {
+                min.min(boxes[i as usize].0[axis])
+            })
+        });
+        let max = [0, 1, 2].map(|axis| {
+            placed.iter().fold(Float::NEG_INFINITY, |max, &i| {
+                max.max(boxes[i as usize].1[axis])
+            })
+        });
+
+        let mut tree = Self {
+            random_hits: objects.iter().any(|object| object.has_random_hits()),
+            objects,
+            loose,
+            nodes: Vec::new(),
+            indices: Vec::new(),
+            min,
+            max,
+            aabb,
+        };
+
+        if !placed.is_empty() {
+            let max_depth = (8.0 + 1.3 * (placed.len() as Float).log2()).round() as usize;
+            tree.build(&boxes, placed, min, max, max_depth.min(MAX_TREE_DEPTH));
+        }
+
+        tree
+    }
+
+    /// Add the node for the box from `min` to `max`, holding the `objects`,
+    /// and everything under it
+    fn build(
+        &mut self,
+        boxes: &[([Float; 3], [Float; 3])],
+        objects: Vec<u32>,
+        min: [Float; 3],
+        max: [Float; 3],
+        depth_left: usize,
+    ) {
+        let index = self.nodes.len();
+
+        let split = match depth_left {
+            0 => None,
+            _ => find_split(boxes, &objects, &min, &max),
+        };
+
+        let Some((axis, split)) = split else {
+            self.nodes.push(KdNode {
+                split: 0.0,
+                offset: self.indices.len() as u32,
+                count: objects.len() as u32,
+                axis: LEAF,
+            });
+            self.indices.extend_from_slice(&objects);
+            return;
+        };
+
+        self.nodes.push(KdNode {
+            split,
+            offset: 0,
+            count: 0,
+            axis: axis as u8,
+        });
+
+        let (mut below, mut above) = (Vec::new(), Vec::new());
+
+        for i in objects {
+            let (object_min, object_max) = &boxes[i as usize];
+            let goes_above = object_max[axis] > split;
+
+            if object_min[axis] < split || !goes_above {
+                below.push(i);
+            }
+
+            if goes_above {
+                above.push(i);
+            }
+        }
+
+        let mut below_max = max;
+        below_max[axis] = split;
+        self.build(boxes, below, min, below_max, depth_left - 1);
+
+        self.nodes[index].offset = self.nodes.len() as u32;
+
+        let mut above_min = min;
+        above_min[axis] = split;
+        self.build(boxes, above, above_min, max, depth_left - 1);
+    }
+}
+
+/// The axis and position of the plane that's expected to make the box from
+/// `min` to `max` cheapest to trace rays through, or `None` if leaving the
+/// `objects` in a leaf is cheapest
+fn find_split(
+    boxes: &[([Float; 3], [Float; 3])],
+    objects: &[u32],
+    min: &[Float; 3],
+    max: &[Float; 3],
+) -> Option<(usize, Float)> {
+    let count = objects.len();
+
+    if count <= 1 {
+        return None;
+    }
+
+    let inverse_area = area(min, max).recip();
+    let mut best: Option<(usize, Float)> = None;
+    let mut best_cost = count as Float;
+    let mut edges = Vec::with_capacity(count * 2);
+
+    for axis in 0..3 {
+        edges.clear();
+
+        for &i in objects {
+            let (object_min, object_max) = &boxes[i as usize];
+
+            edges.push(Edge {
+                position: object_min[axis],
+                starts: true,
+            });
+            edges.push(Edge {
+                position: object_max[axis],
+                starts: false,
+            });
+        }
+
+        // Objects that end at a plane don't count as being above it,
+        // and ones that start there don't count as being below it
+        edges.sort_unstable_by(|a, b| {
+            a.position
+                .total_cmp(&b.position)
+                .then(a.starts.cmp(&b.starts))
+        });
+
+        let (mut below, mut above) = (0, count);
+
+        for edge in &edges {
+            if !edge.starts {
+                above -= 1;
+            }
+
+            if edge.position > min[axis] && edge.position < max[axis] {
+                let mut below_max = *max;
+                below_max[axis] = edge.position;
+                let mut above_min = *min;
+                above_min[axis] = edge.position;
+
+                let bonus = match below == 0 || above == 0 {
+                    true => EMPTY_BONUS,
+                    false => 0.0,
+                };
+
+                let cost = TRAVERSAL_COST
+                    + (1.0 - bonus)
+                        * (area(min, &below_max) * below as Float
+                            + area(&above_min, max) * above as Float)
+                        * inverse_area;
+
+                if cost < best_cost {
+                    best_cost = cost;
+                    best = Some((axis, edge.position));
+                }
+            }
+
+            if edge.starts {
+                below += 1;
+            }
+        }
+    }
+
+    best
+}
+
+impl HittableObject for KdTree {
+    fn hit<'a>(&'a self, ray: &Ray, range: &Range<Float>, record: &mut HitRecord<'a>) -> bool {
+        let mut closest = range.end;
+        let mut hit_anything = false;
+
+        for &i in &self.loose {
+            if self.objects[i as usize].hit(ray, &(range.start..closest), record) {
+                hit_anything = true;
+                closest = record.t;
+            }
+        }
+
+        if self.nodes.is_empty() {
+            return hit_anything;
+        }
+
+        let origin = [ray.origin().x, ray.origin().y, ray.origin().z];
+        let direction = [ray.direction().x, ray.direction().y, ray.direction().z];
+        let inverse_direction = direction.map(Float::recip);
+
+        // Where the ray is inside the root's box
+        let mut enter = range.start;
+        let mut exit = closest;
+
+        for axis in 0..3 {
+            let t0 = (self.min[axis] - origin[axis]) * inverse_direction[axis];
+            let t1 = (self.max[axis] - origin[axis]) * inverse_direction[axis];
+
+            enter = Float::max(enter, Float::min(t0, t1));
+            exit = Float::min(exit, Float::max(t0, t1) * EXIT_SCALE);
+        }
+
+        if enter > exit {
+            return hit_anything;
+        }
+
+        // Nodes further along the ray that we still have to come back to,
+        // with where the ray goes into and out of them
+        let mut stack = [(0, 0.0, 0.0); MAX_TREE_DEPTH];
+        let mut stack_size = 0;
+        let mut current = (0, enter, exit);
+
+        loop {
+            let (index, enter, exit) = current;
+
+            // Everything left is further along than something already hit
+            if closest < enter {
+                return hit_anything;
+            }
+
+            stats::count(Counter::KdTreeNodesVisited);
+            let node = &self.nodes[index];
+
+            if node.axis != LEAF {
+                let axis = node.axis as usize;
+                let split = (node.split - origin[axis]) * inverse_direction[axis];
+
+                let below_first = origin[axis] < node.split
+                    || (origin[axis] == node.split && direction[axis] <= 0.0);
+
+                let (near, far) = match below_first {
+                    true => (index + 1, node.offset as usize),
+                    false => (node.offset as usize, index + 1),
+                };
+
+                if split > exit || split <= 0.0 {
+                    current = (near, enter, exit);
+                } else if split < enter {
+                    current = (far, enter, exit);
+                } else {
+                    stack[stack_size] = (far, split, exit);
+                    stack_size += 1;
+                    current = (near, enter, split);
+                }
+
+                continue;
+            }
+
+            let first = node.offset as usize;
+
+            for &i in &self.indices[first..first + node.count as usize] {
+                if self.objects[i as usize].hit(ray, &(range.start..closest), record) {
+                    hit_anything = true;
+                    closest = record.t;
+                }
+            }
+
+            if stack_size == 0 {
+                return hit_anything;
+            }
+
+            stack_size -= 1;
+            current = stack[stack_size];
+        }
+    }
+
+    fn bounding_box(&self) -> &AABB {
+        &self.aabb
+    }
+
+    fn materials<'a>(&'a self, materials: &mut Vec<&'a dyn Material>) {
+        for object in self.objects.iter() {
+            object.materials(materials);
+        }
+    }
+
+    fn has_random_hits(&self) -> bool {
+        self.random_hits
+    }
+
+    fn accelerator_memory(&self) -> usize {
+        mem::size_of::<Self>()
+            + self.objects.len() * mem::size_of::<Arc<dyn HittableObject>>()
+            + (self.loose.len() + self.indices.len()) * mem::size_of::<u32>()
+            + self.nodes.len() * mem::size_of::<KdNode>()
+            + self
+                .objects
+                .iter()
+                .map(|object| object.accelerator_memory())
+                .sum::<usize>()
+    }
+}
@@ -1,13 +1,30 @@
 pub mod aabb;
+pub mod accelerator;
+pub mod bdpt;
 pub mod bvh;
+pub mod bvh_cache;
 pub mod camera;
+pub mod checkpoint;
 pub mod constant_medium;
+pub mod distributed;
+mod encoding;
+pub mod film;
+pub mod grid;
 pub mod hittable;
+pub mod instance;
+pub mod integrator;
+pub mod kdtree;
 pub mod material;
+pub mod packet;
+pub mod photon_map;
 pub mod pixel;
+pub mod progress;
 pub mod quad;
+pub mod random;
 pub mod ray;
 pub mod scene;
+pub mod spectral;
 pub mod sphere;
+pub mod stats;
 pub mod texture;
 pub mod vector;
@@ -2,734 +2,373 @@
 //! I'm building this both to practice Rust and to
 //! brush up on graphics programming in general.
 
-use rand::Rng;
 use ray_tracer_rust::{
-    bvh::BVHNode,
-    camera::{Camera, CameraSettings, RenderSettings},
-    constant_medium::ConstantMedium,
-    hittable::{HittableList, RotateY, Translate},
-    material::{refraction_indices, Dielectric, DiffuseLight, Lambertian, Material, Metal},
-    quad::Quad,
-    sphere::Sphere,
-    texture::{CheckerBoard, ImageTexture, NoiseTexture},
-    vector,
-    vector::{Color, Vec3},
+    accelerator::{Accelerator, AcceleratorKind},
+    bvh_cache::BvhCache,
+    camera::{Camera, RenderOptions, RenderSettings, StoppingCriteria},
+    checkpoint::Checkpoint,
+    distributed::{self, RenderJob},
+    film::Film,
+    integrator::IntegratorKind,
+    progress::{Progress, ProgressObserver, RenderSummary},
+    random,
+    scene::build_scene,
+    stats,
 };
 
 use clap::Parser;
-use std::sync::Arc;
-
-fn render_checkered_spheres_scene(render_settings: &RenderSettings) {
-    let mut scene = HittableList::default();
-
-    let checker_texture = Arc::new(CheckerBoard::from_colors(
-        0.32,
-        Color::new(0.2, 0.3, 0.1),
-        Color::new(0.9, 0.9, 0.9),
-    ));
-
-    let checker_texture = Arc::new(Lambertian::from_texture(checker_texture));
-
-    scene.add(Arc::new(Sphere::new(
-        Vec3::new(0.0, -10.0, 0.0),
-        vector::zero_vec3(),
-        10.0,
-        checker_texture.clone(),
-    )));
-
-    scene.add(Arc::new(Sphere::new(
-        Vec3::new(0.0, 10.0, 0.0),
-        vector::zero_vec3(),
-        10.0,
-        checker_texture.clone(),
-    )));
-
-    let camera = Camera::new(
-        &CameraSettings {
-            position: Vec3::new(13.0, 2.0, 3.0),
-            target_position: vector::zero_vec3(),
-            up_direction: vector::up_vec3(),
-            fov: 20.0,
-            aspect_ratio: 16.0 / 9.0,
-            defocus_angle: 0.6,
-            focus_dist: 10.0,
-            background_color: Color::new(0.7, 0.8, 1.0),
-        },
-        render_settings,
-    );
+use progressing::{clamping::Bar as ClampingBar, Baring};
+use std::{
+    net::TcpListener,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
-    camera.render(&scene);
-}
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    #[arg(short, long, default_value_t = 2)]
+    scene: u8,
 
-fn render_earth_scene(render_settings: &RenderSettings) {
-    let mut scene = HittableList::default();
-
-    // let earth_texture_neat = Arc::new(ImageTexture::new("./images/earth-map-neat.jpg"));
-    // let earth_surface_neat = Arc::new(Lambertian::from_texture(earth_texture_neat));
-
-    let earth_texture_realistic = Arc::new(ImageTexture::new("./images/earth-realistic.jpg"));
-    let earth_surface_realistic = Arc::new(Lambertian::from_texture(earth_texture_realistic));
-
-    let scifi_planet_texture_realistic = Arc::new(ImageTexture::new("./images/scifi-planet.jpg"));
-    let scifi_planet_surface_realistic =
-        Arc::new(Lambertian::from_texture(scifi_planet_texture_realistic));
-
-    scene.add(Arc::new(Sphere::new(
-        Vec3::new(-2.3, 0.0, 0.0),
-        vector::zero_vec3(),
-        2.0,
-        earth_surface_realistic,
-    )));
-
-    scene.add(Arc::new(Sphere::new(
-        Vec3::new(2.3, 0.0, 0.0),
-        vector::zero_vec3(),
-        2.0,
-        scifi_planet_surface_realistic,
-    )));
-
-    // scene.add(Arc::new(Sphere::new(
-    //     Vec3::new(2.0, 0.0, 0.0),
-    //     vector::zero_vec3(),
-    //     1.5,
-    //     earth_surface_neat,
-    // )));
-
-    let camera = Camera::new(
-        &CameraSettings {
-            position: Vec3::new(-4.0, -2.0, 9.0),
-            target_position: vector::zero_vec3(),
-            up_direction: vector::up_vec3(),
-            fov: 35.0,
-            aspect_ratio: 16.0 / 9.0,
-            defocus_angle: 0.6,
-            focus_dist: 10.0,
-            background_color: Color::new(0.7, 0.8, 1.0),
-        },
-        render_settings,
-    );
+    #[arg(short, long, default_value_t = 2)]
+    quality: u8,
+
+    #[arg(short, long)]
+    width: Option<usize>,
+
+    #[arg(short = 'p', long)]
+    samples: Option<usize>,
+
+    #[arg(short, long)]
+    depth: Option<usize>,
 
-    camera.render(&scene);
+    /// How to light the scene: path, nee, bdpt, photon, spectral, ao, whitted,
+    /// or one of the debug views normals, uv, depth, material,
+    /// bvh-nodes, primitive-tests and bvh-boxes:DEPTH
+    #[arg(long, default_value_t = IntegratorKind::PathTracer)]
+    integrator: IntegratorKind,
+
+    /// What to put the scene's objects in to find what rays hit quickly:
+    /// bvh, grid or kd-tree
+    #[arg(long, default_value_t = AcceleratorKind::Bvh)]
+    accelerator: AcceleratorKind,
+
+    /// Keep the BVHs scenes build in this directory, and read them back
+    /// from there when a scene gets rendered again
+    #[arg(long)]
+    bvh_cache: Option<PathBuf>,
+
+    /// Seed for the scene layout and every sample
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+
+    /// Periodically save the render's progress to this file
+    #[arg(long)]
+    checkpoint: Option<PathBuf>,
+
+    /// Seconds between checkpoint saves
+    #[arg(long, default_value_t = 600)]
+    checkpoint_interval: u64,
+
+    /// Keep rendering passes until this much time has passed,
+    /// e.g. 90s, 10m or 1.5h
+    #[arg(long, value_parser = parse_duration)]
+    time: Option<Duration>,
+
+    /// Keep rendering passes until the estimated mean relative error
+    /// drops to this, e.g. 0.01
+    #[arg(long)]
+    max_error: Option<f64>,
+
+    /// Print statistics about the work the render did once it's done
+    #[arg(long)]
+    stats: bool,
+
+    /// Find where camera rays first hit the scene in packets of neighbouring
+    /// rays. Gives the same image. Only pays off for scenes that are one big
+    /// BVH, like the forest, with integrators that mostly need the first hit,
+    /// like normals; see `cargo bench --bench render -- packets`.
+    #[arg(long)]
+    packets: bool,
+
+    /// Instead of rendering an image, compare building and rendering every
+    /// scene that has an acceleration structure with each kind of one.
+    /// Renders are 200 pixels wide with 4 samples per pixel unless
+    /// --width or --samples say otherwise.
+    #[arg(long)]
+    bench: bool,
+
+    /// Write the render statistics to this file as JSON
+    #[arg(long)]
+    stats_json: Option<PathBuf>,
+
+    /// Continue rendering from a checkpoint file.
+    /// The scene, seed and depth are taken from the checkpoint,
+    /// and progress keeps being saved to the same file unless
+    /// --checkpoint says otherwise.
+    #[arg(long)]
+    resume: Option<PathBuf>,
+
+    /// Split the render between workers that connect to this address,
+    /// e.g. 0.0.0.0:7878
+    #[arg(long)]
+    coordinator: Option<String>,
+
+    /// Render tiles for the coordinator at this address
+    /// instead of rendering an image here
+    #[arg(long)]
+    worker: Option<String>,
+
+    /// Rows of the image in each tile handed out to a worker
+    #[arg(long, default_value_t = 16)]
+    tile_rows: usize,
+
+    /// Seconds the coordinator waits to hear from a worker, which says it's
+    /// still rendering every few seconds, before giving its tile to another
+    #[arg(long, default_value_t = 60)]
+    tile_timeout: u64,
 }
 
-fn render_bouncing_balls_scene(render_settings: &RenderSettings) {
-    let mut rng = rand::thread_rng();
+/// Prints the render's progress to stderr
+struct ProgressBar {
+    bar: Mutex<ClampingBar>,
+}
+
+impl ProgressBar {
+    fn new() -> Self {
+        let mut bar = ClampingBar::new();
+        bar.set_len(20);
+
+        Self {
+            bar: Mutex::new(bar),
+        }
+    }
+}
 
-    let mut scene = HittableList::default();
+impl ProgressObserver for ProgressBar {
+    fn on_start(&self) {
+        eprintln!("Rendering scene...");
+    }
 
-    // Ground
-    let checker_texture = Arc::new(CheckerBoard::from_colors(
-        0.32,
-        Color::new(0.2, 0.3, 0.1),
-        Color::new(0.9, 0.9, 0.9),
-    ));
+    fn on_progress(&self, progress: &Progress) {
+        let Some(fraction) = progress.fraction else {
+            return;
+        };
 
-    // let material_ground = Arc::new(Lambertian::from_color_components(0.5, 0.5, 0.5));
-    let material_ground = Arc::new(Lambertian::from_texture(checker_texture));
+        let mut bar = self.bar.lock().unwrap();
+        bar.set(fraction);
 
-    scene.add(Arc::new(Sphere::new(
-        Vec3::new(0.0, -1000.0, 0.0),
-        vector::zero_vec3(),
-        1000.0,
-        material_ground,
-    )));
+        if bar.has_progressed_significantly() {
+            bar.remember_significant_progress();
 
-    for a in -11..11 {
-        for b in -11..11 {
-            let choose_mat: f64 = rng.gen();
+            let eta = match progress.eta {
+                Some(eta) => format_duration(eta),
+                None => String::from("unknown"),
+            };
 
-            let center = Vec3::new(
-                a as f64 + 0.9 * rng.gen::<f64>(),
-                0.2,
-                b as f64 + 0.9 * rng.gen::<f64>(),
+            eprintln!(
+                "{} pass {}, {:.1} samples per pixel, ETA {}",
+                bar, progress.pass, progress.samples_per_pixel, eta
             );
+        }
+    }
 
-            if glm::ext::sqlength(center - glm::dvec3(4.0, 0.2, 0.0)) > 0.81 {
-                let mut velocity = vector::zero_vec3();
-
-                let sphere_material: Arc<dyn Material> = match choose_mat {
-                    choose_mat if choose_mat < 0.8 => {
-                        let albedo = vector::random_vec3(0.0..1.0) * vector::random_vec3(0.0..1.0);
-                        velocity.y = rng.gen_range(0.0..0.5);
-                        Arc::new(Lambertian::from_color(albedo))
-                    }
-                    choose_mat if choose_mat < 0.9 => {
-                        let albedo = vector::random_vec3(0.5..1.0);
-                        let fuzz: f64 = rng.gen_range(0.0..0.5);
-                        Arc::new(Metal::new(albedo, fuzz))
-                    }
-                    _ => Arc::new(Dielectric::new(refraction_indices::GLASS)),
-                };
-
-                scene.add(Arc::new(Sphere::new(
-                    center,
-                    velocity,
-                    0.2,
-                    sphere_material,
-                )));
-            }
+    fn on_pass(&self, progress: &Progress, _film: &Film) {
+        // Without any limit to measure against, a bar can't show anything
+        if progress.fraction.is_none() {
+            eprintln!(
+                "Pass {}, {:.1} samples per pixel",
+                progress.pass, progress.samples_per_pixel
+            );
         }
     }
 
-    let material1 = Arc::new(Dielectric::new(refraction_indices::GLASS));
-
-    scene.add(Arc::new(Sphere::new(
-        glm::dvec3(0.0, 1.0, 0.0),
-        vector::zero_vec3(),
-        1.0,
-        material1,
-    )));
-
-    let material2 = Arc::new(Lambertian::from_color(glm::dvec3(0.4, 0.2, 0.1)));
-
-    scene.add(Arc::new(Sphere::new(
-        glm::dvec3(-4.0, 1.0, 0.0),
-        vector::zero_vec3(),
-        1.0,
-        material2,
-    )));
-
-    let material3 = Arc::new(Metal::new(glm::dvec3(0.4, 0.6, 0.5), 0.0));
-
-    scene.add(Arc::new(Sphere::new(
-        glm::dvec3(4.0, 1.0, 0.0),
-        vector::zero_vec3(),
-        1.0,
-        material3,
-    )));
-
-    let tree = BVHNode::from(scene.objects());
-
-    let camera = Camera::new(
-        &CameraSettings {
-            position: Vec3::new(13.0, 2.0, 3.0),
-            target_position: vector::zero_vec3(),
-            up_direction: vector::up_vec3(),
-            fov: 20.0,
-            aspect_ratio: 16.0 / 9.0,
-            defocus_angle: 0.6,
-            focus_dist: 10.0,
-            background_color: Color::new(0.7, 0.8, 1.0),
-        },
-        render_settings,
-    );
+    fn on_finish(&self, summary: &RenderSummary) {
+        if summary.cancelled {
+            eprintln!("Render cancelled");
+        }
 
-    camera.render(&tree);
+        eprintln!("Scene rendered in {}", format_duration(summary.elapsed));
+
+        match summary.mean_relative_error {
+            Some(error) => eprintln!(
+                "Finished with {} samples per pixel and a mean relative error of {:.4}",
+                summary.samples_per_pixel, error
+            ),
+            None => eprintln!(
+                "Finished with {} samples per pixel",
+                summary.samples_per_pixel
+            ),
+        }
+    }
 }
 
-fn render_perlin_spheres_scene(render_settings: &RenderSettings) {
-    let perlin_texture = Arc::new(NoiseTexture::new(0, 4.0, 10.0, 6, 1.0, 1.0));
-    let perlin_material = Arc::new(Lambertian::from_texture(perlin_texture));
-
-    let mut scene = HittableList::default();
-
-    scene.add(Arc::new(Sphere::new(
-        Vec3::new(0.0, -1000.0, 0.0),
-        vector::zero_vec3(),
-        1000.0,
-        perlin_material.clone(),
-    )));
-
-    scene.add(Arc::new(Sphere::new(
-        Vec3::new(0.0, 2.0, 0.0),
-        vector::zero_vec3(),
-        2.0,
-        perlin_material.clone(),
-    )));
-
-    let camera = Camera::new(
-        &CameraSettings {
-            position: Vec3::new(13.0, 2.0, 3.0),
-            target_position: Vec3::new(0.0, 0.0, 0.0),
-            up_direction: vector::up_vec3(),
-            fov: 20.0,
-            aspect_ratio: 16.0 / 9.0,
-            defocus_angle: 0.0,
-            focus_dist: 10.0,
-            background_color: Color::new(0.7, 0.8, 1.0),
-        },
-        render_settings,
-    );
-
-    camera.render(&scene);
+/// Saves the film to a checkpoint file every so often
+struct CheckpointSaver {
+    checkpoint: Checkpoint,
+    path: PathBuf,
+    interval: Duration,
+    last_save: Mutex<Instant>,
 }
 
-fn render_simple_light_scene(render_settings: &RenderSettings) {
-    let perlin_texture = Arc::new(NoiseTexture::new(0, 4.0, 10.0, 6, 1.0, 1.0));
-    let perlin_material = Arc::new(Lambertian::from_texture(perlin_texture));
-
-    let mut scene = HittableList::default();
-
-    scene.add(Arc::new(Sphere::new(
-        Vec3::new(0.0, -1000.0, 0.0),
-        vector::zero_vec3(),
-        1000.0,
-        perlin_material.clone(),
-    )));
-
-    scene.add(Arc::new(Sphere::new(
-        Vec3::new(0.0, 2.0, 0.0),
-        vector::zero_vec3(),
-        2.0,
-        perlin_material.clone(),
-    )));
-
-    let diff_light_material = Arc::new(DiffuseLight::from_color_components(4.0, 4.0, 4.0));
-
-    scene.add(Arc::new(Quad::new(
-        Vec3::new(3.0, 1.0, -2.0),
-        Vec3::new(2.0, 0.0, 0.0),
-        Vec3::new(0.0, 2.0, 0.0),
-        diff_light_material.clone(),
-    )));
-
-    scene.add(Arc::new(Sphere::new(
-        Vec3::new(0.0, 7.0, 0.0),
-        vector::zero_vec3(),
-        2.0,
-        diff_light_material.clone(),
-    )));
-
-    let camera = Camera::new(
-        &CameraSettings {
-            position: Vec3::new(26.0, 3.0, 6.0),
-            target_position: Vec3::new(0.0, 2.0, 0.0),
-            up_direction: vector::up_vec3(),
-            fov: 20.0,
-            aspect_ratio: 16.0 / 9.0,
-            defocus_angle: 0.6,
-            focus_dist: 10.0,
-            background_color: Color::new(0.0, 0.0, 0.0),
-        },
-        render_settings,
-    );
+impl CheckpointSaver {
+    fn save(&self, film: &Film) {
+        if let Err(error) = self.checkpoint.save(&self.path, film) {
+            eprintln!(
+                "Failed to save checkpoint {}: {}",
+                self.path.display(),
+                error
+            );
+        }
 
-    camera.render(&scene);
+        *self.last_save.lock().unwrap() = Instant::now();
+    }
 }
 
-fn render_quads_scene(render_settings: &RenderSettings) {
-    let left_red = Arc::new(Lambertian::from_color_components(1.0, 0.2, 0.2));
-    let back_green = Arc::new(Lambertian::from_color_components(0.2, 1.0, 0.2));
-    let right_blue = Arc::new(Lambertian::from_color_components(0.2, 0.2, 1.0));
-    let upper_orange = Arc::new(Lambertian::from_color_components(1.0, 0.5, 0.0));
-    let lower_teal = Arc::new(Lambertian::from_color_components(0.2, 0.8, 0.8));
-
-    let mut scene = HittableList::default();
-
-    scene.add(Arc::new(Quad::new(
-        Vec3::new(-3.0, -2.0, 5.0),
-        Vec3::new(0.0, 0.0, -4.0),
-        Vec3::new(0.0, 4.0, 0.0),
-        left_red,
-    )));
-
-    scene.add(Arc::new(Quad::new(
-        Vec3::new(-2.0, -2.0, 0.0),
-        Vec3::new(4.0, 0.0, 0.0),
-        Vec3::new(0.0, 4.0, 0.0),
-        back_green,
-    )));
-
-    scene.add(Arc::new(Quad::new(
-        Vec3::new(3.0, -2.0, 1.0),
-        Vec3::new(0.0, 0.0, 4.0),
-        Vec3::new(0.0, 4.0, 0.0),
-        right_blue,
-    )));
-
-    scene.add(Arc::new(Quad::new(
-        Vec3::new(-2.0, 3.0, 1.0),
-        Vec3::new(4.0, 0.0, 0.0),
-        Vec3::new(0.0, 0.0, 4.0),
-        upper_orange,
-    )));
-
-    scene.add(Arc::new(Quad::new(
-        Vec3::new(-2.0, -3.0, 5.0),
-        Vec3::new(4.0, 0.0, 0.0),
-        Vec3::new(0.0, 0.0, -4.0),
-        lower_teal,
-    )));
-
-    let camera = Camera::new(
-        &CameraSettings {
-            position: Vec3::new(0.0, 0.0, 9.0),
-            target_position: vector::zero_vec3(),
-            up_direction: vector::up_vec3(),
-            fov: 80.0,
-            aspect_ratio: 1.0,
-            defocus_angle: 0.6,
-            focus_dist: 10.0,
-            background_color: Color::new(0.7, 0.8, 1.0),
-        },
-        render_settings,
-    );
+impl ProgressObserver for CheckpointSaver {
+    fn on_pass(&self, _progress: &Progress, film: &Film) {
+        let last_save = *self.last_save.lock().unwrap();
 
-    camera.render(&scene);
+        if last_save.elapsed() >= self.interval {
+            self.save(film);
+        }
+    }
 }
 
-fn render_cornell_box_scene(render_settings: &RenderSettings) {
-    let red = Arc::new(Lambertian::from_color_components(0.65, 0.05, 0.05));
-    let white = Arc::new(Lambertian::from_color_components(0.73, 0.73, 0.73));
-    let green = Arc::new(Lambertian::from_color_components(0.12, 0.45, 0.15));
-    let light = Arc::new(DiffuseLight::from_color_components(15.0, 15.0, 15.0));
-
-    let mut scene = HittableList::default();
-
-    scene.add(Arc::new(Quad::new(
-        Vec3::new(555.0, 0.0, 0.0),
-        Vec3::new(0.0, 555.0, 0.0),
-        Vec3::new(0.0, 0.0, 555.0),
-        green,
-    )));
-
-    scene.add(Arc::new(Quad::new(
-        Vec3::new(0.0, 0.0, 0.0),
-        Vec3::new(0.0, 555.0, 0.0),
-        Vec3::new(0.0, 0.0, 555.0),
-        red,
-    )));
-
-    scene.add(Arc::new(Quad::new(
-        Vec3::new(343.0, 554.0, 332.0),
-        Vec3::new(-130.0, 0.0, 0.0),
-        Vec3::new(0.0, 0.0, -105.0),
-        light,
-    )));
-
-    scene.add(Arc::new(Quad::new(
-        Vec3::new(0.0, 0.0, 0.0),
-        Vec3::new(555.0, 0.0, 0.0),
-        Vec3::new(0.0, 0.0, 555.0),
-        white.clone(),
-    )));
-
-    scene.add(Arc::new(Quad::new(
-        Vec3::new(555.0, 555.0, 555.0),
-        Vec3::new(-555.0, 0.0, 0.0),
-        Vec3::new(0.0, 0.0, -555.0),
-        white.clone(),
-    )));
-
-    scene.add(Arc::new(Quad::new(
-        Vec3::new(0.0, 0.0, 555.0),
-        Vec3::new(555.0, 0.0, 0.0),
-        Vec3::new(0.0, 555.0, 0.0),
-        white.clone(),
-    )));
-
-    let mut box1 = Quad::box_from_opposite_corners(
-        Vec3::new(0.0, 0.0, 0.0),
-        Vec3::new(165.0, 330.0, 165.0),
-        white.clone(),
-    );
-
-    box1 = Arc::new(RotateY::new(box1, 15.0));
-    box1 = Arc::new(Translate::new(box1, Vec3::new(265.0, 0.0, 295.0)));
-
-    scene.add(box1);
-
-    let mut box2 = Quad::box_from_opposite_corners(
-        Vec3::new(0.0, 0.0, 0.0),
-        Vec3::new(165.0, 165.0, 165.0),
-        white.clone(),
-    );
+fn format_duration(duration: Duration) -> String {
+    let mut elapsed = duration.as_secs();
+    let hours = elapsed / 3600;
+    elapsed %= 3600;
 
-    box2 = Arc::new(RotateY::new(box2, -18.0));
-    box2 = Arc::new(Translate::new(box2, Vec3::new(130.0, 0.0, 65.0)));
-
-    scene.add(box2);
-
-    let camera = Camera::new(
-        &CameraSettings {
-            position: Vec3::new(278.0, 278.0, -800.0),
-            target_position: Vec3::new(278.0, 278.0, 0.0),
-            up_direction: vector::up_vec3(),
-            fov: 40.0,
-            aspect_ratio: 1.0,
-            defocus_angle: 0.0,
-            focus_dist: 10.0,
-            background_color: Color::new(0.0, 0.0, 0.0),
-        },
-        render_settings,
-    );
+    let minutes = elapsed / 60;
+    let seconds = elapsed % 60;
 
-    camera.render(&scene);
+    format!("{} hours, {} minutes, {} seconds", hours, minutes, seconds)
 }
 
-fn render_cornell_smoke_box_scene(render_settings: &RenderSettings) {
-    let red = Arc::new(Lambertian::from_color_components(0.65, 0.05, 0.05));
-    let white = Arc::new(Lambertian::from_color_components(0.73, 0.73, 0.73));
-    let green = Arc::new(Lambertian::from_color_components(0.12, 0.45, 0.15));
-    let light = Arc::new(DiffuseLight::from_color_components(7.0, 7.0, 7.0));
-
-    let mut scene = HittableList::default();
-
-    scene.add(Arc::new(Quad::new(
-        Vec3::new(555.0, 0.0, 0.0),
-        Vec3::new(0.0, 555.0, 0.0),
-        Vec3::new(0.0, 0.0, 555.0),
-        green,
-    )));
-
-    scene.add(Arc::new(Quad::new(
-        Vec3::new(0.0, 0.0, 0.0),
-        Vec3::new(0.0, 555.0, 0.0),
-        Vec3::new(0.0, 0.0, 555.0),
-        red,
-    )));
-
-    scene.add(Arc::new(Quad::new(
-        Vec3::new(113.0, 554.0, 127.0),
-        Vec3::new(330.0, 0.0, 0.0),
-        Vec3::new(0.0, 0.0, 305.0),
-        light,
-    )));
-
-    scene.add(Arc::new(Quad::new(
-        Vec3::new(0.0, 0.0, 0.0),
-        Vec3::new(555.0, 0.0, 0.0),
-        Vec3::new(0.0, 0.0, 555.0),
-        white.clone(),
-    )));
-
-    scene.add(Arc::new(Quad::new(
-        Vec3::new(555.0, 555.0, 555.0),
-        Vec3::new(-555.0, 0.0, 0.0),
-        Vec3::new(0.0, 0.0, -555.0),
-        white.clone(),
-    )));
-
-    scene.add(Arc::new(Quad::new(
-        Vec3::new(0.0, 0.0, 555.0),
-        Vec3::new(555.0, 0.0, 0.0),
-        Vec3::new(0.0, 555.0, 0.0),
-        white.clone(),
-    )));
-
-    let mut box1 = Quad::box_from_opposite_corners(
-        Vec3::new(0.0, 0.0, 0.0),
-        Vec3::new(165.0, 330.0, 165.0),
-        white.clone(),
-    );
-
-    box1 = Arc::new(ConstantMedium::from_color(
-        box1,
-        0.01,
-        Color::new(0.0, 0.0, 0.0),
-    ));
-
-    box1 = Arc::new(RotateY::new(box1, 15.0));
-    box1 = Arc::new(Translate::new(box1, Vec3::new(265.0, 0.0, 295.0)));
-
-    scene.add(box1);
+fn parse_duration(text: &str) -> Result<Duration, String> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(text.len());
+    let (amount, unit) = text.split_at(split);
+
+    let amount: f64 = amount
+        .parse()
+        .map_err(|_| format!("invalid duration `{}`", text))?;
+
+    let seconds_per_unit = match unit {
+        "" | "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        _ => return Err(format!("unknown duration unit `{}`, use s, m or h", unit)),
+    };
 
-    let mut box2 = Quad::box_from_opposite_corners(
-        Vec3::new(0.0, 0.0, 0.0),
-        Vec3::new(165.0, 165.0, 165.0),
-        white.clone(),
-    );
+    Duration::try_from_secs_f64(amount * seconds_per_unit)
+        .map_err(|error| format!("invalid duration `{}`: {}", text, error))
+}
 
-    box2 = Arc::new(ConstantMedium::from_color(
-        box2,
-        0.01,
-        Color::new(1.0, 1.0, 1.0),
-    ));
-
-    box2 = Arc::new(RotateY::new(box2, -18.0));
-    box2 = Arc::new(Translate::new(box2, Vec3::new(130.0, 0.0, 65.0)));
-
-    scene.add(box2);
-
-    let camera = Camera::new(
-        &CameraSettings {
-            position: Vec3::new(278.0, 278.0, -800.0),
-            target_position: Vec3::new(278.0, 278.0, 0.0),
-            up_direction: vector::up_vec3(),
-            fov: 40.0,
-            aspect_ratio: 1.0,
-            defocus_angle: 0.0,
-            focus_dist: 10.0,
-            background_color: Color::new(0.0, 0.0, 0.0),
-        },
-        render_settings,
+/// Build every scene that keeps its objects in an acceleration structure with
+/// each kind of structure, render it, and print how long building the scene
+/// took, how much memory the structures take up and how fast rays got traced
+fn bench(render_settings: &RenderSettings, seed: u64) {
+    println!(
+        "{:<7}{:<13}{:>12}{:>14}{:>13}{:>10}",
+        "scene", "accelerator", "build (ms)", "memory (KiB)", "render (ms)", "Mrays/s"
     );
 
-    camera.render(&scene);
-}
-
-fn render_final_scene(render_settings: &RenderSettings) {
-    let mut rng = rand::thread_rng();
+    for id in 0..=u8::MAX {
+        for accelerator in AcceleratorKind::ALL {
+            random::seed(seed);
 
-    let mut scene = HittableList::default();
+            let accelerator = Accelerator {
+                kind: accelerator,
+                ..Default::default()
+            };
+            let Some(scene) = build_scene(id, &accelerator) else {
+                return;
+            };
+            let build_time = *accelerator.build_time.lock().unwrap();
 
-    // Floor boxes
-    let mut boxes_1 = HittableList::default();
-    let ground = Arc::new(Lambertian::from_color_components(0.48, 0.83, 0.53));
-    let boxes_per_side = 20;
+            let memory = scene.world.accelerator_memory();
 
-    for i in 0..boxes_per_side {
-        for j in 0..boxes_per_side {
-            let w = 100.0;
-            let a = Vec3::new(-1000.0 + i as f64 * w, 0.0, -1000.0 + j as f64 * w);
-            let b = Vec3::new(a.x + w, rng.gen_range(1.0..101.0), a.z + w);
+            // Without any acceleration structure, every kind would do the same
+            if memory == 0 {
+                break;
+            }
 
-            boxes_1.add(Quad::box_from_opposite_corners(a, b, ground.clone()));
+            let camera = Camera::new(&scene.camera_settings, render_settings);
+            let mut film = Film::new(camera.image_width(), camera.image_height());
+            let options = RenderOptions {
+                seed,
+                ..Default::default()
+            };
+
+            let summary = camera.render_film(&scene, &mut film, &options);
+
+            println!(
+                "{:<7}{:<13}{:>12.1}{:>14.1}{:>13.1}{:>10.3}",
+                id,
+                accelerator.kind.name(),
+                build_time.as_secs_f64() * 1000.0,
+                memory as f64 / 1024.0,
+                summary.elapsed.as_secs_f64() * 1000.0,
+                summary.stats.rays_per_second(summary.elapsed) / 1e6
+            );
         }
     }
+}
 
-    scene.add(Arc::new(BVHNode::from(boxes_1.objects())));
-
-    let light = Arc::new(DiffuseLight::from_color_components(7.0, 7.0, 7.0));
-    scene.add(Arc::new(Quad::new(
-        Vec3::new(123.0, 554.0, 147.0),
-        Vec3::new(300.0, 0.0, 0.0),
-        Vec3::new(0.0, 0.0, 265.0),
-        light,
-    )));
-
-    // Moving orange ball in the
-    scene.add(Arc::new(Sphere::new(
-        Vec3::new(400.0, 400.0, 200.0),
-        Vec3::new(30.0, 0.0, 0.0),
-        50.0,
-        Arc::new(Lambertian::from_color_components(0.73, 0.3, 0.1)),
-    )));
-
-    // Glass ball in the bottom center
-    scene.add(Arc::new(Sphere::new(
-        Vec3::new(260.0, 150.0, 45.0),
-        vector::zero_vec3(),
-        50.0,
-        Arc::new(Dielectric::new(1.5)),
-    )));
-
-    // Metal ball in the bottom right
-    scene.add(Arc::new(Sphere::new(
-        Vec3::new(0.0, 150.0, 145.0),
-        vector::zero_vec3(),
-        50.0,
-        Arc::new(Metal::new(Color::new(0.8, 0.8, 0.9), 1.0)),
-    )));
-
-    // Blue glass ball in the bottom left
-    let boundary = Arc::new(Sphere::new(
-        Vec3::new(360.0, 150.0, 145.0),
-        vector::zero_vec3(),
-        70.0,
-        Arc::new(Dielectric::new(1.5)),
-    ));
-
-    // Filling for the ball above
-    scene.add(boundary.clone());
-    scene.add(Arc::new(ConstantMedium::from_color(
-        boundary,
-        0.2,
-        Color::new(0.2, 0.4, 0.9),
-    )));
-
-    // Outer shell of the scene
-    let boundary = Arc::new(Sphere::new(
-        vector::zero_vec3(),
-        vector::zero_vec3(),
-        5000.0,
-        Arc::new(Dielectric::new(1.5)),
-    ));
-
-    // // Thin mist around the entire scene
-    scene.add(boundary.clone());
-    scene.add(Arc::new(ConstantMedium::from_color(
-        boundary,
-        1e-4,
-        Color::new(1.0, 1.0, 1.0),
-    )));
-
-    // Earth ball
-    let earth_texture_realistic = Arc::new(ImageTexture::new("./images/earth-realistic.jpg"));
-    let earth_surface_realistic = Arc::new(Lambertian::from_texture(earth_texture_realistic));
-    scene.add(Arc::new(Sphere::new(
-        Vec3::new(400.0, 200.0, 400.0),
-        vector::zero_vec3(),
-        100.0,
-        earth_surface_realistic,
-    )));
-
-    // Perlin noise ball
-    let perlin_texture = Arc::new(NoiseTexture::new(0, 0.2, 0.1, 6, 1.0, 1.0));
-    let perlin_material = Arc::new(Lambertian::from_texture(perlin_texture));
-
-    scene.add(Arc::new(Sphere::new(
-        Vec3::new(220.0, 280.0, 300.0),
-        vector::zero_vec3(),
-        80.0,
-        perlin_material,
-    )));
-
-    // Box full of balls in the top right corner
-    let mut boxes_2 = HittableList::default();
-    let white_material = Arc::new(Lambertian::from_color_components(0.73, 0.73, 0.73));
-    let white_sphere = Arc::new(Sphere::new(
-        vector::zero_vec3(),
-        vector::zero_vec3(),
-        10.0,
-        white_material.clone(),
-    ));
-
-    let ns = 1000;
-
-    for _ in 0..ns {
-        boxes_2.add(Arc::new(Translate::new(
-            white_sphere.clone(),
-            vector::random_vec3(0.0..165.0),
-        )));
-    }
+/// Say how many BVHs came from the cache, and what went wrong with it
+fn report_cache(cache: &BvhCache) {
+    let summary = cache.summary();
 
-    scene.add(Arc::new(Translate::new(
-        Arc::new(RotateY::new(
-            Arc::new(BVHNode::from(boxes_2.objects())),
-            15.0,
-        )),
-        Vec3::new(-100.0, 270.0, 395.0),
-    )));
-
-    let camera = Camera::new(
-        &CameraSettings {
-            position: Vec3::new(478.0, 278.0, -600.0),
-            target_position: Vec3::new(278.0, 278.0, 0.0),
-            up_direction: vector::up_vec3(),
-            fov: 40.0,
-            aspect_ratio: 1.0,
-            defocus_angle: 0.0,
-            focus_dist: 10.0,
-            background_color: Color::new(0.0, 0.0, 0.0),
-        },
-        render_settings,
+    eprintln!(
+        "Loaded {} BVHs from the cache and built {}, {} of them replacing stale ones",
+        summary.loaded, summary.built, summary.stale
     );
 
-    camera.render(&scene);
+    for error in &summary.errors {
+        eprintln!("BVH cache: {}", error);
+    }
 }
 
-#[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
-struct Args {
-    #[arg(short, long, default_value_t = 2)]
-    scene: u8,
+fn main() {
+    let args = Args::parse();
 
-    #[arg(short, long, default_value_t = 2)]
-    quality: u8,
+    // Counting costs time on every ray, so only count what gets read
+    let wants_stats = args.stats || args.stats_json.is_some();
 
-    #[arg(short, long)]
-    width: Option<usize>,
+    let accelerator = Accelerator {
+        kind: args.accelerator,
+        cache: args
+            .bvh_cache
+            .as_ref()
+            .map(|path| Arc::new(BvhCache::new(path))),
+        ..Default::default()
+    };
 
-    #[arg(short = 'p', long)]
-    samples: Option<usize>,
+    if let Some(address) = &args.worker {
+        let build_scene = |scene| build_scene(scene, &accelerator);
 
-    #[arg(short, long)]
-    depth: Option<usize>,
-}
+        match distributed::run_worker(address.as_str(), build_scene) {
+            Ok(tiles) => eprintln!("Rendered {} tiles, coordinator is done", tiles),
+            Err(error) => {
+                eprintln!("Worker stopped: {}", error);
+                std::process::exit(1);
+            }
+        }
 
-fn main() {
-    let args = Args::parse();
+        return;
+    }
+
+    if args.coordinator.is_some()
+        && (args.resume.is_some() || args.time.is_some() || args.max_error.is_some())
+    {
+        eprintln!("Distributed renders can't be resumed or limited by time or noise");
+        std::process::exit(1);
+    }
 
     let mut render_settings = match args.quality {
         0 => Camera::very_simple_debug_settings(),
@@ -758,19 +397,162 @@ fn main() {
         render_settings.max_depth = depth;
     }
 
-    match args.scene {
-        0 => render_bouncing_balls_scene(&render_settings),
-        1 => render_checkered_spheres_scene(&render_settings),
-        2 => render_earth_scene(&render_settings),
-        3 => render_perlin_spheres_scene(&render_settings),
-        4 => render_simple_light_scene(&render_settings),
-        5 => render_quads_scene(&render_settings),
-        6 => render_cornell_box_scene(&render_settings),
-        7 => render_cornell_smoke_box_scene(&render_settings),
-        8 => render_final_scene(&render_settings),
-        _ => {
+    if args.bench {
+        render_settings.image_width = args.width.unwrap_or(200);
+        render_settings.samples_per_pixel = args.samples.unwrap_or(4);
+
+        // Rays per second come from the counters
+        stats::set_enabled(true);
+        bench(&render_settings, args.seed);
+        return;
+    }
+
+    let stopping = StoppingCriteria {
+        time_budget: args.time,
+        max_relative_error: args.max_error,
+    };
+
+    // Time and noise limits replace the quality preset's sample count,
+    // unless a sample count was asked for explicitly.
+    if !stopping.only_counts_samples() && args.samples.is_none() {
+        render_settings.samples_per_pixel = usize::MAX;
+    }
+
+    let mut checkpoint = Checkpoint {
+        scene: args.scene,
+        seed: args.seed,
+        max_depth: render_settings.max_depth,
+        integrator: args.integrator,
+    };
+
+    let mut resumed_film = None;
+
+    if let Some(path) = &args.resume {
+        let (resumed_checkpoint, film) = match Checkpoint::load(path) {
+            Ok(loaded) => loaded,
+            Err(error) => {
+                eprintln!("Failed to load checkpoint {}: {}", path.display(), error);
+                std::process::exit(1);
+            }
+        };
+
+        eprintln!(
+            "Resuming scene {} from {} samples per pixel",
+            resumed_checkpoint.scene,
+            film.min_sample_count()
+        );
+
+        render_settings.image_width = film.width();
+        render_settings.max_depth = resumed_checkpoint.max_depth;
+        checkpoint = resumed_checkpoint;
+        resumed_film = Some(film);
+    }
+
+    // The scene layout is random too, so it has to be seeded
+    // for a resumed render to see the same scene.
+    random::seed(checkpoint.seed);
+
+    let scene = match build_scene(checkpoint.scene, &accelerator) {
+        Some(scene) => scene,
+        None => {
             eprintln!("Invalid scene id");
             std::process::exit(1);
         }
+    };
+
+    if let Some(cache) = &accelerator.cache {
+        report_cache(cache);
+    }
+
+    let camera = Camera::new(&scene.camera_settings, &render_settings);
+
+    let mut film = match resumed_film {
+        Some(film) if film.height() != camera.image_height() => {
+            eprintln!("Checkpoint image size does not match the scene");
+            std::process::exit(1);
+        }
+        Some(film) => film,
+        None => Film::new(camera.image_width(), camera.image_height()),
+    };
+
+    let checkpoint_saver = args.checkpoint.or(args.resume).map(|path| CheckpointSaver {
+        checkpoint,
+        path,
+        interval: Duration::from_secs(args.checkpoint_interval),
+        last_save: Mutex::new(Instant::now()),
+    });
+
+    let progress_bar = ProgressBar::new();
+    let mut observers: Vec<&dyn ProgressObserver> = vec![&progress_bar];
+
+    if let Some(saver) = &checkpoint_saver {
+        observers.push(saver);
+    }
+
+    let summary = match &args.coordinator {
+        Some(address) => {
+            let job = RenderJob {
+                scene: checkpoint.scene,
+                seed: checkpoint.seed,
+                image_width: camera.image_width(),
+                samples_per_pixel: camera.samples_per_pixel(),
+                max_depth: camera.max_depth(),
+                integrator: checkpoint.integrator,
+                stats: wants_stats,
+            };
+
+            let result = TcpListener::bind(address).and_then(|listener| {
+                eprintln!("Waiting for workers on {}", listener.local_addr()?);
+                distributed::coordinate(
+                    listener,
+                    &job,
+                    &mut film,
+                    args.tile_rows,
+                    Duration::from_secs(args.tile_timeout),
+                    &observers,
+                )
+            });
+
+            match result {
+                Ok(summary) => summary,
+                Err(error) => {
+                    eprintln!("Distributed render failed: {}", error);
+                    std::process::exit(1);
+                }
+            }
+        }
+        None => {
+            let integrator = checkpoint.integrator.build(&scene);
+            stats::set_enabled(wants_stats || integrator.needs_stats());
+
+            let options = RenderOptions {
+                seed: checkpoint.seed,
+                stopping,
+                observers,
+                integrator: Some(integrator.as_ref()),
+                packets: args.packets,
+                ..Default::default()
+            };
+
+            camera.render_film(&scene, &mut film, &options)
+        }
+    };
+
+    if args.stats {
+        eprint!("{}", summary.stats.report(summary.elapsed));
+    }
+
+    if let Some(path) = &args.stats_json {
+        let json = summary.stats.to_json(summary.elapsed);
+
+        if let Err(error) = std::fs::write(path, json) {
+            eprintln!("Failed to write stats to {}: {}", path.display(), error);
+        }
+    }
+
+    if let Some(saver) = &checkpoint_saver {
+        saver.save(&film);
     }
+
+    film.write_ppm();
 }
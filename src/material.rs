@@ -1,8 +1,9 @@
 use crate::hittable::HitRecord;
+use crate::random;
 use crate::ray::Ray;
 use crate::texture::{SolidColor, Texture};
 use crate::vector;
-use crate::vector::{Color, Vec2, Vec3};
+use crate::vector::{consts::PI, Color, Float, Point3, Vec2, Vec3};
 use rand::Rng;
 use std::sync::Arc;
 
@@ -10,9 +11,50 @@ pub trait Material: Send + Sync {
     fn scatter(&self, record: &mut HitRecord, attenuation: &mut Color, scattered: &mut Ray)
         -> bool;
 
-    fn emitted(&self, _uv: &Vec2, _point: &Vec3) -> Color {
+    fn emitted(&self, _uv: &Vec2, _point: &Point3) -> Color {
         Color::new(0.0, 0.0, 0.0)
     }
+
+    /// Whether `scatter` picks its directions from a handful of sharp
+    /// possibilities, which `scattering` and `scattering_pdf` can't describe.
+    /// Light can't be sampled towards these materials.
+    fn is_specular(&self) -> bool {
+        false
+    }
+
+    /// Whether the material scatters light inside a volume rather than
+    /// off a surface, so there's no surface for light to arrive at an angle to
+    fn is_volumetric(&self) -> bool {
+        false
+    }
+
+    /// How much of the light arriving from `direction` gets scattered back
+    /// along the record's ray, including the cosine term for surfaces
+    fn scattering(&self, _record: &HitRecord, _direction: &Vec3) -> Color {
+        Vec3::ZERO
+    }
+
+    /// The probability density of `scatter` picking `direction`, per unit solid angle
+    fn scattering_pdf(&self, _record: &HitRecord, _direction: &Vec3) -> Float {
+        0.0
+    }
+
+    /// Like `scatter`, for light of a single `wavelength` in nanometers.
+    /// Only materials that treat some wavelengths differently need to override it.
+    fn scatter_wavelength(
+        &self,
+        record: &mut HitRecord,
+        _wavelength: Float,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> bool {
+        self.scatter(record, attenuation, scattered)
+    }
+
+    /// Whether `scatter_wavelength` sends different wavelengths in different directions
+    fn is_dispersive(&self) -> bool {
+        false
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -45,7 +87,7 @@ impl Lambertian {
         Self::from_texture(Arc::new(SolidColor::from(color)))
     }
 
-    pub fn from_color_components(r: f64, g: f64, b: f64) -> Self {
+    pub fn from_color_components(r: Float, g: Float, b: Float) -> Self {
         Self::from_color(Color::new(r, g, b))
     }
 }
@@ -59,103 +101,182 @@ impl Material for Lambertian {
     ) -> bool {
         let mut scatter_direction = record.normal + vector::random_unit_sphere_vec();
 
-        if vector::is_vec_near_zero(&scatter_direction) {
-            scatter_direction = record.normal;
+        if scatter_direction.is_near_zero() {
+            scatter_direction = record.normal.to_vec3();
         }
 
-        *scattered = Ray::new(record.point, scatter_direction, record.in_ray.time());
+        *scattered = record.spawn_ray(scatter_direction);
         *attenuation = self.texture.sample(&record.uv, &record.point);
 
         true
     }
+
+    fn scattering(&self, record: &HitRecord, direction: &Vec3) -> Color {
+        self.texture.sample(&record.uv, &record.point) * self.scattering_pdf(record, direction)
+    }
+
+    fn scattering_pdf(&self, record: &HitRecord, direction: &Vec3) -> Float {
+        // Scattering along the normal plus a random unit vector
+        // picks directions with a cosine distribution
+        let cosine = record.normal.dot(direction.normalize());
+        Float::max(cosine, 0.0) / PI
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
 pub struct Metal {
-    albedo: glm::DVec3,
-    fuzz: f64,
+    albedo: Vec3,
+    fuzz: Float,
 }
 
 impl Metal {
-    pub fn new(albedo: glm::DVec3, fuzz: f64) -> Self {
+    pub fn new(albedo: Vec3, fuzz: Float) -> Self {
         Self {
             albedo,
-            fuzz: f64::min(fuzz, 1.0),
+            fuzz: Float::min(fuzz, 1.0),
         }
     }
 }
 
 impl Material for Metal {
-    fn scatter(
-        &self,
-        record: &mut HitRecord,
-        attenuation: &mut glm::DVec3,
-        scattered: &mut Ray,
-    ) -> bool {
-        let mut reflected_direction = glm::reflect(record.in_ray.direction(), record.normal);
-        reflected_direction = glm::normalize(reflected_direction);
-        reflected_direction = reflected_direction + vector::random_unit_sphere_vec() * self.fuzz;
+    fn scatter(&self, record: &mut HitRecord, attenuation: &mut Vec3, scattered: &mut Ray) -> bool {
+        let mut reflected_direction = record.in_ray.direction().reflect(record.normal);
+        reflected_direction = reflected_direction.normalize();
+        reflected_direction += vector::random_unit_sphere_vec() * self.fuzz;
 
-        *scattered = Ray::new(record.point, reflected_direction, record.in_ray.time());
+        *scattered = record.spawn_ray(reflected_direction);
         *attenuation = self.albedo;
 
-        glm::dot(reflected_direction, record.normal) > 0.0
+        reflected_direction.dot(record.normal) > 0.0
+    }
+
+    fn is_specular(&self) -> bool {
+        true
     }
 }
 
 pub mod refraction_indices {
-    pub const AIR: f64 = 1.0;
-    pub const GLASS: f64 = 1.5;
-    pub const WATER: f64 = 1.33;
+    use super::RefractiveIndex;
+    use crate::vector::Float;
+
+    pub const AIR: Float = 1.0;
+    pub const GLASS: Float = 1.5;
+    pub const WATER: Float = 1.33;
+
+    /// Glass with an index of 1.5 at the reference wavelength, like `GLASS`,
+    /// that spreads white light out into a rainbow about as much as flint glass
+    pub const DISPERSIVE_GLASS: RefractiveIndex = RefractiveIndex::Cauchy {
+        a: 1.4712,
+        b: 0.0100,
+    };
+}
+
+/// How a material's refractive index changes with the wavelength of light
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum RefractiveIndex {
+    /// The same index for every wavelength
+    Constant(Float),
+    /// Cauchy's equation, n = a + b / λ², with λ in micrometers
+    Cauchy { a: Float, b: Float },
+    /// The Sellmeier equation, n² = 1 + Σ bᵢλ² / (λ² - cᵢ), with λ in micrometers
+    Sellmeier { b: [Float; 3], c: [Float; 3] },
+}
+
+impl RefractiveIndex {
+    /// The wavelength glass catalogs quote refractive indices at,
+    /// the yellow sodium D line, in nanometers.
+    /// Renders that don't track wavelengths see this index.
+    pub const REFERENCE_WAVELENGTH: Float = 589.3;
+
+    /// The index for light of `wavelength` in nanometers
+    pub fn at(&self, wavelength: Float) -> Float {
+        let micrometers = wavelength / 1000.0;
+        let squared = micrometers * micrometers;
+
+        match self {
+            RefractiveIndex::Constant(index) => *index,
+            RefractiveIndex::Cauchy { a, b } => a + b / squared,
+            RefractiveIndex::Sellmeier { b, c } => {
+                let sum: Float = (0..3).map(|i| b[i] * squared / (squared - c[i])).sum();
+                (1.0 + sum).sqrt()
+            }
+        }
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
 pub struct Dielectric {
-    refraction_index: f64,
+    refraction_index: RefractiveIndex,
 }
 
 impl Dielectric {
-    pub fn new(refraction_index: f64) -> Self {
+    pub fn new(refraction_index: Float) -> Self {
+        Self::dispersive(RefractiveIndex::Constant(refraction_index))
+    }
+
+    /// Glass that bends shorter wavelengths more than longer ones,
+    /// which only shows in spectral renders
+    pub fn dispersive(refraction_index: RefractiveIndex) -> Self {
         Self { refraction_index }
     }
 
-    fn reflectance(&self, cosine: f64) -> f64 {
-        let r0 = (1.0 - self.refraction_index) / (1.0 + self.refraction_index).powf(2.0);
+    fn reflectance(refraction_index: Float, cosine: Float) -> Float {
+        let r0 = (1.0 - refraction_index) / (1.0 + refraction_index).powf(2.0);
         r0 + (1.0 - r0) * (1.0 - cosine).powf(5.0)
     }
 }
 
 impl Material for Dielectric {
-    fn scatter(
+    fn scatter(&self, record: &mut HitRecord, attenuation: &mut Vec3, scattered: &mut Ray) -> bool {
+        self.scatter_wavelength(
+            record,
+            RefractiveIndex::REFERENCE_WAVELENGTH,
+            attenuation,
+            scattered,
+        )
+    }
+
+    fn scatter_wavelength(
         &self,
         record: &mut HitRecord,
-        attenuation: &mut glm::DVec3,
+        wavelength: Float,
+        attenuation: &mut Color,
         scattered: &mut Ray,
     ) -> bool {
-        let mut rng = rand::thread_rng();
+        let mut rng = random::rng();
+        let refraction_index = self.refraction_index.at(wavelength);
 
-        let ri: f64 = match record.front_face {
-            true => self.refraction_index.recip(),
-            false => self.refraction_index,
+        let ri: Float = match record.front_face {
+            true => refraction_index.recip(),
+            false => refraction_index,
         };
 
-        let unit_direction = glm::normalize(record.in_ray.direction());
+        let unit_direction = record.in_ray.direction().normalize();
 
-        let cos_theta = f64::min(glm::dot(-unit_direction, record.normal), 1.0);
+        let cos_theta = Float::min((-unit_direction).dot(record.normal), 1.0);
         let sin_theta = (1.0 - cos_theta.powf(2.0)).sqrt();
 
-        let should_reflect = ri * sin_theta > 1.0 || self.reflectance(cos_theta) > rng.gen();
+        let should_reflect =
+            ri * sin_theta > 1.0 || Self::reflectance(refraction_index, cos_theta) > rng.gen();
 
         let direction = match should_reflect {
-            true => glm::reflect(unit_direction, record.normal),
-            false => glm::refract(unit_direction, record.normal, ri),
+            true => unit_direction.reflect(record.normal),
+            false => unit_direction.refract(record.normal, ri),
         };
 
-        *scattered = Ray::new(record.point, direction, record.in_ray.time());
-        *attenuation = vector::one_vec3();
+        *scattered = record.spawn_ray(direction);
+        *attenuation = Vec3::ONE;
+
+        true
+    }
 
+    fn is_specular(&self) -> bool {
         true
     }
+
+    fn is_dispersive(&self) -> bool {
+        !matches!(self.refraction_index, RefractiveIndex::Constant(_))
+    }
 }
 
 pub struct DiffuseLight {
@@ -171,7 +292,7 @@ impl DiffuseLight {
         Self::from_texture(Arc::new(SolidColor::from(color)))
     }
 
-    pub fn from_color_components(r: f64, g: f64, b: f64) -> Self {
+    pub fn from_color_components(r: Float, g: Float, b: Float) -> Self {
         Self::from_color(Color::new(r, g, b))
     }
 }
@@ -180,13 +301,13 @@ impl Material for DiffuseLight {
     fn scatter(
         &self,
         _record: &mut HitRecord,
-        _attenuation: &mut glm::DVec3,
+        _attenuation: &mut Vec3,
         _scattered: &mut Ray,
     ) -> bool {
         false
     }
 
-    fn emitted(&self, uv: &Vec2, point: &Vec3) -> Color {
+    fn emitted(&self, uv: &Vec2, point: &Point3) -> Color {
         self.texture.sample(uv, point)
     }
 }
@@ -208,20 +329,23 @@ impl Isotropic {
 }
 
 impl Material for Isotropic {
-    fn scatter(
-        &self,
-        record: &mut HitRecord,
-        attenuation: &mut glm::DVec3,
-        scattered: &mut Ray,
-    ) -> bool {
-        *scattered = Ray::new(
-            record.point,
-            vector::random_unit_vec3(),
-            record.in_ray.time(),
-        );
+    fn scatter(&self, record: &mut HitRecord, attenuation: &mut Vec3, scattered: &mut Ray) -> bool {
+        *scattered = record.spawn_ray(vector::random_unit_sphere_vec());
 
         *attenuation = self.texture.sample(&record.uv, &record.point);
 
         true
     }
+
+    fn scattering(&self, record: &HitRecord, direction: &Vec3) -> Color {
+        self.texture.sample(&record.uv, &record.point) * self.scattering_pdf(record, direction)
+    }
+
+    fn scattering_pdf(&self, _record: &HitRecord, _direction: &Vec3) -> Float {
+        (4.0 * PI).recip()
+    }
+
+    fn is_volumetric(&self) -> bool {
+        true
+    }
 }
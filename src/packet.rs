@@ -0,0 +1,318 @@
+//! Tracing bundles of coherent rays through the scene together
+//!
+//! Camera rays through neighbouring pixels start out from about the same
+//! point and go about the same way, so they mostly visit the same BVH nodes.
+//! A packet of them walks the tree together: a cheap test of a box against
+//! the whole packet at once, using interval arithmetic over the packet's
+//! origins and directions, skips boxes none of the rays can go through,
+//! and only the boxes that pass get tested against the rays one by one.
+//!
+//! Once rays bounce off what they hit they go every which way, so packets
+//! are only used to find where camera rays first hit something, and paths
+//! carry on one ray at a time from there. Rays that might reach something
+//! whose hits are random, like a volume, are given up on and traced on their
+//! own as well, so the random numbers get drawn in the same order either way.
+
+use crate::aabb::{AABB, EXIT_SCALE};
+use crate::hittable::{HitRecord, HittableObject};
+use crate::material::Material;
+use crate::ray::{Ray, MIN_HIT_DISTANCE};
+use crate::vector::Float;
+
+use std::{cell::Cell, ops::Range};
+
+/// How many pixels next to each other in a row share a packet
+pub const PACKET_PIXELS: usize = 8;
+
+/// How many samples of each of those pixels go into a packet
+pub const PACKET_SAMPLES: u32 = 8;
+
+/// Rays that get traced through the scene together
+#[derive(Default)]
+pub struct RayPacket {
+    rays: Vec<Ray>,
+    origins: Vec<[Float; 3]>,
+    inverse_directions: Vec<[Float; 3]>,
+    origin_min: [Float; 3],
+    origin_max: [Float; 3],
+    inverse_direction_min: [Float; 3],
+    inverse_direction_max: [Float; 3],
+    /// Which rays have to be traced on their own instead
+    given_up: Vec<Cell<bool>>,
+}
+
+impl RayPacket {
+    pub fn new(rays: Vec<Ray>) -> Self {
+        let mut packet = Self::default();
+        packet.fill(rays);
+        packet
+    }
+
+    /// Make this packet hold `rays` instead, reusing its memory
+    pub fn fill(&mut self, rays: impl IntoIterator<Item = Ray>) {
+        self.rays.clear();
+        self.rays.extend(rays);
+
+        self.origins.clear();
+        self.origins.extend(
+            self.rays
+                .iter()
+                .map(|ray| [ray.origin().x, ray.origin().y, ray.origin().z]),
+        );
+
+        self.inverse_directions.clear();
+        self.inverse_directions.extend(self.rays.iter().map(|ray| {
+            [ray.direction().x, ray.direction().y, ray.direction().z].map(Float::recip)
+        }));
+
+        self.given_up.clear();
+        self.given_up.resize(self.rays.len(), Cell::new(false));
+
+        let min = |values: &[[Float; 3]], axis: usize| {
+            values
+                .iter()
+                .fold(Float::INFINITY, |min, value| min.min(value[axis]))
+        };
+        let max = |values: &[[Float; 3]], axis: usize| {
+            values
+                .iter()
+                .fold(Float::NEG_INFINITY, |max, value| max.max(value[axis]))
+        };
+
+        self.origin_min = [0, 1, 2].map(|axis| min(&self.origins, axis));
+        self.origin_max = [0, 1, 2].map(|axis| max(&self.origins, axis));
+        self.inverse_direction_min = [0, 1, 2].map(|axis| min(&self.inverse_directions, axis));
+        self.inverse_direction_max = [0, 1, 2].map(|axis| max(&self.inverse_directions, axis));
+    }
+
+    pub fn rays(&self) -> &[Ray] {
+        &self.rays
+    }
+
+    pub fn len(&self) -> usize {
+        self.rays.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rays.is_empty()
+    }
+
+    /// Leave finding where the ray at `index` hits to tracing it on its own
+    pub fn give_up(&self, index: usize) {
+        self.given_up[index].set(true);
+    }
+
+    /// Whether the ray at `index` has to be traced on its own,
+    /// whatever hit the packet found for it
+    pub fn gave_up(&self, index: usize) -> bool {
+        self.given_up[index].get()
+    }
+
+    /// Whether all the rays go the same way along `axis`,
+    /// which is what culling boxes for the whole packet relies on
+    fn coherent_along(&self, axis: usize) -> bool {
+        let (min, max) = (
+            self.inverse_direction_min[axis],
+            self.inverse_direction_max[axis],
+        );
+
+        min.is_finite() && max.is_finite() && (min > 0.0 || max < 0.0)
+    }
+
+    /// Whether the rays are coherent enough for tracing them together to pay off
+    pub fn is_coherent(&self) -> bool {
+        (0..3).any(|axis| self.coherent_along(axis))
+    }
+
+    /// The least distance along any of the rays at which one of them could
+    /// go into the box from `min` to `max` before `end`, or `None` if none of
+    /// them can. May find rays going into boxes they miss, but never the other
+    /// way around.
+    pub fn enters(&self, min: &[Float; 3], max: &[Float; 3], end: Float) -> Option<Float> {
+        let mut start = MIN_HIT_DISTANCE;
+        let mut end = end;
+
+        for axis in 0..3 {
+            if !self.coherent_along(axis) {
+                continue;
+            }
+
+            let (near, far) = match self.inverse_direction_min[axis] > 0.0 {
+                true => (min[axis], max[axis]),
+                false => (max[axis], min[axis]),
+            };
+
+            let inverse_direction =
+                self.inverse_direction_min[axis]..self.inverse_direction_max[axis];
+
+            // The soonest any ray can get into the slab, and the latest any can leave it
+            let (enter, _) = product(
+                near - self.origin_max[axis]..near - self.origin_min[axis],
+                &inverse_direction,
+            );
+            let (_, leave) = product(
+                far - self.origin_max[axis]..far - self.origin_min[axis],
+                &inverse_direction,
+            );
+
+            start = Float::max(start, enter);
+            end = Float::min(end, leave * EXIT_SCALE);
+        }
+
+        match start <= end {
+            true => Some(start),
+            false => None,
+        }
+    }
+
+    /// Whether the ray at `index` goes through the box from `min` to `max` within `range`
+    #[inline]
+    pub fn hits(
+        &self,
+        index: usize,
+        min: &[Float; 3],
+        max: &[Float; 3],
+        range: &Range<Float>,
+    ) -> bool {
+        let origin = &self.origins[index];
+        let inverse_direction = &self.inverse_directions[index];
+
+        let mut start = range.start;
+        let mut end = range.end;
+
+        for axis in 0..3 {
+            let t0 = (min[axis] - origin[axis]) * inverse_direction[axis];
+            let t1 = (max[axis] - origin[axis]) * inverse_direction[axis];
+
+            start = Float::max(start, Float::min(t0, t1));
+            end = Float::min(end, Float::max(t0, t1) * EXIT_SCALE);
+        }
+
+        start < end
+    }
+}
+
+/// The least and greatest of everything in `a` times everything in `b`
+fn product(a: Range<Float>, b: &Range<Float>) -> (Float, Float) {
+    let products = [
+        a.start * b.start,
+        a.start * b.end,
+        a.end * b.start,
+        a.end * b.end,
+    ];
+
+    (
+        products.into_iter().fold(Float::INFINITY, Float::min),
+        products.into_iter().fold(Float::NEG_INFINITY, Float::max),
+    )
+}
+
+/// How far along the ray `record` has found a hit so far
+pub fn hit_distance(record: &Option<HitRecord>) -> Float {
+    record.as_ref().map_or(Float::INFINITY, |record| record.t)
+}
+
+/// Trace every ray of `packet` through `object` on its own,
+/// keeping whichever hits are closer than the ones in `records`
+pub fn hit_separately<'a, T: HittableObject + ?Sized>(
+    object: &'a T,
+    packet: &RayPacket,
+    records: &mut [Option<HitRecord<'a>>],
+) {
+    let random_hits = object.has_random_hits();
+    let aabb = object.bounding_box();
+    let min = [aabb.x.start, aabb.y.start, aabb.z.start];
+    let max = [aabb.x.end, aabb.y.end, aabb.z.end];
+
+    let Some(first) = packet.rays().first() else {
+        return;
+    };
+    let mut candidate = HitRecord::new(first);
+
+    for (i, (ray, record)) in packet.rays().iter().zip(records.iter_mut()).enumerate() {
+        if packet.gave_up(i) {
+            continue;
+        }
+
+        if random_hits {
+            if packet.hits(i, &min, &max, &(MIN_HIT_DISTANCE..hit_distance(record))) {
+                packet.give_up(i);
+            }
+
+            continue;
+        }
+
+        candidate.in_ray = *ray;
+
+        if object.hit(
+            ray,
+            &(MIN_HIT_DISTANCE..hit_distance(record)),
+            &mut candidate,
+        ) {
+            *record = Some(candidate);
+        }
+    }
+}
+
+/// The scene, except that where one ray first hits it is already known,
+/// because that ray was traced in a packet
+pub struct KnownHit<'a> {
+    pub world: &'a dyn HittableObject,
+    pub ray: Ray,
+    pub record: Option<HitRecord<'a>>,
+}
+
+impl KnownHit<'_> {
+    /// Whether the known hit settles where `ray` first hits something within
+    /// `range`. It does for the packet's own ray, as long as the range doesn't
+    /// start closer than the packet looked, or past the known hit.
+    fn knows(&self, ray: &Ray, range: &Range<Float>) -> bool {
+        range.start >= MIN_HIT_DISTANCE
+            && range.start <= hit_distance(&self.record)
+            && ray.origin() == self.ray.origin()
+            && ray.direction() == self.ray.direction()
+            && ray.time() == self.ray.time()
+    }
+
+    /// The known hit, if it's within `range`
+    fn known_within(&self, range: &Range<Float>) -> Option<&HitRecord<'_>> {
+        self.record
+            .as_ref()
+            .filter(|known| range.contains(&known.t))
+    }
+}
+
+impl HittableObject for KnownHit<'_> {
+    fn hit<'a>(&'a self, ray: &Ray, range: &Range<Float>, record: &mut HitRecord<'a>) -> bool {
+        if !self.knows(ray, range) {
+            return self.world.hit(ray, range, record);
+        }
+
+        match self.known_within(range) {
+            Some(known) => {
+                *record = *known;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn occluded(&self, ray: &Ray, range: &Range<Float>) -> bool {
+        match self.knows(ray, range) {
+            true => self.known_within(range).is_some(),
+            false => self.world.occluded(ray, range),
+        }
+    }
+
+    fn bounding_box(&self) -> &AABB {
+        self.world.bounding_box()
+    }
+
+    fn materials<'a>(&'a self, materials: &mut Vec<&'a dyn Material>) {
+        self.world.materials(materials);
+    }
+
+    fn bvh_boxes(&self, depth: usize, boxes: &mut Vec<AABB>) {
+        self.world.bvh_boxes(depth, boxes);
+    }
+}
@@ -0,0 +1,374 @@
+//! Caustics by photon mapping
+//!
+//! Light focused by glass or a mirror onto a diffuse surface is very hard to
+//! find from the camera: a path would have to leave the surface in just the
+//! right direction to make it through the glass and onto a light.
+//! So before the camera takes its samples, photons get shot out of the lights,
+//! and every photon that lands on a diffuse surface after passing through or
+//! off something specular is stored in a kd-tree. Camera paths then add up the
+//! photons near each diffuse surface they hit, and stop counting light they
+//! find through specular bounces that the photons already stand for.
+//!
+//! The photon map is progressive: every few samples per pixel get a fresh
+//! set of photons and a slightly smaller radius to look for them in,
+//! so the blur from averaging nearby photons fades as the render goes on.
+//! Only light from the scene's lights ends up in photons; caustics of the
+//! sky are still left to the camera paths to find.
+
+use crate::hittable::HitRecord;
+use crate::integrator::{power_heuristic, Integrator, SceneView};
+use crate::random;
+use crate::ray::Ray;
+use crate::stats::{self, Counter};
+use crate::vector::{consts::PI, Color, Float, Point3, Vec3};
+
+use rand::Rng;
+use rayon::prelude::*;
+use std::{
+    ops::Range,
+    sync::{Arc, RwLock},
+};
+
+/// How many samples per pixel share the same photons
+const SAMPLES_PER_GENERATION: u32 = 8;
+
+/// How much of the photons found so far each generation keeps,
+/// which decides how quickly the search radius shrinks
+const RADIUS_ALPHA: Float = 2.0 / 3.0;
+
+/// Keeps photon seeds apart from the camera's sample seeds
+const PHOTON_SEED: u64 = 0x7068_6f74_6f6e_7321;
+
+#[derive(Debug, Copy, Clone)]
+struct Photon {
+    position: Point3,
+    /// Which way the photon was travelling when it landed
+    direction: Vec3,
+    power: Color,
+}
+
+/// Photons sorted into an implicit kd-tree: the photon in the middle of any
+/// range splits it in two along `axes[middle]`, and both halves are trees too
+struct PhotonMap {
+    photons: Vec<Photon>,
+    axes: Vec<u8>,
+}
+
+fn component(v: &Point3, axis: u8) -> Float {
+    match axis {
+        0 => v.x,
+        1 => v.y,
+        _ => v.z,
+    }
+}
+
+impl PhotonMap {
+    fn new(mut photons: Vec<Photon>) -> Self {
+        let mut axes = vec![0; photons.len()];
+        Self::build(&mut photons, &mut axes);
+
+        Self { photons, axes }
+    }
+
+    fn build(photons: &mut [Photon], axes: &mut [u8]) {
+        if photons.len() <= 1 {
+            return;
+        }
+
+        // Split along whichever axis the photons are most spread out on
+        let mut min = photons[0].position;
+        let mut max = photons[0].position;
+
+        for photon in photons.iter() {
+            min = min.min(photon.position);
+            max = max.max(photon.position);
+        }
+
+        let extent = max - min;
+        let axis = match (
+            extent.x >= extent.y,
+            extent.x >= extent.z,
+            extent.y >= extent.z,
+        ) {
+            (true, true, _) => 0,
+            (false, _, true) => 1,
+            _ => 2,
+        };
+
+        let middle = photons.len() / 2;
+        photons.select_nth_unstable_by(middle, |a, b| {
+            component(&a.position, axis).total_cmp(&component(&b.position, axis))
+        });
+        axes[middle] = axis;
+
+        let (left, right) = photons.split_at_mut(middle);
+        let (left_axes, right_axes) = axes.split_at_mut(middle);
+        Self::build(left, left_axes);
+        Self::build(&mut right[1..], &mut right_axes[1..]);
+    }
+
+    /// Call `visit` with every photon within `radius` of `point`
+    fn gather(&self, point: &Point3, radius: Float, visit: &mut impl FnMut(&Photon)) {
+        self.gather_range(0..self.photons.len(), point, radius, visit);
+    }
+
+    fn gather_range(
+        &self,
+        range: Range<usize>,
+        point: &Point3,
+        radius: Float,
+        visit: &mut impl FnMut(&Photon),
+    ) {
+        if range.is_empty() {
+            return;
+        }
+
+        let middle = range.start + range.len() / 2;
+        let photon = &self.photons[middle];
+
+        if (photon.position - *point).length_squared() <= radius * radius {
+            visit(photon);
+        }
+
+        let axis = self.axes[middle];
+        let offset = component(point, axis) - component(&photon.position, axis);
+
+        let (near, far) = match offset < 0.0 {
+            true => (range.start..middle, middle + 1..range.end),
+            false => (middle + 1..range.end, range.start..middle),
+        };
+
+        self.gather_range(near, point, radius, visit);
+
+        if offset.abs() <= radius {
+            self.gather_range(far, point, radius, visit);
+        }
+    }
+
+    /// The caustic light leaving a diffuse surface towards where `record`'s ray came from,
+    /// averaged over a disk of the given `radius`
+    fn radiance(&self, record: &HitRecord, radius: Float) -> Color {
+        let mut total = Vec3::ZERO;
+
+        self.gather(&record.point, radius, &mut |photon| {
+            let to_light = -photon.direction;
+            let cosine = record.normal.dot(to_light);
+
+            // Photons that landed on the other side of the surface
+            if cosine <= 0.0 {
+                return;
+            }
+
+            total += record.mat.scattering(record, &to_light) / cosine * photon.power;
+        });
+
+        total / (PI * radius * radius)
+    }
+}
+
+/// Shoot a photon from a light, and return where it lands
+/// if it went through or off something specular first
+fn trace_photon(scene: &SceneView, photon_count: usize) -> Option<Photon> {
+    let time = random::rng().gen();
+    let emitted = scene.emit_light_ray(time)?;
+
+    let cosine = Float::abs(emitted.normal.dot(emitted.ray.direction()));
+    let mut power = emitted.emission * cosine
+        / (emitted.point_pdf * emitted.direction_pdf * photon_count as Float);
+    let mut ray = emitted.ray;
+
+    for bounce in 0..scene.max_depth {
+        let mut record = scene.hit(&ray)?;
+        let mat = record.mat;
+
+        if !mat.is_specular() {
+            // Light that goes straight from a light to a diffuse surface
+            // is easy enough to find from the camera
+            if bounce == 0 || mat.is_volumetric() {
+                return None;
+            }
+
+            return Some(Photon {
+                position: record.point,
+                direction: ray.direction().normalize(),
+                power,
+            });
+        }
+
+        let mut scattered = Ray::new(Point3::ORIGIN, Vec3::ZERO, ray.time());
+        let mut attenuation = Vec3::ZERO;
+
+        if !mat.scatter(&mut record, &mut attenuation, &mut scattered) {
+            return None;
+        }
+
+        power *= attenuation;
+        ray = scattered;
+    }
+
+    None
+}
+
+/// A path tracer with next event estimation that gets its caustics from a
+/// progressive photon map, so glass and mirrors cast sharp caustics
+/// from small lights long before paths from the camera would find them
+pub struct PhotonMapping {
+    /// How many photons to shoot for each generation of the photon map
+    pub photons_per_generation: usize,
+    /// How far from a point the first generation looks for photons,
+    /// later generations look a little less far
+    pub initial_radius: Float,
+    /// The photon maps for the samples currently being taken, by generation
+    maps: RwLock<Vec<(u32, Arc<PhotonMap>)>>,
+}
+
+impl PhotonMapping {
+    pub fn new(photons_per_generation: usize, initial_radius: Float) -> Self {
+        Self {
+            photons_per_generation,
+            initial_radius,
+            maps: RwLock::new(Vec::new()),
+        }
+    }
+
+    /// The search radius for a generation.
+    /// Shrinks so that the averaged generations converge to the right image,
+    /// see Knaus and Zwicker, "Progressive Photon Mapping: A Probabilistic Approach".
+    fn radius(&self, generation: u32) -> Float {
+        let shrink: Float = (1..=generation)
+            .map(|i| (i as Float + RADIUS_ALPHA) / (i as Float + 1.0))
+            .product();
+
+        self.initial_radius * shrink.sqrt()
+    }
+
+    fn shoot_photons(&self, scene: &SceneView, seed: u64, generation: u32) -> PhotonMap {
+        let photons = (0..self.photons_per_generation)
+            .into_par_iter()
+            .filter_map(|i| {
+                random::seed(random::sample_seed(
+                    seed ^ PHOTON_SEED,
+                    i,
+                    generation as usize,
+                ));
+                trace_photon(scene, self.photons_per_generation)
+            })
+            .collect();
+
+        PhotonMap::new(photons)
+    }
+}
+
+impl Integrator for PhotonMapping {
+    fn prepare(&self, scene: &SceneView, seed: u64, samples: Range<u32>) {
+        if samples.is_empty() {
+            return;
+        }
+
+        let generations =
+            (samples.start / SAMPLES_PER_GENERATION)..=((samples.end - 1) / SAMPLES_PER_GENERATION);
+
+        let mut maps = self.maps.write().unwrap();
+        maps.retain(|(generation, _)| generations.contains(generation));
+
+        for generation in generations {
+            if !maps.iter().any(|(existing, _)| *existing == generation) {
+                let map = self.shoot_photons(scene, seed, generation);
+                maps.push((generation, Arc::new(map)));
+            }
+        }
+    }
+
+    fn radiance(&self, ray: &Ray, scene: &SceneView, sample: u32) -> Color {
+        let generation = sample / SAMPLES_PER_GENERATION;
+        let radius = self.radius(generation);
+        let map = self
+            .maps
+            .read()
+            .unwrap()
+            .iter()
+            .find(|(existing, _)| *existing == generation)
+            .map(|(_, map)| map.clone());
+
+        let mut color = Vec3::ZERO;
+        let mut throughput = Vec3::ONE;
+        let mut ray = *ray;
+
+        // How likely the last bounce was to pick the current ray,
+        // or `None` if sampling a light could never have found it
+        let mut scattering_pdf: Option<Float> = None;
+
+        // Whether the last diffuse surface looked up photons,
+        // and only specular bounces followed it.
+        // Any light this ray finds on a light is already in the photon map then.
+        let mut in_photon_map = false;
+
+        // Whether the last diffuse vertex looked up photons
+        let mut after_photon_lookup = false;
+
+        // The rays we may still trace, counting the current one
+        for depth in (1..=scene.max_depth).rev() {
+            let Some(mut record) = scene.hit(&ray) else {
+                color += throughput * scene.background_color;
+                break;
+            };
+
+            let emission = record.mat.emitted(&record.uv, &record.point);
+
+            if !emission.is_near_zero() {
+                let light_pdf = || scene.light_pdf(&ray.origin(), &ray.direction(), ray.time());
+
+                let weight = match scattering_pdf {
+                    _ if in_photon_map && light_pdf() > 0.0 => 0.0,
+                    Some(pdf) => power_heuristic(pdf, light_pdf()),
+                    None => 1.0,
+                };
+
+                color += throughput * emission * weight;
+            }
+
+            let mat = record.mat;
+
+            if !mat.is_specular() {
+                after_photon_lookup = false;
+
+                if let Some(map) = map.as_ref().filter(|_| !mat.is_volumetric()) {
+                    color += throughput * map.radiance(&record, radius);
+                    after_photon_lookup = true;
+                }
+
+                // Only aim for a light if the path is allowed one more ray
+                if depth > 1 {
+                    if let Some(light) = scene.sample_light(&record) {
+                        let scattering = mat.scattering(&record, &light.direction);
+                        let weight = power_heuristic(
+                            light.pdf,
+                            mat.scattering_pdf(&record, &light.direction),
+                        );
+
+                        color += throughput * scattering * light.emission * (weight / light.pdf);
+                    }
+                }
+            }
+
+            let mut scattered = Ray::new(Point3::ORIGIN, Vec3::ZERO, ray.time());
+            let mut attenuation = Vec3::ZERO;
+
+            if !mat.scatter(&mut record, &mut attenuation, &mut scattered) {
+                break;
+            }
+
+            scattering_pdf = match mat.is_specular() {
+                true => None,
+                false => Some(mat.scattering_pdf(&record, &scattered.direction())),
+            };
+            in_photon_map = mat.is_specular() && after_photon_lookup;
+
+            throughput *= attenuation;
+            stats::count(Counter::SecondaryRays);
+            ray = scattered;
+        }
+
+        color
+    }
+}
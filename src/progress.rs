@@ -0,0 +1,77 @@
+//! Keeping an eye on renders while they run, and stopping them early
+
+use crate::film::Film;
+use crate::stats::Stats;
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+/// A snapshot of how far along a render is
+#[derive(Debug, Copy, Clone)]
+pub struct Progress {
+    /// The pass currently being rendered, starting at 1
+    pub pass: usize,
+    /// Samples per pixel taken so far, averaged over the whole image
+    pub samples_per_pixel: f64,
+    /// How much of the render is done, from 0 to 1,
+    /// if the stopping criteria let us estimate it
+    pub fraction: Option<f64>,
+    pub elapsed: Duration,
+    /// Estimated time until the render is done
+    pub eta: Option<Duration>,
+    /// The film's mean relative error as of the last finished pass
+    pub mean_relative_error: Option<f64>,
+}
+
+/// How a render ended up
+#[derive(Debug, Copy, Clone)]
+pub struct RenderSummary {
+    /// The fewest samples taken by any pixel
+    pub samples_per_pixel: u32,
+    pub mean_relative_error: Option<f64>,
+    pub elapsed: Duration,
+    pub cancelled: bool,
+    /// How much work went into the render
+    pub stats: Stats,
+}
+
+/// Gets told about a render's progress.
+/// Calls can come from any of the rendering threads: every so often
+/// in the middle of a pass, and once more when the pass is done.
+pub trait ProgressObserver: Send + Sync {
+    fn on_start(&self) {}
+
+    fn on_progress(&self, _progress: &Progress) {}
+
+    /// Called after every pass, with the film as it stands
+    fn on_pass(&self, _progress: &Progress, _film: &Film) {}
+
+    fn on_finish(&self, _summary: &RenderSummary) {}
+}
+
+/// Stops a render cleanly from another thread.
+/// Clones share the same flag, so hand a clone to the render
+/// and keep one around to cancel it with.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
@@ -2,33 +2,43 @@
 
 use crate::{
     aabb::AABB,
-    hittable::{HitRecord, HittableList, HittableObject},
+    hittable::{HitRecord, HittableList, HittableObject, SurfaceSample},
     material::Material,
-    ray::Ray,
-    vector,
-    vector::{Vec2, Vec3},
+    random,
+    ray::{self, Ray},
+    stats::{self, Counter},
+    vector::{Float, Normal3, Point3, Vec2, Vec3},
 };
-use glm;
+use rand::Rng;
 use std::{ops::Range, sync::Arc};
 
-const EPSILON: f64 = 1e-8;
-
 #[derive(Clone)]
 pub struct Quad {
-    point: Vec3,
+    point: Point3,
     u: Vec3,
     v: Vec3,
     material: Arc<dyn Material>,
     aabb: AABB,
-    normal: Vec3,
-    d: f64,
+    normal: Normal3,
+    d: Float,
     w: Vec3,
+    /// How far past its edges, in `uv`, a hit can still count. Rounding can
+    /// put hits right on an edge just outside it, and where two quads meet
+    /// that would let rays slip through the crack between them.
+    edge_margin: Vec2,
 }
 
 impl Quad {
-    pub fn new(point: Vec3, u: Vec3, v: Vec3, material: Arc<dyn Material>) -> Self {
-        let n = glm::cross(u, v);
-        let normal = glm::normalize(n);
+    pub fn new(point: Point3, u: Vec3, v: Vec3, material: Arc<dyn Material>) -> Self {
+        let n = u.cross(v);
+        let normal = Normal3::from(n.normalize());
+        let w = n / n.length_squared();
+
+        // Hits come out up to about this far off along each axis, see
+        // `point_at`, and `w` turns that into how far off alpha and beta are
+        let far_corner = point.abs() + u.abs() + v.abs();
+        let error = far_corner.x.max(far_corner.y).max(far_corner.z) * ray::gamma(16);
+        let edge_margin = Vec2::new(error * v.cross(w).length(), error * w.cross(u).length());
 
         Self {
             point,
@@ -37,20 +47,21 @@ impl Quad {
             material,
             aabb: create_bounding_box(&point, &u, &v),
             normal,
-            d: glm::dot(normal, point),
-            w: n / glm::ext::sqlength(n),
+            d: normal.dot(point.to_vec3()),
+            w,
+            edge_margin,
         }
     }
 
     pub fn box_from_opposite_corners(
-        a: Vec3,
-        b: Vec3,
+        a: Point3,
+        b: Point3,
         material: Arc<dyn Material>,
     ) -> Arc<dyn HittableObject> {
         let mut sides = HittableList::default();
 
-        let min = vector::min_vec3(&a, &b);
-        let max = vector::max_vec3(&a, &b);
+        let min = a.min(b);
+        let max = a.max(b);
         let diff = max - min;
 
         let dx = Vec3::new(diff.x, 0.0, 0.0);
@@ -58,42 +69,42 @@ impl Quad {
         let dz = Vec3::new(0.0, 0.0, diff.z);
 
         sides.add(Arc::new(Self::new(
-            Vec3::new(min.x, min.y, max.z),
+            Point3::new(min.x, min.y, max.z),
             dx,
             dy,
             material.clone(),
         )));
 
         sides.add(Arc::new(Self::new(
-            Vec3::new(max.x, min.y, max.z),
+            Point3::new(max.x, min.y, max.z),
             -dz,
             dy,
             material.clone(),
         )));
 
         sides.add(Arc::new(Self::new(
-            Vec3::new(max.x, min.y, min.z),
+            Point3::new(max.x, min.y, min.z),
             -dx,
             dy,
             material.clone(),
         )));
 
         sides.add(Arc::new(Self::new(
-            Vec3::new(min.x, min.y, min.z),
+            Point3::new(min.x, min.y, min.z),
             dz,
             dy,
             material.clone(),
         )));
 
         sides.add(Arc::new(Self::new(
-            Vec3::new(min.x, max.y, max.z),
+            Point3::new(min.x, max.y, max.z),
             dx,
             -dz,
             material.clone(),
         )));
 
         sides.add(Arc::new(Self::new(
-            Vec3::new(min.x, min.y, min.z),
+            Point3::new(min.x, min.y, min.z),
             dx,
             dz,
             material,
@@ -101,47 +112,98 @@ impl Quad {
 
         Arc::new(sides)
     }
-}
 
-impl HittableObject for Quad {
-    fn hit(&self, ray: &Ray, range: &Range<f64>, record: &mut HitRecord) -> bool {
-        let denominator = glm::dot(self.normal, ray.direction());
+    /// How far along `ray` it hits the quad within `range`, if it does,
+    /// and where on the quad
+    fn intersect(&self, ray: &Ray, range: &Range<Float>) -> Option<(Float, Vec2)> {
+        stats::count(Counter::QuadTests);
 
-        if f64::abs(denominator) < EPSILON {
-            return false;
+        // Rays parallel to the quad end up with an infinite or NaN `t`,
+        // which is never in range
+        let denominator = self.normal.dot(ray.direction());
+        let t = (self.d - self.normal.dot(ray.origin().to_vec3())) / denominator;
+
+        if !range.contains(&t) {
+            return None;
         }
 
-        let t = (self.d - glm::dot(self.normal, ray.origin())) / denominator;
+        let planar_hit_point_vector = ray.at(t) - self.point;
+        let alpha = self.w.dot(planar_hit_point_vector.cross(self.v));
+        let beta = self.w.dot(self.u.cross(planar_hit_point_vector));
 
-        if !range.contains(&t) {
-            return false;
+        let margin = self.edge_margin;
+
+        if !(-margin.x..=1.0 + margin.x).contains(&alpha)
+            || !(-margin.y..=1.0 + margin.y).contains(&beta)
+        {
+            return None;
         }
 
-        let intersection = ray.at(t);
+        Some((t, Vec2::new(alpha.clamp(0.0, 1.0), beta.clamp(0.0, 1.0))))
+    }
 
-        let planar_hit_point_vector = intersection - self.point;
-        let alpha = glm::dot(self.w, glm::cross(planar_hit_point_vector, self.v));
-        let beta = glm::dot(self.w, glm::cross(self.u, planar_hit_point_vector));
+    /// The point at `uv` on the quad, with how far off it might be along each axis.
+    /// Worked out from the corner and sides rather than along the ray,
+    /// so it's on the quad's plane up to rounding, however far the ray went.
+    fn point_at(&self, uv: &Vec2) -> (Point3, Vec3) {
+        let (u, v) = (self.u * uv.x, self.v * uv.y);
+        let error = (self.point.abs() + u.abs() + v.abs()) * ray::gamma(7);
+
+        (self.point + u + v, error)
+    }
+}
 
-        if !(0.0..1.0).contains(&alpha) || !(0.0..1.0).contains(&beta) {
+impl HittableObject for Quad {
+    fn hit<'a>(&'a self, ray: &Ray, range: &Range<Float>, record: &mut HitRecord<'a>) -> bool {
+        let Some((t, uv)) = self.intersect(ray, range) else {
             return false;
-        }
+        };
+
+        let (point, error) = self.point_at(&uv);
 
         record.t = t;
-        record.point = intersection;
-        record.mat = self.material.clone();
+        record.point = point;
+        record.error = error;
+        record.mat = &*self.material;
         record.set_normal(ray, &self.normal);
-        record.uv = Vec2::new(alpha, beta);
+        record.uv = uv;
 
         true
     }
 
+    fn occluded(&self, ray: &Ray, range: &Range<Float>) -> bool {
+        self.intersect(ray, range).is_some()
+    }
+
     fn bounding_box(&self) -> &AABB {
         &self.aabb
     }
+
+    fn materials<'a>(&'a self, materials: &mut Vec<&'a dyn Material>) {
+        materials.push(&*self.material);
+    }
+
+    fn sample_surface(&self, _time: Float) -> Option<SurfaceSample<'_>> {
+        let mut rng = random::rng();
+        let uv = Vec2::new(rng.gen(), rng.gen());
+        let (point, error) = self.point_at(&uv);
+
+        Some(SurfaceSample {
+            point,
+            error,
+            normal: self.normal,
+            uv,
+            mat: &*self.material,
+            pdf: self.surface_area().recip(),
+        })
+    }
+
+    fn surface_area(&self) -> Float {
+        self.u.cross(self.v).length()
+    }
 }
 
-fn create_bounding_box(point: &Vec3, u: &Vec3, v: &Vec3) -> AABB {
+fn create_bounding_box(point: &Point3, u: &Vec3, v: &Vec3) -> AABB {
     let pu = *point + *u;
     let pv = *point + *v;
     let puv = pu + *v;
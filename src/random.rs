@@ -0,0 +1,70 @@
+//! Seedable, thread-local random number generation
+//!
+//! Works just like `rand::thread_rng`, except the generator can be reseeded.
+//! The camera reseeds it before every sample so that each sample is fully
+//! determined by its pixel and sample index, no matter which thread traces it
+//! or how the render is split up.
+
+use rand::{rngs::SmallRng, Error, RngCore, SeedableRng};
+use std::cell::RefCell;
+
+thread_local! {
+    static RNG: RefCell<SmallRng> = RefCell::new(SmallRng::from_entropy());
+}
+
+/// A handle to the current thread's generator
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SampleRng;
+
+impl RngCore for SampleRng {
+    fn next_u32(&mut self) -> u32 {
+        RNG.with(|rng| rng.borrow_mut().next_u32())
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        RNG.with(|rng| rng.borrow_mut().next_u64())
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        RNG.with(|rng| rng.borrow_mut().fill_bytes(dest))
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        RNG.with(|rng| rng.borrow_mut().try_fill_bytes(dest))
+    }
+}
+
+pub fn rng() -> SampleRng {
+    SampleRng
+}
+
+/// Reseed the current thread's generator
+pub fn seed(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = SmallRng::seed_from_u64(seed));
+}
+
+/// The current thread's generator as it is now,
+/// for carrying on from there later with `restore`
+pub fn save() -> SmallRng {
+    RNG.with(|rng| rng.borrow().clone())
+}
+
+/// Put the current thread's generator back the way `save` found it
+pub fn restore(state: SmallRng) {
+    RNG.with(|rng| *rng.borrow_mut() = state);
+}
+
+/// Derive the seed for a single sample of a single pixel
+pub fn sample_seed(seed: u64, pixel: usize, sample: usize) -> u64 {
+    let pixel_seed = mix(seed ^ mix(pixel as u64));
+    mix(pixel_seed ^ (sample as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15))
+}
+
+// SplitMix64 finalizer, so that neighbouring pixels and samples
+// get completely unrelated seeds.
+fn mix(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
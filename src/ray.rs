@@ -1,16 +1,71 @@
 //! A definition for a ray
+//!
+//! Rays that leave a surface don't start exactly at the point they hit it,
+//! since rounding leaves that point a little off the real surface, possibly
+//! on the wrong side of it. Instead, every hit works out how far off its point
+//! might be along each axis, and rays leaving it start from just far enough
+//! along the normal that they can't hit the same surface again, the way PBRT
+//! does it. Unlike skipping a fixed distance along the ray, this works the
+//! same however big or small the scene is.
 
-use crate::vector::Vec3;
+use crate::vector::{Float, Normal3, Point3, Vec3};
+
+/// How far along a ray a hit has to be to count. Rays leaving surfaces
+/// start off them, see `offset_origin`, so nothing needs skipping, but hits
+/// do have to be in front of the origin. Points that come out exact, like
+/// ones on a quad lined up with the axes, don't get moved at all, and rays
+/// from them hit the same quad right at the origin.
+pub const MIN_HIT_DISTANCE: Float = Float::MIN_POSITIVE;
+
+/// A bound on the relative error `n` floating-point operations in a row
+/// can add up to
+pub const fn gamma(n: u32) -> Float {
+    let e = n as Float * Float::EPSILON * 0.5;
+    e / (1.0 - e)
+}
+
+/// Where a ray leaving `point` along `direction` should start, given that
+/// the point might be up to `error` off the surface with the given `normal`
+/// along each axis. The point gets moved along the normal, to whichever side
+/// of the surface the ray goes, until the box of places the surface could be
+/// is behind it.
+pub fn offset_origin(point: &Point3, error: &Vec3, normal: &Normal3, direction: &Vec3) -> Point3 {
+    let distance = normal.abs().dot(*error);
+
+    let offset = match direction.dot(*normal) < 0.0 {
+        true => normal.to_vec3() * -distance,
+        false => normal.to_vec3() * distance,
+    };
+
+    // Adding the offset rounds too, so push each coordinate one more step away
+    let round_away = |value: Float, offset: Float| {
+        if offset > 0.0 {
+            value.next_up()
+        } else if offset < 0.0 {
+            value.next_down()
+        } else {
+            value
+        }
+    };
+
+    let origin = *point + offset;
+
+    Point3::new(
+        round_away(origin.x, offset.x),
+        round_away(origin.y, offset.y),
+        round_away(origin.z, offset.z),
+    )
+}
 
 #[derive(Clone, Copy, Debug)]
 pub struct Ray {
-    origin: Vec3,
+    origin: Point3,
     direction: Vec3,
-    time: f64,
+    time: Float,
 }
 
 impl Ray {
-    pub fn new(origin: glm::DVec3, direction: Vec3, time: f64) -> Ray {
+    pub fn new(origin: Point3, direction: Vec3, time: Float) -> Ray {
         Self {
             origin,
             direction,
@@ -18,11 +73,11 @@ impl Ray {
         }
     }
 
-    pub fn at(&self, t: f64) -> Vec3 {
+    pub fn at(&self, t: Float) -> Point3 {
         self.origin + self.direction * t
     }
 
-    pub fn origin(&self) -> Vec3 {
+    pub fn origin(&self) -> Point3 {
         self.origin
     }
 
@@ -30,7 +85,7 @@ impl Ray {
         self.direction
     }
 
-    pub fn time(&self) -> f64 {
+    pub fn time(&self) -> Float {
         self.time
     }
 }
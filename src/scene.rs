@@ -1 +1,812 @@
-//! A definition for a scene full of objects to render
+//! A definition for a scene full of objects to render, and the built-in scenes
+
+use crate::accelerator::Accelerator;
+use crate::camera::CameraSettings;
+use crate::constant_medium::ConstantMedium;
+use crate::hittable::{HittableList, HittableObject, RotateY, Translate};
+use crate::instance::{Instance, Transform};
+use crate::material::{refraction_indices, Dielectric, DiffuseLight, Lambertian, Material, Metal};
+use crate::quad::Quad;
+use crate::random;
+use crate::sphere::Sphere;
+use crate::texture::{CheckerBoard, ImageTexture, NoiseTexture};
+use crate::vector;
+use crate::vector::{Color, Float, Point3, Vec3};
+
+use rand::Rng;
+use std::sync::Arc;
+
+pub struct Scene {
+    pub world: Arc<dyn HittableObject>,
+    /// Emissive objects in `world` that integrators can aim rays at
+    pub lights: Vec<Arc<dyn HittableObject>>,
+    pub camera_settings: CameraSettings,
+}
+
+fn checkered_spheres_scene() -> Scene {
+    let mut scene = HittableList::default();
+
+    let checker_texture = Arc::new(CheckerBoard::from_colors(
+        0.32,
+        Color::new(0.2, 0.3, 0.1),
+        Color::new(0.9, 0.9, 0.9),
+    ));
+
+    let checker_texture = Arc::new(Lambertian::from_texture(checker_texture));
+
+    scene.add(Arc::new(Sphere::new(
+        Point3::new(0.0, -10.0, 0.0),
+        Vec3::ZERO,
+        10.0,
+        checker_texture.clone(),
+    )));
+
+    scene.add(Arc::new(Sphere::new(
+        Point3::new(0.0, 10.0, 0.0),
+        Vec3::ZERO,
+        10.0,
+        checker_texture.clone(),
+    )));
+
+    Scene {
+        world: Arc::new(scene),
+        lights: Vec::new(),
+        camera_settings: CameraSettings {
+            position: Point3::new(13.0, 2.0, 3.0),
+            target_position: Point3::ORIGIN,
+            up_direction: Vec3::Y,
+            fov: 20.0,
+            aspect_ratio: 16.0 / 9.0,
+            defocus_angle: 0.6,
+            focus_dist: 10.0,
+            background_color: Color::new(0.7, 0.8, 1.0),
+        },
+    }
+}
+
+fn earth_scene() -> Scene {
+    let mut scene = HittableList::default();
+
+    // let earth_texture_neat = Arc::new(ImageTexture::new("./images/earth-map-neat.jpg"));
+    // let earth_surface_neat = Arc::new(Lambertian::from_texture(earth_texture_neat));
+
+    let earth_texture_realistic = Arc::new(ImageTexture::new("./images/earth-realistic.jpg"));
+    let earth_surface_realistic = Arc::new(Lambertian::from_texture(earth_texture_realistic));
+
+    let scifi_planet_texture_realistic = Arc::new(ImageTexture::new("./images/scifi-planet.jpg"));
+    let scifi_planet_surface_realistic =
+        Arc::new(Lambertian::from_texture(scifi_planet_texture_realistic));
+
+    scene.add(Arc::new(Sphere::new(
+        Point3::new(-2.3, 0.0, 0.0),
+        Vec3::ZERO,
+        2.0,
+        earth_surface_realistic,
+    )));
+
+    scene.add(Arc::new(Sphere::new(
+        Point3::new(2.3, 0.0, 0.0),
+        Vec3::ZERO,
+        2.0,
+        scifi_planet_surface_realistic,
+    )));
+
+    // scene.add(Arc::new(Sphere::new(
+    //     Vec3::new(2.0, 0.0, 0.0),
+    //     Vec3::ZERO,
+    //     1.5,
+    //     earth_surface_neat,
+    // )));
+
+    Scene {
+        world: Arc::new(scene),
+        lights: Vec::new(),
+        camera_settings: CameraSettings {
+            position: Point3::new(-4.0, -2.0, 9.0),
+            target_position: Point3::ORIGIN,
+            up_direction: Vec3::Y,
+            fov: 35.0,
+            aspect_ratio: 16.0 / 9.0,
+            defocus_angle: 0.6,
+            focus_dist: 10.0,
+            background_color: Color::new(0.7, 0.8, 1.0),
+        },
+    }
+}
+
+fn bouncing_balls_scene(accelerator: &Accelerator) -> Scene {
+    let mut rng = random::rng();
+
+    let mut scene = HittableList::default();
+
+    // Ground
+    let checker_texture = Arc::new(CheckerBoard::from_colors(
+        0.32,
+        Color::new(0.2, 0.3, 0.1),
+        Color::new(0.9, 0.9, 0.9),
+    ));
+
+    // let material_ground = Arc::new(Lambertian::from_color_components(0.5, 0.5, 0.5));
+    let material_ground = Arc::new(Lambertian::from_texture(checker_texture));
+
+    scene.add(Arc::new(Sphere::new(
+        Point3::new(0.0, -1000.0, 0.0),
+        Vec3::ZERO,
+        1000.0,
+        material_ground,
+    )));
+
+    for a in -11..11 {
+        for b in -11..11 {
+            let choose_mat: Float = rng.gen();
+
+            let center = Point3::new(
+                a as Float + 0.9 * rng.gen::<Float>(),
+                0.2,
+                b as Float + 0.9 * rng.gen::<Float>(),
+            );
+
+            if (center - Point3::new(4.0, 0.2, 0.0)).length_squared() > 0.81 {
+                let mut velocity = Vec3::ZERO;
+
+                let sphere_material: Arc<dyn Material> = match choose_mat {
+                    choose_mat if choose_mat < 0.8 => {
+                        let albedo = vector::random_vec3(0.0..1.0) * vector::random_vec3(0.0..1.0);
+                        velocity.y = rng.gen_range(0.0..0.5);
+                        Arc::new(Lambertian::from_color(albedo))
+                    }
+                    choose_mat if choose_mat < 0.9 => {
+                        let albedo = vector::random_vec3(0.5..1.0);
+                        let fuzz: Float = rng.gen_range(0.0..0.5);
+                        Arc::new(Metal::new(albedo, fuzz))
+                    }
+                    _ => Arc::new(Dielectric::new(refraction_indices::GLASS)),
+                };
+
+                scene.add(Arc::new(Sphere::new(
+                    center,
+                    velocity,
+                    0.2,
+                    sphere_material,
+                )));
+            }
+        }
+    }
+
+    let material1 = Arc::new(Dielectric::dispersive(refraction_indices::DISPERSIVE_GLASS));
+
+    scene.add(Arc::new(Sphere::new(
+        Point3::new(0.0, 1.0, 0.0),
+        Vec3::ZERO,
+        1.0,
+        material1,
+    )));
+
+    let material2 = Arc::new(Lambertian::from_color(Vec3::new(0.4, 0.2, 0.1)));
+
+    scene.add(Arc::new(Sphere::new(
+        Point3::new(-4.0, 1.0, 0.0),
+        Vec3::ZERO,
+        1.0,
+        material2,
+    )));
+
+    let material3 = Arc::new(Metal::new(Vec3::new(0.4, 0.6, 0.5), 0.0));
+
+    scene.add(Arc::new(Sphere::new(
+        Point3::new(4.0, 1.0, 0.0),
+        Vec3::ZERO,
+        1.0,
+        material3,
+    )));
+
+    Scene {
+        world: accelerator.build(scene.objects()),
+        lights: Vec::new(),
+        camera_settings: CameraSettings {
+            position: Point3::new(13.0, 2.0, 3.0),
+            target_position: Point3::ORIGIN,
+            up_direction: Vec3::Y,
+            fov: 20.0,
+            aspect_ratio: 16.0 / 9.0,
+            defocus_angle: 0.6,
+            focus_dist: 10.0,
+            background_color: Color::new(0.7, 0.8, 1.0),
+        },
+    }
+}
+
+fn perlin_spheres_scene() -> Scene {
+    let perlin_texture = Arc::new(NoiseTexture::new(0, 4.0, 10.0, 6, 1.0, 1.0));
+    let perlin_material = Arc::new(Lambertian::from_texture(perlin_texture));
+
+    let mut scene = HittableList::default();
+
+    scene.add(Arc::new(Sphere::new(
+        Point3::new(0.0, -1000.0, 0.0),
+        Vec3::ZERO,
+        1000.0,
+        perlin_material.clone(),
+    )));
+
+    scene.add(Arc::new(Sphere::new(
+        Point3::new(0.0, 2.0, 0.0),
+        Vec3::ZERO,
+        2.0,
+        perlin_material.clone(),
+    )));
+
+    Scene {
+        world: Arc::new(scene),
+        lights: Vec::new(),
+        camera_settings: CameraSettings {
+            position: Point3::new(13.0, 2.0, 3.0),
+            target_position: Point3::new(0.0, 0.0, 0.0),
+            up_direction: Vec3::Y,
+            fov: 20.0,
+            aspect_ratio: 16.0 / 9.0,
+            defocus_angle: 0.0,
+            focus_dist: 10.0,
+            background_color: Color::new(0.7, 0.8, 1.0),
+        },
+    }
+}
+
+fn simple_light_scene() -> Scene {
+    let perlin_texture = Arc::new(NoiseTexture::new(0, 4.0, 10.0, 6, 1.0, 1.0));
+    let perlin_material = Arc::new(Lambertian::from_texture(perlin_texture));
+
+    let mut scene = HittableList::default();
+
+    scene.add(Arc::new(Sphere::new(
+        Point3::new(0.0, -1000.0, 0.0),
+        Vec3::ZERO,
+        1000.0,
+        perlin_material.clone(),
+    )));
+
+    scene.add(Arc::new(Sphere::new(
+        Point3::new(0.0, 2.0, 0.0),
+        Vec3::ZERO,
+        2.0,
+        perlin_material.clone(),
+    )));
+
+    let diff_light_material = Arc::new(DiffuseLight::from_color_components(4.0, 4.0, 4.0));
+
+    let light_quad: Arc<dyn HittableObject> = Arc::new(Quad::new(
+        Point3::new(3.0, 1.0, -2.0),
+        Vec3::new(2.0, 0.0, 0.0),
+        Vec3::new(0.0, 2.0, 0.0),
+        diff_light_material.clone(),
+    ));
+
+    let light_sphere: Arc<dyn HittableObject> = Arc::new(Sphere::new(
+        Point3::new(0.0, 7.0, 0.0),
+        Vec3::ZERO,
+        2.0,
+        diff_light_material.clone(),
+    ));
+
+    scene.add(light_quad.clone());
+    scene.add(light_sphere.clone());
+
+    Scene {
+        world: Arc::new(scene),
+        lights: vec![light_quad, light_sphere],
+        camera_settings: CameraSettings {
+            position: Point3::new(26.0, 3.0, 6.0),
+            target_position: Point3::new(0.0, 2.0, 0.0),
+            up_direction: Vec3::Y,
+            fov: 20.0,
+            aspect_ratio: 16.0 / 9.0,
+            defocus_angle: 0.6,
+            focus_dist: 10.0,
+            background_color: Color::new(0.0, 0.0, 0.0),
+        },
+    }
+}
+
+fn quads_scene() -> Scene {
+    let left_red = Arc::new(Lambertian::from_color_components(1.0, 0.2, 0.2));
+    let back_green = Arc::new(Lambertian::from_color_components(0.2, 1.0, 0.2));
+    let right_blue = Arc::new(Lambertian::from_color_components(0.2, 0.2, 1.0));
+    let upper_orange = Arc::new(Lambertian::from_color_components(1.0, 0.5, 0.0));
+    let lower_teal = Arc::new(Lambertian::from_color_components(0.2, 0.8, 0.8));
+
+    let mut scene = HittableList::default();
+
+    scene.add(Arc::new(Quad::new(
+        Point3::new(-3.0, -2.0, 5.0),
+        Vec3::new(0.0, 0.0, -4.0),
+        Vec3::new(0.0, 4.0, 0.0),
+        left_red,
+    )));
+
+    scene.add(Arc::new(Quad::new(
+        Point3::new(-2.0, -2.0, 0.0),
+        Vec3::new(4.0, 0.0, 0.0),
+        Vec3::new(0.0, 4.0, 0.0),
+        back_green,
+    )));
+
+    scene.add(Arc::new(Quad::new(
+        Point3::new(3.0, -2.0, 1.0),
+        Vec3::new(0.0, 0.0, 4.0),
+        Vec3::new(0.0, 4.0, 0.0),
+        right_blue,
+    )));
+
+    scene.add(Arc::new(Quad::new(
+        Point3::new(-2.0, 3.0, 1.0),
+        Vec3::new(4.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, 4.0),
+        upper_orange,
+    )));
+
+    scene.add(Arc::new(Quad::new(
+        Point3::new(-2.0, -3.0, 5.0),
+        Vec3::new(4.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, -4.0),
+        lower_teal,
+    )));
+
+    Scene {
+        world: Arc::new(scene),
+        lights: Vec::new(),
+        camera_settings: CameraSettings {
+            position: Point3::new(0.0, 0.0, 9.0),
+            target_position: Point3::ORIGIN,
+            up_direction: Vec3::Y,
+            fov: 80.0,
+            aspect_ratio: 1.0,
+            defocus_angle: 0.6,
+            focus_dist: 10.0,
+            background_color: Color::new(0.7, 0.8, 1.0),
+        },
+    }
+}
+
+fn cornell_box_scene() -> Scene {
+    let red = Arc::new(Lambertian::from_color_components(0.65, 0.05, 0.05));
+    let white = Arc::new(Lambertian::from_color_components(0.73, 0.73, 0.73));
+    let green = Arc::new(Lambertian::from_color_components(0.12, 0.45, 0.15));
+    let light = Arc::new(DiffuseLight::from_color_components(15.0, 15.0, 15.0));
+
+    let mut scene = HittableList::default();
+
+    scene.add(Arc::new(Quad::new(
+        Point3::new(555.0, 0.0, 0.0),
+        Vec3::new(0.0, 555.0, 0.0),
+        Vec3::new(0.0, 0.0, 555.0),
+        green,
+    )));
+
+    scene.add(Arc::new(Quad::new(
+        Point3::new(0.0, 0.0, 0.0),
+        Vec3::new(0.0, 555.0, 0.0),
+        Vec3::new(0.0, 0.0, 555.0),
+        red,
+    )));
+
+    let light_quad: Arc<dyn HittableObject> = Arc::new(Quad::new(
+        Point3::new(343.0, 554.0, 332.0),
+        Vec3::new(-130.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, -105.0),
+        light,
+    ));
+
+    scene.add(light_quad.clone());
+
+    scene.add(Arc::new(Quad::new(
+        Point3::new(0.0, 0.0, 0.0),
+        Vec3::new(555.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, 555.0),
+        white.clone(),
+    )));
+
+    scene.add(Arc::new(Quad::new(
+        Point3::new(555.0, 555.0, 555.0),
+        Vec3::new(-555.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, -555.0),
+        white.clone(),
+    )));
+
+    scene.add(Arc::new(Quad::new(
+        Point3::new(0.0, 0.0, 555.0),
+        Vec3::new(555.0, 0.0, 0.0),
+        Vec3::new(0.0, 555.0, 0.0),
+        white.clone(),
+    )));
+
+    let mut box1 = Quad::box_from_opposite_corners(
+        Point3::new(0.0, 0.0, 0.0),
+        Point3::new(165.0, 330.0, 165.0),
+        white.clone(),
+    );
+
+    box1 = Arc::new(RotateY::new(box1, 15.0));
+    box1 = Arc::new(Translate::new(box1, Vec3::new(265.0, 0.0, 295.0)));
+
+    scene.add(box1);
+
+    let mut box2 = Quad::box_from_opposite_corners(
+        Point3::new(0.0, 0.0, 0.0),
+        Point3::new(165.0, 165.0, 165.0),
+        white.clone(),
+    );
+
+    box2 = Arc::new(RotateY::new(box2, -18.0));
+    box2 = Arc::new(Translate::new(box2, Vec3::new(130.0, 0.0, 65.0)));
+
+    scene.add(box2);
+
+    Scene {
+        world: Arc::new(scene),
+        lights: vec![light_quad],
+        camera_settings: CameraSettings {
+            position: Point3::new(278.0, 278.0, -800.0),
+            target_position: Point3::new(278.0, 278.0, 0.0),
+            up_direction: Vec3::Y,
+            fov: 40.0,
+            aspect_ratio: 1.0,
+            defocus_angle: 0.0,
+            focus_dist: 10.0,
+            background_color: Color::new(0.0, 0.0, 0.0),
+        },
+    }
+}
+
+fn cornell_smoke_box_scene() -> Scene {
+    let red = Arc::new(Lambertian::from_color_components(0.65, 0.05, 0.05));
+    let white = Arc::new(Lambertian::from_color_components(0.73, 0.73, 0.73));
+    let green = Arc::new(Lambertian::from_color_components(0.12, 0.45, 0.15));
+    let light = Arc::new(DiffuseLight::from_color_components(7.0, 7.0, 7.0));
+
+    let mut scene = HittableList::default();
+
+    scene.add(Arc::new(Quad::new(
+        Point3::new(555.0, 0.0, 0.0),
+        Vec3::new(0.0, 555.0, 0.0),
+        Vec3::new(0.0, 0.0, 555.0),
+        green,
+    )));
+
+    scene.add(Arc::new(Quad::new(
+        Point3::new(0.0, 0.0, 0.0),
+        Vec3::new(0.0, 555.0, 0.0),
+        Vec3::new(0.0, 0.0, 555.0),
+        red,
+    )));
+
+    let light_quad: Arc<dyn HittableObject> = Arc::new(Quad::new(
+        Point3::new(113.0, 554.0, 127.0),
+        Vec3::new(330.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, 305.0),
+        light,
+    ));
+
+    scene.add(light_quad.clone());
+
+    scene.add(Arc::new(Quad::new(
+        Point3::new(0.0, 0.0, 0.0),
+        Vec3::new(555.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, 555.0),
+        white.clone(),
+    )));
+
+    scene.add(Arc::new(Quad::new(
+        Point3::new(555.0, 555.0, 555.0),
+        Vec3::new(-555.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, -555.0),
+        white.clone(),
+    )));
+
+    scene.add(Arc::new(Quad::new(
+        Point3::new(0.0, 0.0, 555.0),
+        Vec3::new(555.0, 0.0, 0.0),
+        Vec3::new(0.0, 555.0, 0.0),
+        white.clone(),
+    )));
+
+    let mut box1 = Quad::box_from_opposite_corners(
+        Point3::new(0.0, 0.0, 0.0),
+        Point3::new(165.0, 330.0, 165.0),
+        white.clone(),
+    );
+
+    box1 = Arc::new(ConstantMedium::from_color(
+        box1,
+        0.01,
+        Color::new(0.0, 0.0, 0.0),
+    ));
+
+    box1 = Arc::new(RotateY::new(box1, 15.0));
+    box1 = Arc::new(Translate::new(box1, Vec3::new(265.0, 0.0, 295.0)));
+
+    scene.add(box1);
+
+    let mut box2 = Quad::box_from_opposite_corners(
+        Point3::new(0.0, 0.0, 0.0),
+        Point3::new(165.0, 165.0, 165.0),
+        white.clone(),
+    );
+
+    box2 = Arc::new(ConstantMedium::from_color(
+        box2,
+        0.01,
+        Color::new(1.0, 1.0, 1.0),
+    ));
+
+    box2 = Arc::new(RotateY::new(box2, -18.0));
+    box2 = Arc::new(Translate::new(box2, Vec3::new(130.0, 0.0, 65.0)));
+
+    scene.add(box2);
+
+    Scene {
+        world: Arc::new(scene),
+        lights: vec![light_quad],
+        camera_settings: CameraSettings {
+            position: Point3::new(278.0, 278.0, -800.0),
+            target_position: Point3::new(278.0, 278.0, 0.0),
+            up_direction: Vec3::Y,
+            fov: 40.0,
+            aspect_ratio: 1.0,
+            defocus_angle: 0.0,
+            focus_dist: 10.0,
+            background_color: Color::new(0.0, 0.0, 0.0),
+        },
+    }
+}
+
+fn final_scene(accelerator: &Accelerator) -> Scene {
+    let mut rng = random::rng();
+
+    let mut scene = HittableList::default();
+
+    // Floor boxes
+    let mut boxes_1 = HittableList::default();
+    let ground = Arc::new(Lambertian::from_color_components(0.48, 0.83, 0.53));
+    let boxes_per_side = 20;
+
+    for i in 0..boxes_per_side {
+        for j in 0..boxes_per_side {
+            let w = 100.0;
+            let a = Point3::new(-1000.0 + i as Float * w, 0.0, -1000.0 + j as Float * w);
+            let b = Point3::new(a.x + w, rng.gen_range(1.0..101.0), a.z + w);
+
+            boxes_1.add(Quad::box_from_opposite_corners(a, b, ground.clone()));
+        }
+    }
+
+    scene.add(accelerator.build(boxes_1.objects()));
+
+    let light = Arc::new(DiffuseLight::from_color_components(7.0, 7.0, 7.0));
+
+    let light_quad: Arc<dyn HittableObject> = Arc::new(Quad::new(
+        Point3::new(123.0, 554.0, 147.0),
+        Vec3::new(300.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, 265.0),
+        light,
+    ));
+
+    scene.add(light_quad.clone());
+
+    // Moving orange ball in the
+    scene.add(Arc::new(Sphere::new(
+        Point3::new(400.0, 400.0, 200.0),
+        Vec3::new(30.0, 0.0, 0.0),
+        50.0,
+        Arc::new(Lambertian::from_color_components(0.73, 0.3, 0.1)),
+    )));
+
+    // Glass ball in the bottom center
+    scene.add(Arc::new(Sphere::new(
+        Point3::new(260.0, 150.0, 45.0),
+        Vec3::ZERO,
+        50.0,
+        Arc::new(Dielectric::dispersive(refraction_indices::DISPERSIVE_GLASS)),
+    )));
+
+    // Metal ball in the bottom right
+    scene.add(Arc::new(Sphere::new(
+        Point3::new(0.0, 150.0, 145.0),
+        Vec3::ZERO,
+        50.0,
+        Arc::new(Metal::new(Color::new(0.8, 0.8, 0.9), 1.0)),
+    )));
+
+    // Blue glass ball in the bottom left
+    let boundary = Arc::new(Sphere::new(
+        Point3::new(360.0, 150.0, 145.0),
+        Vec3::ZERO,
+        70.0,
+        Arc::new(Dielectric::new(1.5)),
+    ));
+
+    // Filling for the ball above
+    scene.add(boundary.clone());
+    scene.add(Arc::new(ConstantMedium::from_color(
+        boundary,
+        0.2,
+        Color::new(0.2, 0.4, 0.9),
+    )));
+
+    // Outer shell of the scene
+    let boundary = Arc::new(Sphere::new(
+        Point3::ORIGIN,
+        Vec3::ZERO,
+        5000.0,
+        Arc::new(Dielectric::new(1.5)),
+    ));
+
+    // // Thin mist around the entire scene
+    scene.add(boundary.clone());
+    scene.add(Arc::new(ConstantMedium::from_color(
+        boundary,
+        1e-4,
+        Color::new(1.0, 1.0, 1.0),
+    )));
+
+    // Earth ball
+    let earth_texture_realistic = Arc::new(ImageTexture::new("./images/earth-realistic.jpg"));
+    let earth_surface_realistic = Arc::new(Lambertian::from_texture(earth_texture_realistic));
+    scene.add(Arc::new(Sphere::new(
+        Point3::new(400.0, 200.0, 400.0),
+        Vec3::ZERO,
+        100.0,
+        earth_surface_realistic,
+    )));
+
+    // Perlin noise ball
+    let perlin_texture = Arc::new(NoiseTexture::new(0, 0.2, 0.1, 6, 1.0, 1.0));
+    let perlin_material = Arc::new(Lambertian::from_texture(perlin_texture));
+
+    scene.add(Arc::new(Sphere::new(
+        Point3::new(220.0, 280.0, 300.0),
+        Vec3::ZERO,
+        80.0,
+        perlin_material,
+    )));
+
+    // Box full of balls in the top right corner
+    let mut boxes_2 = HittableList::default();
+    let white_material = Arc::new(Lambertian::from_color_components(0.73, 0.73, 0.73));
+    let white_sphere = Arc::new(Sphere::new(
+        Point3::ORIGIN,
+        Vec3::ZERO,
+        10.0,
+        white_material.clone(),
+    ));
+
+    let ns = 1000;
+
+    for _ in 0..ns {
+        boxes_2.add(Arc::new(Translate::new(
+            white_sphere.clone(),
+            vector::random_vec3(0.0..165.0),
+        )));
+    }
+
+    scene.add(Arc::new(Translate::new(
+        Arc::new(RotateY::new(accelerator.build(boxes_2.objects()), 15.0)),
+        Vec3::new(-100.0, 270.0, 395.0),
+    )));
+
+    Scene {
+        world: Arc::new(scene),
+        lights: vec![light_quad],
+        camera_settings: CameraSettings {
+            position: Point3::new(478.0, 278.0, -600.0),
+            target_position: Point3::new(278.0, 278.0, 0.0),
+            up_direction: Vec3::Y,
+            fov: 40.0,
+            aspect_ratio: 1.0,
+            defocus_angle: 0.0,
+            focus_dist: 10.0,
+            background_color: Color::new(0.0, 0.0, 0.0),
+        },
+    }
+}
+
+fn forest_scene(accelerator: &Accelerator) -> Scene {
+    let mut rng = random::rng();
+
+    let mut scene = HittableList::default();
+
+    let grass = Arc::new(Lambertian::from_color_components(0.35, 0.5, 0.2));
+    scene.add(Arc::new(Sphere::new(
+        Point3::new(0.0, -1000.0, 0.0),
+        Vec3::ZERO,
+        1000.0,
+        grass,
+    )));
+
+    // One tree, which every instance below shares
+    let bark = Arc::new(Lambertian::from_color_components(0.35, 0.22, 0.12));
+    let leaves = Arc::new(Lambertian::from_color_components(0.15, 0.45, 0.12));
+
+    let mut tree = HittableList::default();
+    tree.add(Quad::box_from_opposite_corners(
+        Point3::new(-0.15, 0.0, -0.15),
+        Point3::new(0.15, 1.5, 0.15),
+        bark,
+    ));
+
+    // A cone of leaves, narrowing towards the top
+    for _ in 0..200 {
+        let height = rng.gen_range(0.0..1.0);
+        let distance = (1.0 - height) * rng.gen_range(0.0..1.2);
+        let angle = rng.gen_range(0.0..vector::consts::TAU);
+
+        tree.add(Arc::new(Sphere::new(
+            Point3::new(
+                distance * angle.cos(),
+                1.2 + height * 2.5,
+                distance * angle.sin(),
+            ),
+            Vec3::ZERO,
+            0.25,
+            leaves.clone(),
+        )));
+    }
+
+    let tree = Arc::new(accelerator.bvh(tree.objects()));
+
+    // Thousands of copies of it, each only as big as its transform
+    let mut forest = HittableList::default();
+    let trees_per_side = 50;
+
+    for i in 0..trees_per_side {
+        for j in 0..trees_per_side {
+            let spacing = 4.0;
+            let position = Vec3::new(
+                (i as Float - trees_per_side as Float / 2.0 + rng.gen_range(-0.3..0.3)) * spacing,
+                0.0,
+                (j as Float - trees_per_side as Float / 2.0 + rng.gen_range(-0.3..0.3)) * spacing,
+            );
+
+            let transform = Transform::identity()
+                .scale(rng.gen_range(0.7..1.3))
+                .rotate(Vec3::Y, rng.gen_range(0.0..360.0))
+                .translate(position);
+
+            forest.add(Arc::new(Instance::new(tree.clone(), transform)));
+        }
+    }
+
+    scene.add(accelerator.build(forest.objects()));
+
+    Scene {
+        world: Arc::new(scene),
+        lights: vec![],
+        camera_settings: CameraSettings {
+            position: Point3::new(-20.0, 12.0, 115.0),
+            target_position: Point3::new(0.0, 2.0, 40.0),
+            up_direction: Vec3::Y,
+            fov: 40.0,
+            aspect_ratio: 16.0 / 9.0,
+            defocus_angle: 0.0,
+            focus_dist: 10.0,
+            background_color: Color::new(0.7, 0.8, 1.0),
+        },
+    }
+}
+
+/// One of the built-in scenes, by its number on the command line,
+/// or `None` if there's no scene with that number
+pub fn build_scene(scene: u8, accelerator: &Accelerator) -> Option<Scene> {
+    match scene {
+        0 => Some(bouncing_balls_scene(accelerator)),
+        1 => Some(checkered_spheres_scene()),
+        2 => Some(earth_scene()),
+        3 => Some(perlin_spheres_scene()),
+        4 => Some(simple_light_scene()),
+        5 => Some(quads_scene()),
+        6 => Some(cornell_box_scene()),
+        7 => Some(cornell_smoke_box_scene()),
+        8 => Some(final_scene(accelerator)),
+        9 => Some(forest_scene(accelerator)),
+        _ => None,
+    }
+}
@@ -0,0 +1,334 @@
+//! Rendering with wavelengths instead of red, green and blue
+//!
+//! Every sample follows a handful of wavelengths spread evenly over the
+//! visible spectrum from a randomly placed first, or hero, wavelength.
+//! Colors in the scene are still given in RGB, and get turned into smooth
+//! spectra wherever the path needs them. Once a path is done, the light found
+//! at each wavelength is weighed by the CIE color matching functions and turned
+//! back into RGB, so the film never needs to know about wavelengths at all.
+//!
+//! When a path goes through glass that bends each wavelength differently,
+//! only the hero wavelength carries on, which is what splits white light
+//! into rainbows.
+
+use crate::integrator::{power_heuristic, Integrator, SceneView};
+use crate::random;
+use crate::ray::Ray;
+use crate::stats::{self, Counter};
+use crate::vector::{Color, Float, Point3, Vec3};
+
+use rand::Rng;
+use std::{
+    ops::{Add, Mul},
+    sync::OnceLock,
+};
+
+/// The shortest wavelength we follow, in nanometers
+pub const WAVELENGTH_MIN: Float = 380.0;
+/// The longest wavelength we follow, in nanometers
+pub const WAVELENGTH_MAX: Float = 780.0;
+
+/// How many wavelengths every path follows
+pub const WAVELENGTH_SAMPLES: usize = 4;
+
+const WAVELENGTH_RANGE: Float = WAVELENGTH_MAX - WAVELENGTH_MIN;
+
+/// The wavelengths a single path follows, in nanometers
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Wavelengths {
+    wavelengths: [Float; WAVELENGTH_SAMPLES],
+    /// Whether only the hero wavelength is still being followed
+    hero_only: bool,
+}
+
+impl Wavelengths {
+    /// Place the hero wavelength `offset` of the way through the visible spectrum,
+    /// and the others at even steps after it, wrapping around at the end
+    pub fn new(offset: Float) -> Self {
+        let step = WAVELENGTH_RANGE / WAVELENGTH_SAMPLES as Float;
+        let wavelengths = std::array::from_fn(|i| {
+            WAVELENGTH_MIN + (offset * WAVELENGTH_RANGE + i as Float * step) % WAVELENGTH_RANGE
+        });
+
+        Self {
+            wavelengths,
+            hero_only: false,
+        }
+    }
+
+    pub fn random() -> Self {
+        Self::new(random::rng().gen())
+    }
+
+    pub fn hero(&self) -> Float {
+        self.wavelengths[0]
+    }
+
+    /// Stop following every wavelength but the hero, for when the path
+    /// takes a turn that only the hero wavelength would have taken
+    pub fn keep_hero_only(&mut self) {
+        self.hero_only = true;
+    }
+
+    /// How many of the wavelengths are still being followed
+    fn count(&self) -> usize {
+        match self.hero_only {
+            true => 1,
+            false => WAVELENGTH_SAMPLES,
+        }
+    }
+
+    /// Turn the light a path found at these wavelengths into linear RGB
+    pub fn to_rgb(&self, spectrum: &SampledSpectrum) -> Color {
+        let mut xyz = Vec3::ZERO;
+
+        for i in 0..self.count() {
+            xyz += color_matching(self.wavelengths[i]) * spectrum.0[i];
+        }
+
+        // Each wavelength was picked with a density of 1 / WAVELENGTH_RANGE
+        let xyz = xyz * (WAVELENGTH_RANGE / self.count() as Float);
+        let rgb = xyz_to_linear_srgb(&xyz);
+        let white = white_point();
+
+        Color::new(rgb.x / white.x, rgb.y / white.y, rgb.z / white.z)
+    }
+}
+
+/// How much of something there is at each of a path's wavelengths
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SampledSpectrum(pub [Float; WAVELENGTH_SAMPLES]);
+
+impl SampledSpectrum {
+    pub fn constant(value: Float) -> Self {
+        Self([value; WAVELENGTH_SAMPLES])
+    }
+
+    /// A smooth spectrum that looks like `rgb`, seen at `wavelengths`
+    pub fn from_rgb(rgb: &Color, wavelengths: &Wavelengths) -> Self {
+        Self(std::array::from_fn(|i| {
+            rgb_to_spectrum(rgb, wavelengths.wavelengths[i])
+        }))
+    }
+}
+
+impl Add for SampledSpectrum {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self(std::array::from_fn(|i| self.0[i] + other.0[i]))
+    }
+}
+
+impl Mul for SampledSpectrum {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        Self(std::array::from_fn(|i| self.0[i] * other.0[i]))
+    }
+}
+
+impl Mul<Float> for SampledSpectrum {
+    type Output = Self;
+
+    fn mul(self, scale: Float) -> Self {
+        Self(self.0.map(|value| value * scale))
+    }
+}
+
+/// Smits' spectra for turning RGB into a spectrum, in ten even steps
+/// from 380 to 720 nanometers, see "An RGB to Spectrum Conversion for Reflectances"
+mod smits {
+    use crate::vector::Float;
+
+    pub const WHITE: [Float; 10] = [
+        1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000,
+    ];
+    pub const CYAN: [Float; 10] = [
+        0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000,
+    ];
+    pub const MAGENTA: [Float; 10] = [
+        1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959,
+    ];
+    pub const YELLOW: [Float; 10] = [
+        0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840,
+    ];
+    pub const RED: [Float; 10] = [
+        0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149,
+    ];
+    pub const GREEN: [Float; 10] = [
+        0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025,
+    ];
+    pub const BLUE: [Float; 10] = [
+        1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496,
+    ];
+
+    pub const FIRST_WAVELENGTH: Float = 380.0;
+    pub const LAST_WAVELENGTH: Float = 720.0;
+}
+
+/// The value at `wavelength` of a smooth spectrum that looks like `rgb`.
+/// Builds the spectrum out of white and the most saturated primaries that fit,
+/// so grays come out flat and the spectrum scales along with `rgb`.
+pub fn rgb_to_spectrum(rgb: &Color, wavelength: Float) -> Float {
+    let position =
+        (wavelength - smits::FIRST_WAVELENGTH) / (smits::LAST_WAVELENGTH - smits::FIRST_WAVELENGTH);
+    let step = ((position * 10.0) as usize).min(9);
+
+    let (r, g, b) = (rgb.x, rgb.y, rgb.z);
+
+    if r <= g && r <= b {
+        let rest = match g <= b {
+            true => (g - r) * smits::CYAN[step] + (b - g) * smits::BLUE[step],
+            false => (b - r) * smits::CYAN[step] + (g - b) * smits::GREEN[step],
+        };
+
+        r * smits::WHITE[step] + rest
+    } else if g <= r && g <= b {
+        let rest = match r <= b {
+            true => (r - g) * smits::MAGENTA[step] + (b - r) * smits::BLUE[step],
+            false => (b - g) * smits::MAGENTA[step] + (r - b) * smits::RED[step],
+        };
+
+        g * smits::WHITE[step] + rest
+    } else {
+        let rest = match r <= g {
+            true => (r - b) * smits::YELLOW[step] + (g - r) * smits::GREEN[step],
+            false => (g - b) * smits::YELLOW[step] + (r - g) * smits::RED[step],
+        };
+
+        b * smits::WHITE[step] + rest
+    }
+}
+
+/// The CIE 1931 color matching functions at `wavelength` in nanometers,
+/// from the multi-lobe fit in Wyman, Sloan and Shirley,
+/// "Simple Analytic Approximations to the CIE XYZ Color Matching Functions"
+pub fn color_matching(wavelength: Float) -> Vec3 {
+    let lobe = |mean: Float, below: Float, above: Float| {
+        let spread = match wavelength < mean {
+            true => below,
+            false => above,
+        };
+        let t = (wavelength - mean) / spread;
+
+        (-0.5 * t * t).exp()
+    };
+
+    Vec3::new(
+        1.056 * lobe(599.8, 37.9, 31.0) + 0.362 * lobe(442.0, 16.0, 26.7)
+            - 0.065 * lobe(501.1, 20.4, 26.2),
+        0.821 * lobe(568.8, 46.9, 40.5) + 0.286 * lobe(530.9, 16.3, 31.1),
+        1.217 * lobe(437.0, 11.8, 36.0) + 0.681 * lobe(459.0, 26.0, 13.8),
+    )
+}
+
+fn xyz_to_linear_srgb(xyz: &Vec3) -> Color {
+    Color::new(
+        3.2404542 * xyz.x - 1.5371385 * xyz.y - 0.4985314 * xyz.z,
+        -0.969266 * xyz.x + 1.8760108 * xyz.y + 0.0415560 * xyz.z,
+        0.0556434 * xyz.x - 0.2040259 * xyz.y + 1.0572252 * xyz.z,
+    )
+}
+
+/// What a flat spectrum of one turns into, so it can be scaled back to plain white
+/// and RGB colors come out of a spectral render looking the same as they went in
+fn white_point() -> Color {
+    static WHITE_POINT: OnceLock<Color> = OnceLock::new();
+
+    *WHITE_POINT.get_or_init(|| {
+        let steps = WAVELENGTH_RANGE as usize;
+        let xyz = (0..steps)
+            .map(|i| color_matching(WAVELENGTH_MIN + i as Float + 0.5))
+            .fold(Vec3::ZERO, |total, xyz| total + xyz);
+
+        xyz_to_linear_srgb(&xyz)
+    })
+}
+
+/// `NextEventEstimation`, following wavelengths instead of RGB,
+/// so glass with a refractive index that depends on the wavelength disperses light
+#[derive(Debug, Copy, Clone, Default)]
+pub struct SpectralPathTracer;
+
+impl Integrator for SpectralPathTracer {
+    fn radiance(&self, ray: &Ray, scene: &SceneView, _sample: u32) -> Color {
+        let mut wavelengths = Wavelengths::random();
+        let spectrum =
+            |rgb: &Color, wavelengths: &Wavelengths| SampledSpectrum::from_rgb(rgb, wavelengths);
+
+        let mut radiance = SampledSpectrum::constant(0.0);
+        let mut throughput = SampledSpectrum::constant(1.0);
+        let mut ray = *ray;
+
+        // How likely the last bounce was to pick the current ray,
+        // or `None` if sampling a light could never have found it
+        let mut scattering_pdf: Option<Float> = None;
+
+        // The rays we may still trace, counting the current one
+        for depth in (1..=scene.max_depth).rev() {
+            let Some(mut record) = scene.hit(&ray) else {
+                radiance = radiance + throughput * spectrum(&scene.background_color, &wavelengths);
+                break;
+            };
+
+            let emission = record.mat.emitted(&record.uv, &record.point);
+
+            if !emission.is_near_zero() {
+                let weight = match scattering_pdf {
+                    Some(pdf) => power_heuristic(
+                        pdf,
+                        scene.light_pdf(&ray.origin(), &ray.direction(), ray.time()),
+                    ),
+                    None => 1.0,
+                };
+
+                radiance = radiance + throughput * spectrum(&emission, &wavelengths) * weight;
+            }
+
+            let mat = record.mat;
+
+            // Only aim for a light if the path is allowed one more ray
+            if depth > 1 && !mat.is_specular() {
+                if let Some(light) = scene.sample_light(&record) {
+                    let scattering = mat.scattering(&record, &light.direction);
+                    let weight =
+                        power_heuristic(light.pdf, mat.scattering_pdf(&record, &light.direction));
+
+                    radiance = radiance
+                        + throughput
+                            * spectrum(&scattering, &wavelengths)
+                            * spectrum(&light.emission, &wavelengths)
+                            * (weight / light.pdf);
+                }
+            }
+
+            if mat.is_dispersive() {
+                wavelengths.keep_hero_only();
+            }
+
+            let mut scattered = Ray::new(Point3::ORIGIN, Vec3::ZERO, ray.time());
+            let mut attenuation = Vec3::ZERO;
+
+            if !mat.scatter_wavelength(
+                &mut record,
+                wavelengths.hero(),
+                &mut attenuation,
+                &mut scattered,
+            ) {
+                break;
+            }
+
+            scattering_pdf = match mat.is_specular() {
+                true => None,
+                false => Some(mat.scattering_pdf(&record, &scattered.direction())),
+            };
+
+            throughput = throughput * spectrum(&attenuation, &wavelengths);
+            stats::count(Counter::SecondaryRays);
+            ray = scattered;
+        }
+
+        wavelengths.to_rgb(&radiance)
+    }
+}
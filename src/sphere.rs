@@ -1,42 +1,42 @@
 //! A definition for a sphere
 
 use crate::aabb::AABB;
-use crate::hittable::{HitRecord, HittableObject};
+use crate::hittable::{HitRecord, HittableObject, SurfaceSample};
 use crate::material::Material;
-use crate::ray::Ray;
-use crate::vector::{Vec2, Vec3};
-use glm;
-use std::{
-    f64::consts::{PI, TAU},
-    ops::Range,
-    sync::Arc,
+use crate::ray::{self, Ray};
+use crate::stats::{self, Counter};
+use crate::vector;
+use crate::vector::{
+    consts::{PI, TAU},
+    Float, Normal3, Point3, Vec2, Vec3,
 };
+use std::{ops::Range, sync::Arc};
 
 #[derive(Clone)]
 pub struct Sphere {
-    center: Vec3,
+    center: Point3,
     velocity: Vec3,
-    radius: f64,
+    radius: Float,
     material: Arc<dyn Material>,
     aabb: AABB,
 }
 
 impl Sphere {
-    pub fn new(center: Vec3, velocity: Vec3, radius: f64, material: Arc<dyn Material>) -> Self {
+    pub fn new(center: Point3, velocity: Vec3, radius: Float, material: Arc<dyn Material>) -> Self {
         let end_point = center + velocity;
 
         let aabb = AABB::new(
             Range {
-                start: f64::min(center.x - radius, center.x + radius),
-                end: f64::max(end_point.x - radius, end_point.x + radius),
+                start: Float::min(center.x - radius, center.x + radius),
+                end: Float::max(end_point.x - radius, end_point.x + radius),
             },
             Range {
-                start: f64::min(center.y - radius, center.y + radius),
-                end: f64::max(end_point.y - radius, end_point.y + radius),
+                start: Float::min(center.y - radius, center.y + radius),
+                end: Float::max(end_point.y - radius, end_point.y + radius),
             },
             Range {
-                start: f64::min(center.z - radius, center.z + radius),
-                end: f64::max(end_point.z - radius, end_point.z + radius),
+                start: Float::min(center.z - radius, center.z + radius),
+                end: Float::max(end_point.z - radius, end_point.z + radius),
             },
         );
 
@@ -49,7 +49,7 @@ impl Sphere {
         }
     }
 
-    fn get_uv(point: &Vec3) -> Vec2 {
+    fn get_uv(point: &Normal3) -> Vec2 {
         let y = -(point.y);
         let z = -(point.z);
 
@@ -58,56 +58,103 @@ impl Sphere {
 
         Vec2::new(phi / TAU, theta / PI)
     }
-}
 
-impl HittableObject for Sphere {
-    fn hit(&self, r: &Ray, range: &Range<f64>, record: &mut HitRecord) -> bool {
-        let oc = (self.center + self.velocity * r.time()) - r.origin();
-        let a = glm::ext::sqlength(r.direction());
-        let h = glm::dot(r.direction(), oc);
-        let c = glm::ext::sqlength(oc) - (self.radius * self.radius);
+    /// How far along `r` it first hits the sphere within `range`, if it does
+    fn root(&self, r: &Ray, range: &Range<Float>) -> Option<Float> {
+        stats::count(Counter::SphereTests);
+
+        let oc = self.center_at(r.time()) - r.origin();
+        let a = r.direction().length_squared();
+        let h = r.direction().dot(oc);
+        let c = oc.length_squared() - (self.radius * self.radius);
 
-        let discriminant = h * h - a * c;
+        // The same as `h * h - a * c`, worked out from how close the ray gets
+        // to the center instead. Taking apart those two products loses most
+        // of the precision for rays that only just hit big spheres, which in
+        // single precision is enough to leave speckled holes in a floor.
+        let closest = (oc - r.direction() * (h / a)).length();
+        let radius = self.radius.abs();
+        let discriminant = a * (radius - closest) * (radius + closest);
 
         // If the discriminant is 0, the ray touches the sphere once.
         // If the discriminant is positive, the ray passes through the
         // sphere, touching the surface twice.
         if discriminant < 0.0 {
-            return false;
+            return None;
         }
 
-        let sqrt_discriminant = discriminant.sqrt();
-
-        // Try the closer intersection first
-        let mut root = (h - sqrt_discriminant) / a;
+        // Written so neither root comes from taking apart two nearly equal
+        // numbers, which would leave roots close to zero with the wrong sign,
+        // and rays leaving the sphere hitting it again
+        let q = h + discriminant.sqrt().copysign(h);
+        let (near, far) = match c / q <= q / a {
+            true => (c / q, q / a),
+            false => (q / a, c / q),
+        };
+
+        // Try the closer intersection first. If neither is in our range
+        // (say, if the sphere is simply behind the camera), it's a miss.
+        [near, far].into_iter().find(|root| range.contains(root))
+    }
 
-        if !range.contains(&root) {
-            // If the closer intersection wasn't in our target range,
-            // check the further intersection.
-            root = (h + sqrt_discriminant) / a;
+    /// Where the sphere's center is at `time`
+    fn center_at(&self, time: Float) -> Point3 {
+        self.center + self.velocity * time
+    }
+}
 
-            if !range.contains(&root) {
-                // If neither intersection is in our range (say, if the sphere
-                // is simply behind the camera), then just return false.
-                return false;
-            }
-        }
+impl HittableObject for Sphere {
+    fn hit<'a>(&'a self, r: &Ray, range: &Range<Float>, record: &mut HitRecord<'a>) -> bool {
+        let Some(root) = self.root(r, range) else {
+            return false;
+        };
 
-        // Reassigning root to make it immutable
-        let root = root;
+        // Moved onto the surface, as the point along the ray can be quite far
+        // off it, and reprojecting is cheaper than keeping track of by how much
+        let center = self.center_at(r.time());
+        let offset = r.at(root) - center;
+        let offset = offset * (self.radius.abs() / offset.length());
 
         record.t = root;
-        record.point = r.at(record.t);
-        record.mat = self.material.clone();
+        record.point = center + offset;
+        record.error = (center.abs() + offset.abs()) * ray::gamma(6);
+        record.mat = &*self.material;
 
-        let outward_normal = (record.point - self.center) / self.radius;
+        let outward_normal = Normal3::from(offset / self.radius);
         record.set_normal(r, &outward_normal);
         record.uv = Self::get_uv(&outward_normal);
 
         true
     }
 
+    fn occluded(&self, r: &Ray, range: &Range<Float>) -> bool {
+        self.root(r, range).is_some()
+    }
+
     fn bounding_box(&self) -> &AABB {
         &self.aabb
     }
+
+    fn materials<'a>(&'a self, materials: &mut Vec<&'a dyn Material>) {
+        materials.push(&*self.material);
+    }
+
+    fn sample_surface(&self, time: Float) -> Option<SurfaceSample<'_>> {
+        let normal = Normal3::from(vector::random_unit_sphere_vec());
+        let center = self.center_at(time);
+        let offset = normal.to_vec3() * self.radius;
+
+        Some(SurfaceSample {
+            point: center + offset,
+            error: (center.abs() + offset.abs()) * ray::gamma(6),
+            normal,
+            uv: Self::get_uv(&normal),
+            mat: &*self.material,
+            pdf: self.surface_area().recip(),
+        })
+    }
+
+    fn surface_area(&self) -> Float {
+        4.0 * PI * self.radius * self.radius
+    }
 }
@@ -0,0 +1,255 @@
+//! Counters for measuring how much work a render does
+//!
+//! Every thread counts into its own thread-local counters, which the camera
+//! collects after each row it renders and merges once the render is done.
+//!
+//! Counting is off unless something turns it on, since the counters sit on
+//! the hottest paths there are. With it off, counting comes down to loading
+//! a flag that never changes, and every count stays at zero.
+
+use crate::encoding::{self, read_u64};
+
+use std::{
+    cell::Cell,
+    fmt::Write,
+    io,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Counter {
+    CameraRays,
+    SecondaryRays,
+    ShadowRays,
+    AabbTests,
+    BvhNodesVisited,
+    SphereTests,
+    QuadTests,
+    ConstantMediumTests,
+    HittableListTests,
+    TranslateTests,
+    RotateYTests,
+    InstanceTests,
+    GridCellsVisited,
+    KdTreeNodesVisited,
+}
+
+impl Counter {
+    pub const ALL: [Counter; 14] = [
+        Counter::CameraRays,
+        Counter::SecondaryRays,
+        Counter::ShadowRays,
+        Counter::AabbTests,
+        Counter::BvhNodesVisited,
+        Counter::SphereTests,
+        Counter::QuadTests,
+        Counter::ConstantMediumTests,
+        Counter::HittableListTests,
+        Counter::TranslateTests,
+        Counter::RotateYTests,
+        Counter::InstanceTests,
+        Counter::GridCellsVisited,
+        Counter::KdTreeNodesVisited,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Counter::CameraRays => "camera_rays",
+            Counter::SecondaryRays => "secondary_rays",
+            Counter::ShadowRays => "shadow_rays",
+            Counter::AabbTests => "aabb_tests",
+            Counter::BvhNodesVisited => "bvh_nodes_visited",
+            Counter::SphereTests => "sphere_tests",
+            Counter::QuadTests => "quad_tests",
+            Counter::ConstantMediumTests => "constant_medium_tests",
+            Counter::HittableListTests => "hittable_list_tests",
+            Counter::TranslateTests => "translate_tests",
+            Counter::RotateYTests => "rotate_y_tests",
+            Counter::InstanceTests => "instance_tests",
+            Counter::GridCellsVisited => "grid_cells_visited",
+            Counter::KdTreeNodesVisited => "kd_tree_nodes_visited",
+        }
+    }
+}
+
+const COUNTER_COUNT: usize = Counter::ALL.len();
+
+thread_local! {
+    static LOCAL_COUNTS: [Cell<u64>; COUNTER_COUNT] = const { [const { Cell::new(0) }; COUNTER_COUNT] };
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Turn counting on or off, for every thread
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether counting is on
+#[inline]
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Add one to a counter on the current thread, if counting is on
+#[inline]
+pub fn count(counter: Counter) {
+    if !enabled() {
+        return;
+    }
+
+    LOCAL_COUNTS.with(|counts| {
+        let cell = &counts[counter as usize];
+        cell.set(cell.get() + 1);
+    });
+}
+
+/// Add `amount` to a counter on the current thread, if counting is on
+#[inline]
+pub fn add(counter: Counter, amount: u64) {
+    if !enabled() {
+        return;
+    }
+
+    LOCAL_COUNTS.with(|counts| {
+        let cell = &counts[counter as usize];
+        cell.set(cell.get() + amount);
+    });
+}
+
+/// How much the current thread has counted on a counter
+/// since its counts were last taken
+pub fn local_count(counter: Counter) -> u64 {
+    LOCAL_COUNTS.with(|counts| counts[counter as usize].get())
+}
+
+/// Take everything the current thread has counted so far,
+/// and start its counters again from zero.
+pub fn take_local() -> Stats {
+    let mut stats = Stats::default();
+
+    LOCAL_COUNTS.with(|counts| {
+        for (total, cell) in stats.counts.iter_mut().zip(counts.iter()) {
+            *total = cell.take();
+        }
+    });
+
+    stats
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Stats {
+    counts: [u64; COUNTER_COUNT],
+}
+
+impl Stats {
+    pub fn get(&self, counter: Counter) -> u64 {
+        self.counts[counter as usize]
+    }
+
+    /// Write out every count, for `read` to read back on another machine
+    pub(crate) fn write(&self, writer: &mut impl io::Write) -> io::Result<()> {
+        for count in self.counts {
+            encoding::write_u64(writer, count)?;
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn read(reader: &mut impl io::Read) -> io::Result<Self> {
+        let mut stats = Self::default();
+
+        for count in stats.counts.iter_mut() {
+            *count = read_u64(reader)?;
+        }
+
+        Ok(stats)
+    }
+
+    pub fn merge(mut self, other: Self) -> Self {
+        for (total, count) in self.counts.iter_mut().zip(other.counts.iter()) {
+            *total += count;
+        }
+
+        self
+    }
+
+    /// Every ray we traced, whether it came from the camera,
+    /// a bounce, or a check for whether a light is visible
+    pub fn total_rays(&self) -> u64 {
+        self.get(Counter::CameraRays)
+            + self.get(Counter::SecondaryRays)
+            + self.get(Counter::ShadowRays)
+    }
+
+    /// How many rays each path was made of, on average,
+    /// not counting shadow rays
+    pub fn average_path_length(&self) -> f64 {
+        let path_rays = self.get(Counter::CameraRays) + self.get(Counter::SecondaryRays);
+
+        match self.get(Counter::CameraRays) {
+            0 => 0.0,
+            camera_rays => path_rays as f64 / camera_rays as f64,
+        }
+    }
+
+    pub fn rays_per_second(&self, elapsed: Duration) -> f64 {
+        match elapsed.is_zero() {
+            true => 0.0,
+            false => self.total_rays() as f64 / elapsed.as_secs_f64(),
+        }
+    }
+
+    /// A human readable summary, one statistic per line
+    pub fn report(&self, elapsed: Duration) -> String {
+        let mut report = String::new();
+
+        for counter in Counter::ALL {
+            writeln!(report, "{:<24}{}", counter.name(), self.get(counter)).unwrap();
+        }
+
+        writeln!(
+            report,
+            "{:<24}{:.3}",
+            "average_path_length",
+            self.average_path_length()
+        )
+        .unwrap();
+
+        writeln!(
+            report,
+            "{:<24}{:.0}",
+            "rays_per_second",
+            self.rays_per_second(elapsed)
+        )
+        .unwrap();
+
+        report
+    }
+
+    pub fn to_json(&self, elapsed: Duration) -> String {
+        let mut json = String::from("{\n");
+
+        for counter in Counter::ALL {
+            writeln!(json, "  \"{}\": {},", counter.name(), self.get(counter)).unwrap();
+        }
+
+        writeln!(
+            json,
+            "  \"average_path_length\": {},",
+            self.average_path_length()
+        )
+        .unwrap();
+        writeln!(
+            json,
+            "  \"rays_per_second\": {},",
+            self.rays_per_second(elapsed)
+        )
+        .unwrap();
+        writeln!(json, "  \"elapsed_seconds\": {}", elapsed.as_secs_f64()).unwrap();
+
+        json.push_str("}\n");
+        json
+    }
+}
@@ -1,14 +1,14 @@
 //! A module for managing textures
 
 use crate::vector;
-use crate::vector::{Color, Vec2, Vec3};
+use crate::vector::{Color, Float, Point3, Vec2};
 use image::{imageops, io::Reader as ImageReader, ImageBuffer, Rgb};
 use noise::{NoiseFn, Perlin, Seedable, Turbulence};
 use std::fmt;
 use std::sync::Arc;
 
 pub trait Texture: fmt::Debug + Send + Sync {
-    fn sample(&self, uv: &Vec2, point: &Vec3) -> Color;
+    fn sample(&self, uv: &Vec2, point: &Point3) -> Color;
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -21,26 +21,26 @@ impl SolidColor {
         Self { color }
     }
 
-    pub fn new(r: f64, g: f64, b: f64) -> Self {
+    pub fn new(r: Float, g: Float, b: Float) -> Self {
         Self::from(Color::new(r, g, b))
     }
 }
 
 impl Texture for SolidColor {
-    fn sample(&self, _uv: &Vec2, _point: &Vec3) -> Color {
+    fn sample(&self, _uv: &Vec2, _point: &Point3) -> Color {
         self.color
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct CheckerBoard {
-    inverse_scale: f64,
+    inverse_scale: Float,
     even: Arc<dyn Texture>,
     odd: Arc<dyn Texture>,
 }
 
 impl CheckerBoard {
-    pub fn new(scale: f64, even: Arc<dyn Texture>, odd: Arc<dyn Texture>) -> Self {
+    pub fn new(scale: Float, even: Arc<dyn Texture>, odd: Arc<dyn Texture>) -> Self {
         Self {
             inverse_scale: scale.recip(),
             even: even.clone(),
@@ -48,7 +48,7 @@ impl CheckerBoard {
         }
     }
 
-    pub fn from_colors(scale: f64, even_color: Color, odd_color: Color) -> Self {
+    pub fn from_colors(scale: Float, even_color: Color, odd_color: Color) -> Self {
         let even_texture = Arc::new(SolidColor::from(even_color));
         let odd_texture = Arc::new(SolidColor::from(odd_color));
 
@@ -61,8 +61,8 @@ impl CheckerBoard {
 }
 
 impl Texture for CheckerBoard {
-    fn sample(&self, uv: &Vec2, point: &Vec3) -> Color {
-        let point = *point * self.inverse_scale;
+    fn sample(&self, uv: &Vec2, point: &Point3) -> Color {
+        let point = (point.to_vec3() * self.inverse_scale).to_point3();
         let x = point.x.floor() as i32;
         let y = point.y.floor() as i32;
         let z = point.z.floor() as i32;
@@ -95,7 +95,7 @@ impl ImageTexture {
 }
 
 impl Texture for ImageTexture {
-    fn sample(&self, uv: &Vec2, _point: &Vec3) -> Color {
+    fn sample(&self, uv: &Vec2, _point: &Point3) -> Color {
         let (_, height) = self.image.dimensions();
 
         if height == 0 {
@@ -104,14 +104,13 @@ impl Texture for ImageTexture {
         }
 
         // Just clamp instead of repeating, etc.
-        let uv = vector::clamp_vec2(uv, 0.0..1.0);
+        let uv = uv.clamp(0.0, 1.0);
 
-        // The image library uses f32 instead of f64, so we'll need
-        // to scale down to sample the texture.
-        let u = uv.x as f32;
+        // The image library samples in single precision, whatever `Float` is
+        let u = vector::to_f32(uv.x);
 
         // Invert the vertical component because images are 0 on top
-        let v = 1.0 - uv.y as f32;
+        let v = 1.0 - vector::to_f32(uv.y);
 
         let pixel_data = match imageops::sample_bilinear(&self.image, u, v) {
             Some(pixel_data) => pixel_data,
@@ -120,9 +119,9 @@ impl Texture for ImageTexture {
         };
 
         Color::new(
-            pixel_data[0] as f64,
-            pixel_data[1] as f64,
-            pixel_data[2] as f64,
+            pixel_data[0] as Float,
+            pixel_data[1] as Float,
+            pixel_data[2] as Float,
         )
     }
 }
@@ -130,24 +129,24 @@ impl Texture for ImageTexture {
 #[derive(Debug, Clone)]
 pub struct NoiseTexture {
     noise: Turbulence<Perlin, Perlin>,
-    scale: f64,
-    phase: f64,
+    scale: Float,
+    phase: Float,
 }
 
 impl NoiseTexture {
     pub fn new(
         seed: u32,
-        scale: f64,
-        phase: f64,
+        scale: Float,
+        phase: Float,
         roughness: usize,
-        frequency: f64,
-        power: f64,
+        frequency: Float,
+        power: Float,
     ) -> Self {
         let noise = Turbulence::new(Perlin::default())
             .set_seed(seed)
-            .set_power(power)
+            .set_power(vector::to_f64(power))
             .set_roughness(roughness)
-            .set_frequency(frequency);
+            .set_frequency(vector::to_f64(frequency));
 
         Self {
             noise,
@@ -158,8 +157,9 @@ impl NoiseTexture {
 }
 
 impl Texture for NoiseTexture {
-    fn sample(&self, _uv: &Vec2, point: &Vec3) -> Color {
-        let noise = self.scale * point.z + self.noise.get(*point.as_array()) * self.phase;
+    fn sample(&self, _uv: &Vec2, point: &Point3) -> Color {
+        let noise = self.scale * point.z
+            + self.noise.get(point.to_array().map(vector::to_f64)) as Float * self.phase;
         Color::new(0.5, 0.5, 0.5) * (1.0 + noise.sin())
     }
 }
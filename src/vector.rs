@@ -1,22 +1,854 @@
-//! A wrapper around GLM vectors that make it a bit more convenient to use
+//! Vectors, points, normals and matrices
+//!
+//! Points, directions and surface normals all have three coordinates, but
+//! they don't behave the same: moving a point moves it while moving a
+//! direction does nothing, and normals have to go through the inverse
+//! transpose of a transform to stay perpendicular to the surface. Keeping
+//! them as separate types lets the compiler catch mixing them up, and lets
+//! `Mat4` do the right thing for each.
+//!
+//! All three are stored as four lanes, the last one always zero, lined up
+//! so they load straight into SSE registers. With the `simd` feature on,
+//! the lane-wise arithmetic is done with SSE.
 
-use glm;
+use crate::random;
 use rand::Rng;
-use std::ops::Range;
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{
+    Add, AddAssign, Div, DivAssign, Index, IndexMut, Mul, MulAssign, Neg, Range, Sub, SubAssign,
+};
 
-pub type Vec2 = glm::DVec2;
-pub type Vec3 = glm::DVec3;
-pub type Color = glm::DVec3;
-pub type Pixel = glm::Vector3<u32>;
+/// The scalar all the geometry and shading math is done in. Building with the `f32` feature
+/// trades precision for speed and memory, and the intersection code is written to stay
+/// watertight either way.
+#[cfg(not(feature = "f32"))]
+pub type Float = f64;
+#[cfg(feature = "f32")]
+pub type Float = f32;
 
-pub fn random_vec2(range: Range<f64>) -> Vec2 {
-    let mut rng = rand::thread_rng();
+#[cfg(feature = "f32")]
+pub use std::f32::consts;
+#[cfg(not(feature = "f32"))]
+pub use std::f64::consts;
+
+/// Widens a `Float` for the things that stay in double precision whatever
+/// the build, like film statistics and the numbers we write to disk
+#[allow(clippy::unnecessary_cast)]
+pub fn to_f64(value: Float) -> f64 {
+    value as f64
+}
+
+/// Narrows a `Float` for the libraries that only work in single precision,
+/// like the image library's sampling
+#[allow(clippy::unnecessary_cast)]
+pub fn to_f32(value: Float) -> f32 {
+    value as f32
+}
+
+/// Colors are added, scaled and multiplied together just like vectors
+pub type Color = Vec3;
+
+/// Lane-wise arithmetic on four lanes at once
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+mod lanes {
+    use super::Float;
+    use std::arch::x86_64::*;
+
+    pub type Lanes = [Float; 4];
+
+    macro_rules! lanewise {
+        ($name:ident, $ps:ident, $pd:ident) => {
+            #[inline]
+            pub fn $name(a: Lanes, b: Lanes) -> Lanes {
+                let mut out = [0.0; 4];
+
+                // SSE2 is part of x86_64, so every x86_64 CPU has it
+                unsafe {
+                    #[cfg(feature = "f32")]
+                    _mm_storeu_ps(
+                        out.as_mut_ptr(),
+                        $ps(_mm_loadu_ps(a.as_ptr()), _mm_loadu_ps(b.as_ptr())),
+                    );
+
+                    #[cfg(not(feature = "f32"))]
+                    for half in [0, 2] {
+                        _mm_storeu_pd(
+                            out[half..].as_mut_ptr(),
+                            $pd(
+                                _mm_loadu_pd(a[half..].as_ptr()),
+                                _mm_loadu_pd(b[half..].as_ptr()),
+                            ),
+                        );
+                    }
+                }
+
+                out
+            }
+        };
+    }
+
+    lanewise!(add, _mm_add_ps, _mm_add_pd);
+    lanewise!(sub, _mm_sub_ps, _mm_sub_pd);
+    lanewise!(mul, _mm_mul_ps, _mm_mul_pd);
+    lanewise!(div, _mm_div_ps, _mm_div_pd);
+    lanewise!(min, _mm_min_ps, _mm_min_pd);
+    lanewise!(max, _mm_max_ps, _mm_max_pd);
+}
+
+/// Lane-wise arithmetic on four lanes at once
+#[cfg(not(all(feature = "simd", target_arch = "x86_64")))]
+mod lanes {
+    use super::Float;
+
+    pub type Lanes = [Float; 4];
+
+    macro_rules! lanewise {
+        ($name:ident, $a:ident, $b:ident => $result:expr) => {
+            #[inline]
+            pub fn $name(a: Lanes, b: Lanes) -> Lanes {
+                std::array::from_fn(|i| {
+                    let ($a, $b) = (a[i], b[i]);
+                    $result
+                })
+            }
+        };
+    }
+
+    lanewise!(add, a, b => a + b);
+    lanewise!(sub, a, b => a - b);
+    lanewise!(mul, a, b => a * b);
+    lanewise!(div, a, b => a / b);
+    // Written like SSE's, which give the second argument if either is NaN
+    lanewise!(min, a, b => if a < b { a } else { b });
+    lanewise!(max, a, b => if a > b { a } else { b });
+}
+
+/// What `Vec3`, `Point3` and `Normal3` have in common
+macro_rules! three_components {
+    ($(#[$attr:meta])* $name:ident) => {
+        $(#[$attr])*
+        #[derive(Clone, Copy, Default, PartialEq)]
+        #[repr(C, align(16))]
+        pub struct $name {
+            pub x: Float,
+            pub y: Float,
+            pub z: Float,
+            /// Pads the coordinates out to a whole SSE register, always zero
+            w: Float,
+        }
+
+        impl $name {
+            pub const fn new(x: Float, y: Float, z: Float) -> Self {
+                Self { x, y, z, w: 0.0 }
+            }
+
+            /// The same value along every axis
+            pub const fn splat(value: Float) -> Self {
+                Self::new(value, value, value)
+            }
+
+            #[inline]
+            fn lanes(self) -> lanes::Lanes {
+                [self.x, self.y, self.z, self.w]
+            }
+
+            #[inline]
+            fn from_lanes(lanes: lanes::Lanes) -> Self {
+                Self::new(lanes[0], lanes[1], lanes[2])
+            }
+
+            /// The smaller of the two along each axis
+            #[inline]
+            pub fn min(self, other: Self) -> Self {
+                Self::from_lanes(lanes::min(self.lanes(), other.lanes()))
+            }
+
+            /// The bigger of the two along each axis
+            #[inline]
+            pub fn max(self, other: Self) -> Self {
+                Self::from_lanes(lanes::max(self.lanes(), other.lanes()))
+            }
+
+            pub fn min_component(self) -> Float {
+                self.x.min(self.y).min(self.z)
+            }
+
+            pub fn max_component(self) -> Float {
+                self.x.max(self.y).max(self.z)
+            }
+
+            pub fn to_array(self) -> [Float; 3] {
+                [self.x, self.y, self.z]
+            }
+
+            pub fn is_finite(self) -> bool {
+                self.x.is_finite() && self.y.is_finite() && self.z.is_finite()
+            }
+        }
+
+        impl From<[Float; 3]> for $name {
+            fn from([x, y, z]: [Float; 3]) -> Self {
+                Self::new(x, y, z)
+            }
+        }
+
+        impl Index<usize> for $name {
+            type Output = Float;
+
+            fn index(&self, axis: usize) -> &Float {
+                match axis {
+                    0 => &self.x,
+                    1 => &self.y,
+                    2 => &self.z,
+                    _ => panic!("axis {} out of range for a 3D {}", axis, stringify!($name)),
+                }
+            }
+        }
+
+        impl IndexMut<usize> for $name {
+            fn index_mut(&mut self, axis: usize) -> &mut Float {
+                match axis {
+                    0 => &mut self.x,
+                    1 => &mut self.y,
+                    2 => &mut self.z,
+                    _ => panic!("axis {} out of range for a 3D {}", axis, stringify!($name)),
+                }
+            }
+        }
+
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.debug_tuple(stringify!($name))
+                    .field(&self.x)
+                    .field(&self.y)
+                    .field(&self.z)
+                    .finish()
+            }
+        }
+    };
+}
+
+three_components!(
+    /// A direction, an offset between two points, or a color
+    Vec3
+);
+three_components!(
+    /// A position in space
+    Point3
+);
+three_components!(
+    /// The direction a surface faces. Not necessarily of length one,
+    /// though most of the time it is.
+    Normal3
+);
+
+impl Vec3 {
+    pub const ZERO: Self = Self::splat(0.0);
+    pub const ONE: Self = Self::splat(1.0);
+    pub const X: Self = Self::new(1.0, 0.0, 0.0);
+    pub const Y: Self = Self::new(0.0, 1.0, 0.0);
+    pub const Z: Self = Self::new(0.0, 0.0, 1.0);
+
+    pub fn dot(self, other: impl Into<Vec3>) -> Float {
+        let other = other.into();
+        self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    pub fn cross(self, other: Vec3) -> Vec3 {
+        Vec3::new(
+            self.y * other.z - other.y * self.z,
+            self.z * other.x - other.z * self.x,
+            self.x * other.y - other.x * self.y,
+        )
+    }
+
+    pub fn length_squared(self) -> Float {
+        self.dot(self)
+    }
+
+    pub fn length(self) -> Float {
+        self.length_squared().sqrt()
+    }
+
+    /// The vector going the same way with a length of one
+    pub fn normalize(self) -> Vec3 {
+        self * self.length_squared().sqrt().recip()
+    }
+
+    pub fn abs(self) -> Vec3 {
+        Vec3::new(self.x.abs(), self.y.abs(), self.z.abs())
+    }
+
+    /// The square root along each axis, taking negative values as zero
+    pub fn sqrt(self) -> Vec3 {
+        Vec3::new(
+            self.x.max(0.0).sqrt(),
+            self.y.max(0.0).sqrt(),
+            self.z.max(0.0).sqrt(),
+        )
+    }
+
+    pub fn clamp(self, min: Float, max: Float) -> Vec3 {
+        Vec3::new(
+            self.x.clamp(min, max),
+            self.y.clamp(min, max),
+            self.z.clamp(min, max),
+        )
+    }
+
+    /// Whether the vector is so short along every axis that it might as well be zero
+    pub fn is_near_zero(self) -> bool {
+        const EPSILON: Float = 0.00000001;
+        let range = 0.0..EPSILON;
+        range.contains(&self.x) && range.contains(&self.y) && range.contains(&self.z)
+    }
+
+    /// The direction mirrored about `normal`, which should have a length of one
+    pub fn reflect(self, normal: impl Into<Vec3>) -> Vec3 {
+        let normal = normal.into();
+        let d = normal.dot(self);
+        self - normal * (d + d)
+    }
+
+    /// The unit direction `self` bent through a surface with the given
+    /// `normal`, where `eta` is the ratio of the refractive indices on either
+    /// side. Gives zero when the light can't get through and gets reflected.
+    pub fn refract(self, normal: impl Into<Vec3>, eta: Float) -> Vec3 {
+        let normal = normal.into();
+        let cosine = self.dot(normal);
+        let k = 1.0 - eta * eta * (1.0 - cosine * cosine);
+
+        match k < 0.0 {
+            true => Vec3::ZERO,
+            false => self * eta - normal * (eta * cosine + k.sqrt()),
+        }
+    }
+
+    /// The point this far from the origin
+    pub fn to_point3(self) -> Point3 {
+        Point3::new(self.x, self.y, self.z)
+    }
+}
+
+impl Add for Vec3 {
+    type Output = Vec3;
+
+    #[inline]
+    fn add(self, other: Vec3) -> Vec3 {
+        Vec3::from_lanes(lanes::add(self.lanes(), other.lanes()))
+    }
+}
+
+impl Sub for Vec3 {
+    type Output = Vec3;
+
+    #[inline]
+    fn sub(self, other: Vec3) -> Vec3 {
+        Vec3::from_lanes(lanes::sub(self.lanes(), other.lanes()))
+    }
+}
+
+/// Multiplies along each axis, the way colors filter each other
+impl Mul for Vec3 {
+    type Output = Vec3;
+
+    #[inline]
+    fn mul(self, other: Vec3) -> Vec3 {
+        Vec3::from_lanes(lanes::mul(self.lanes(), other.lanes()))
+    }
+}
+
+impl Div for Vec3 {
+    type Output = Vec3;
+
+    #[inline]
+    fn div(self, other: Vec3) -> Vec3 {
+        Vec3::from_lanes(lanes::div(self.lanes(), other.lanes()))
+    }
+}
+
+impl Mul<Float> for Vec3 {
+    type Output = Vec3;
+
+    #[inline]
+    fn mul(self, scale: Float) -> Vec3 {
+        self * Vec3::splat(scale)
+    }
+}
+
+impl Mul<Vec3> for Float {
+    type Output = Vec3;
+
+    #[inline]
+    fn mul(self, v: Vec3) -> Vec3 {
+        v * self
+    }
+}
+
+impl Div<Float> for Vec3 {
+    type Output = Vec3;
+
+    #[inline]
+    fn div(self, scale: Float) -> Vec3 {
+        self / Vec3::splat(scale)
+    }
+}
+
+impl Neg for Vec3 {
+    type Output = Vec3;
+
+    fn neg(self) -> Vec3 {
+        Vec3::new(-self.x, -self.y, -self.z)
+    }
+}
+
+impl AddAssign for Vec3 {
+    fn add_assign(&mut self, other: Vec3) {
+        *self = *self + other;
+    }
+}
+
+impl SubAssign for Vec3 {
+    fn sub_assign(&mut self, other: Vec3) {
+        *self = *self - other;
+    }
+}
+
+impl MulAssign for Vec3 {
+    fn mul_assign(&mut self, other: Vec3) {
+        *self = *self * other;
+    }
+}
+
+impl MulAssign<Float> for Vec3 {
+    fn mul_assign(&mut self, scale: Float) {
+        *self = *self * scale;
+    }
+}
+
+impl DivAssign<Float> for Vec3 {
+    fn div_assign(&mut self, scale: Float) {
+        *self = *self / scale;
+    }
+}
+
+impl Sum for Vec3 {
+    fn sum<I: Iterator<Item = Vec3>>(iter: I) -> Vec3 {
+        iter.fold(Vec3::ZERO, Add::add)
+    }
+}
+
+impl From<Normal3> for Vec3 {
+    fn from(normal: Normal3) -> Vec3 {
+        Vec3::new(normal.x, normal.y, normal.z)
+    }
+}
+
+impl Point3 {
+    pub const ORIGIN: Self = Self::splat(0.0);
+
+    /// The offset of the point from the origin
+    pub fn to_vec3(self) -> Vec3 {
+        Vec3::new(self.x, self.y, self.z)
+    }
+
+    /// How far the point is from the origin along each axis
+    pub fn abs(self) -> Vec3 {
+        self.to_vec3().abs()
+    }
+
+    pub fn distance(self, other: Point3) -> Float {
+        (self - other).length()
+    }
+
+    pub fn distance_squared(self, other: Point3) -> Float {
+        (self - other).length_squared()
+    }
+
+    /// The point `t` of the way from `self` to `other`
+    pub fn lerp(self, other: Point3, t: Float) -> Point3 {
+        self + (other - self) * t
+    }
+}
+
+impl Add<Vec3> for Point3 {
+    type Output = Point3;
+
+    #[inline]
+    fn add(self, offset: Vec3) -> Point3 {
+        Point3::from_lanes(lanes::add(self.lanes(), offset.lanes()))
+    }
+}
+
+impl Sub<Vec3> for Point3 {
+    type Output = Point3;
+
+    #[inline]
+    fn sub(self, offset: Vec3) -> Point3 {
+        Point3::from_lanes(lanes::sub(self.lanes(), offset.lanes()))
+    }
+}
+
+/// The offset that takes `other` to `self`
+impl Sub for Point3 {
+    type Output = Vec3;
+
+    #[inline]
+    fn sub(self, other: Point3) -> Vec3 {
+        Vec3::from_lanes(lanes::sub(self.lanes(), other.lanes()))
+    }
+}
+
+impl AddAssign<Vec3> for Point3 {
+    fn add_assign(&mut self, offset: Vec3) {
+        *self = *self + offset;
+    }
+}
+
+impl SubAssign<Vec3> for Point3 {
+    fn sub_assign(&mut self, offset: Vec3) {
+        *self = *self - offset;
+    }
+}
+
+impl Normal3 {
+    pub fn dot(self, other: impl Into<Vec3>) -> Float {
+        self.to_vec3().dot(other)
+    }
+
+    pub fn length(self) -> Float {
+        self.to_vec3().length()
+    }
+
+    pub fn normalize(self) -> Normal3 {
+        self.to_vec3().normalize().into()
+    }
+
+    pub fn abs(self) -> Vec3 {
+        self.to_vec3().abs()
+    }
+
+    pub fn to_vec3(self) -> Vec3 {
+        Vec3::from(self)
+    }
+
+    /// The normal flipped if need be to be on the same side as `v`
+    pub fn face_forward(self, v: impl Into<Vec3>) -> Normal3 {
+        match self.dot(v) < 0.0 {
+            true => -self,
+            false => self,
+        }
+    }
+}
+
+impl Neg for Normal3 {
+    type Output = Normal3;
+
+    fn neg(self) -> Normal3 {
+        Normal3::new(-self.x, -self.y, -self.z)
+    }
+}
+
+impl Mul<Float> for Normal3 {
+    type Output = Normal3;
+
+    #[inline]
+    fn mul(self, scale: Float) -> Normal3 {
+        Normal3::from_lanes(lanes::mul(self.lanes(), Vec3::splat(scale).lanes()))
+    }
+}
+
+/// Pushing off a surface, e.g. to pick a direction around the normal
+impl Add<Vec3> for Normal3 {
+    type Output = Vec3;
+
+    #[inline]
+    fn add(self, v: Vec3) -> Vec3 {
+        self.to_vec3() + v
+    }
+}
+
+impl From<Vec3> for Normal3 {
+    fn from(v: Vec3) -> Normal3 {
+        Normal3::new(v.x, v.y, v.z)
+    }
+}
+
+/// A 2D vector, for texture coordinates and the like
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Vec2 {
+    pub x: Float,
+    pub y: Float,
+}
+
+impl Vec2 {
+    pub const fn new(x: Float, y: Float) -> Self {
+        Self { x, y }
+    }
+
+    pub fn length_squared(self) -> Float {
+        self.x * self.x + self.y * self.y
+    }
+
+    pub fn clamp(self, min: Float, max: Float) -> Vec2 {
+        Vec2::new(self.x.clamp(min, max), self.y.clamp(min, max))
+    }
+}
+
+impl Add for Vec2 {
+    type Output = Vec2;
+
+    fn add(self, other: Vec2) -> Vec2 {
+        Vec2::new(self.x + other.x, self.y + other.y)
+    }
+}
+
+impl Sub for Vec2 {
+    type Output = Vec2;
+
+    fn sub(self, other: Vec2) -> Vec2 {
+        Vec2::new(self.x - other.x, self.y - other.y)
+    }
+}
+
+impl Mul<Float> for Vec2 {
+    type Output = Vec2;
+
+    fn mul(self, scale: Float) -> Vec2 {
+        Vec2::new(self.x * scale, self.y * scale)
+    }
+}
+
+impl Div<Float> for Vec2 {
+    type Output = Vec2;
+
+    fn div(self, scale: Float) -> Vec2 {
+        Vec2::new(self.x / scale, self.y / scale)
+    }
+}
+
+impl Index<usize> for Vec2 {
+    type Output = Float;
+
+    fn index(&self, axis: usize) -> &Float {
+        match axis {
+            0 => &self.x,
+            1 => &self.y,
+            _ => panic!("axis {} out of range for a Vec2", axis),
+        }
+    }
+}
+
+/// A color ready to be written out, with 0 to 255 in each channel
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Pixel {
+    pub x: u32,
+    pub y: u32,
+    pub z: u32,
+}
+
+impl Pixel {
+    pub const fn new(x: u32, y: u32, z: u32) -> Self {
+        Self { x, y, z }
+    }
+
+    /// Cuts the fraction off each channel of an already scaled color
+    pub fn from_color(color: &Color) -> Self {
+        Self::new(color.x as u32, color.y as u32, color.z as u32)
+    }
+}
+
+/// A 4x4 matrix, for transforms made of rotations, scales and translations
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mat4 {
+    rows: [[Float; 4]; 4],
+}
+
+impl Default for Mat4 {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl Mat4 {
+    pub const IDENTITY: Self = Self::from_rows([
+        [1.0, 0.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0, 0.0],
+        [0.0, 0.0, 1.0, 0.0],
+        [0.0, 0.0, 0.0, 1.0],
+    ]);
+
+    pub const fn from_rows(rows: [[Float; 4]; 4]) -> Self {
+        Self { rows }
+    }
+
+    pub fn rows(&self) -> &[[Float; 4]; 4] {
+        &self.rows
+    }
+
+    pub fn translation(offset: Vec3) -> Self {
+        Self::from_rows([
+            [1.0, 0.0, 0.0, offset.x],
+            [0.0, 1.0, 0.0, offset.y],
+            [0.0, 0.0, 1.0, offset.z],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    /// Scales away from the origin by a factor along each axis
+    pub fn scaling(factors: Vec3) -> Self {
+        Self::from_rows([
+            [factors.x, 0.0, 0.0, 0.0],
+            [0.0, factors.y, 0.0, 0.0],
+            [0.0, 0.0, factors.z, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    /// A rotation of `angle` degrees around `axis`,
+    /// counterclockwise when looking down the axis at the origin
+    pub fn rotation(axis: Vec3, angle: Float) -> Self {
+        let axis = axis.normalize();
+        let (sin, cos) = angle.to_radians().sin_cos();
+        let k = 1.0 - cos;
+
+        Self::from_rows([
+            [
+                cos + axis.x * axis.x * k,
+                axis.x * axis.y * k - axis.z * sin,
+                axis.x * axis.z * k + axis.y * sin,
+                0.0,
+            ],
+            [
+                axis.y * axis.x * k + axis.z * sin,
+                cos + axis.y * axis.y * k,
+                axis.y * axis.z * k - axis.x * sin,
+                0.0,
+            ],
+            [
+                axis.z * axis.x * k - axis.y * sin,
+                axis.z * axis.y * k + axis.x * sin,
+                cos + axis.z * axis.z * k,
+                0.0,
+            ],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn transpose(&self) -> Self {
+        Self::from_rows(std::array::from_fn(|i| {
+            std::array::from_fn(|j| self.rows[j][i])
+        }))
+    }
+
+    /// The matrix that undoes this one, or `None` if it squashes space flat.
+    /// Done with Gauss-Jordan elimination, picking the biggest pivot each time.
+    pub fn inverse(&self) -> Option<Self> {
+        let mut left = self.rows;
+        let mut right = Self::IDENTITY.rows;
+
+        for column in 0..4 {
+            let pivot = (column..4)
+                .max_by(|&a, &b| left[a][column].abs().total_cmp(&left[b][column].abs()))
+                .unwrap();
+
+            if left[pivot][column] == 0.0 {
+                return None;
+            }
+
+            left.swap(column, pivot);
+            right.swap(column, pivot);
+
+            let scale = left[column][column].recip();
+
+            for j in 0..4 {
+                left[column][j] *= scale;
+                right[column][j] *= scale;
+            }
+
+            for row in (0..4).filter(|&row| row != column) {
+                let factor = left[row][column];
+
+                for j in 0..4 {
+                    left[row][j] -= factor * left[column][j];
+                    right[row][j] -= factor * right[column][j];
+                }
+            }
+        }
+
+        Some(Self::from_rows(right))
+    }
+
+    /// The matrix with every entry made positive, for bounding rounding errors
+    pub fn abs(&self) -> Self {
+        Self::from_rows(self.rows.map(|row| row.map(Float::abs)))
+    }
+
+    /// Moves, rotates and scales a point. Matrices with a bottom row other
+    /// than `0 0 0 1` divide by the fourth coordinate the point ends up with.
+    pub fn transform_point(&self, point: Point3) -> Point3 {
+        let [x, y, z, w] = self
+            .rows
+            .map(|row| row[0] * point.x + row[1] * point.y + row[2] * point.z + row[3]);
+
+        match w == 1.0 {
+            true => Point3::new(x, y, z),
+            false => Point3::new(x / w, y / w, z / w),
+        }
+    }
+
+    /// Rotates and scales a vector, leaving out any translation
+    pub fn transform_vector(&self, v: Vec3) -> Vec3 {
+        let [x, y, z, _] = self
+            .rows
+            .map(|row| row[0] * v.x + row[1] * v.y + row[2] * v.z);
+
+        Vec3::new(x, y, z)
+    }
+
+    /// Transforms a normal by this matrix's transpose. Normals have to go
+    /// through the inverse transpose of whatever transformed the surface to
+    /// stay perpendicular to it, so call this on the inverse of that.
+    /// The result isn't normalized.
+    pub fn transform_normal(&self, normal: Normal3) -> Normal3 {
+        let m = &self.rows;
+
+        Normal3::new(
+            m[0][0] * normal.x + m[1][0] * normal.y + m[2][0] * normal.z,
+            m[0][1] * normal.x + m[1][1] * normal.y + m[2][1] * normal.z,
+            m[0][2] * normal.x + m[1][2] * normal.y + m[2][2] * normal.z,
+        )
+    }
+}
+
+impl Mul for Mat4 {
+    type Output = Mat4;
+
+    /// The transform that does `other` and then `self`
+    fn mul(self, other: Mat4) -> Mat4 {
+        Mat4::from_rows(std::array::from_fn(|i| {
+            std::array::from_fn(|j| (0..4).map(|k| self.rows[i][k] * other.rows[k][j]).sum())
+        }))
+    }
+}
+
+impl Index<(usize, usize)> for Mat4 {
+    type Output = Float;
+
+    fn index(&self, (row, column): (usize, usize)) -> &Float {
+        &self.rows[row][column]
+    }
+}
+
+pub fn random_vec2(range: Range<Float>) -> Vec2 {
+    let mut rng = random::rng();
 
     Vec2::new(rng.gen_range(range.clone()), rng.gen_range(range.clone()))
 }
 
-pub fn random_vec3(range: Range<f64>) -> Vec3 {
-    let mut rng = rand::thread_rng();
+pub fn random_vec3(range: Range<Float>) -> Vec3 {
+    let mut rng = random::rng();
 
     Vec3::new(
         rng.gen_range(range.clone()),
@@ -26,7 +858,7 @@ pub fn random_vec3(range: Range<f64>) -> Vec3 {
 }
 
 pub fn random_unit_vec3() -> Vec3 {
-    glm::normalize(random_vec3(0.0..1.0))
+    random_vec3(0.0..1.0).normalize()
 }
 
 pub fn random_sphere_vec() -> Vec3 {
@@ -36,28 +868,28 @@ pub fn random_sphere_vec() -> Vec3 {
     loop {
         let attempt = random_vec3(-1.0..1.0);
 
-        if glm::ext::sqlength(attempt) < 1.0 {
+        if attempt.length_squared() < 1.0 {
             return attempt;
         }
     }
 }
 
 pub fn random_unit_sphere_vec() -> Vec3 {
-    glm::normalize(random_sphere_vec())
+    random_sphere_vec().normalize()
 }
 
-pub fn random_hemisphere_vec(normal: glm::DVec3) -> Vec3 {
+pub fn random_hemisphere_vec(normal: Normal3) -> Vec3 {
     let mut sphere_vec = random_sphere_vec();
 
-    if glm::dot(sphere_vec, normal) < 0.0 {
+    if sphere_vec.dot(normal) < 0.0 {
         sphere_vec = -sphere_vec;
     }
 
     sphere_vec
 }
 
-pub fn random_unit_hemisphere_vec(normal: glm::DVec3) -> Vec3 {
-    glm::normalize(random_hemisphere_vec(normal))
+pub fn random_unit_hemisphere_vec(normal: Normal3) -> Vec3 {
+    random_hemisphere_vec(normal).normalize()
 }
 
 pub fn random_unit_disk_vec() -> Vec2 {
@@ -67,67 +899,27 @@ pub fn random_unit_disk_vec() -> Vec2 {
     loop {
         let attempt = random_vec2(-1.0..1.0);
 
-        if glm::ext::sqlength(attempt) < 1.0 {
+        if attempt.length_squared() < 1.0 {
             return attempt;
         }
     }
 }
 
 pub fn random_unit_square_vec() -> Vec3 {
-    let mut rng = rand::thread_rng();
-    let x: f64 = rng.gen();
-    let y: f64 = rng.gen();
+    let mut rng = random::rng();
+    let x: Float = rng.gen();
+    let y: Float = rng.gen();
 
     Vec3::new(x - 0.5, y - 0.5, 0.0)
 }
 
-pub fn is_vec_near_zero(v: &Vec3) -> bool {
-    static EPSILON: f64 = 0.00000001;
-    static RANGE: Range<f64> = 0.0..EPSILON;
-    RANGE.contains(&v.x) && RANGE.contains(&v.y) && RANGE.contains(&v.z)
-}
-
-pub fn sqrt_vec(v: &Vec3) -> Vec3 {
-    Vec3::new(
-        f64::max(v.x, 0.0).sqrt(),
-        f64::max(v.y, 0.0).sqrt(),
-        f64::max(v.z, 0.0).sqrt(),
-    )
-}
-
-pub fn clamp_vec2(v: &Vec2, range: Range<f64>) -> Vec2 {
-    Vec2::new(
-        v.x.clamp(range.start, range.end),
-        v.y.clamp(range.start, range.end),
-    )
-}
-
-pub fn clamp_vec3(v: &Vec3, range: Range<f64>) -> Vec3 {
-    Vec3::new(
-        v.x.clamp(range.start, range.end),
-        v.y.clamp(range.start, range.end),
-        v.z.clamp(range.start, range.end),
-    )
-}
-
-pub fn zero_vec3() -> Vec3 {
-    Vec3::new(0.0, 0.0, 0.0)
-}
-
-pub fn one_vec3() -> Vec3 {
-    Vec3::new(1.0, 1.0, 1.0)
-}
-
-pub fn up_vec3() -> Vec3 {
-    Vec3::new(0.0, 1.0, 0.0)
-}
-
-pub fn color_to_pixel(v: &Color) -> Pixel {
-    Pixel::new(v.x as u32, v.y as u32, v.z as u32)
+/// The perceived brightness of a color, using the Rec. 709 weights
+pub fn luminance(color: &Color) -> Float {
+    0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z
 }
 
 pub fn random_color_range(range: Range<u32>) -> Pixel {
-    let mut rng = rand::thread_rng();
+    let mut rng = random::rng();
 
     Pixel {
         x: rng.gen_range(range.clone()),
@@ -140,10 +932,216 @@ pub fn random_color() -> Pixel {
     random_color_range(0..255)
 }
 
-pub fn min_vec3(a: &Vec3, b: &Vec3) -> Vec3 {
-    Vec3::new(f64::min(a.x, b.x), f64::min(a.y, b.y), f64::min(a.z, b.z))
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(a: Vec3, b: Vec3) {
+        assert!((a - b).abs().max_component() < 1e-5, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn layout_fills_a_register() {
+        assert_eq!(
+            std::mem::size_of::<Vec3>(),
+            4 * std::mem::size_of::<Float>()
+        );
+        assert_eq!(std::mem::align_of::<Point3>(), 16);
+    }
+
+    #[test]
+    fn arithmetic_is_lane_wise() {
+        let a = Vec3::new(1.0, 2.0, 3.0);
+        let b = Vec3::new(4.0, -5.0, 6.0);
+
+        assert_eq!(a + b, Vec3::new(5.0, -3.0, 9.0));
+        assert_eq!(a - b, Vec3::new(-3.0, 7.0, -3.0));
+        assert_eq!(a * b, Vec3::new(4.0, -10.0, 18.0));
+        assert_eq!(b / a, Vec3::new(4.0, -2.5, 2.0));
+        assert_eq!(a * 2.0, 2.0 * a);
+        assert_eq!(a / 2.0, Vec3::new(0.5, 1.0, 1.5));
+        assert_eq!(-a, Vec3::new(-1.0, -2.0, -3.0));
+        assert_eq!(a.min(b), Vec3::new(1.0, -5.0, 3.0));
+        assert_eq!(a.max(b), Vec3::new(4.0, 2.0, 6.0));
+        assert_eq!([a, b].into_iter().sum::<Vec3>(), a + b);
+
+        let mut c = a;
+        c += b;
+        c -= a;
+        c *= 2.0;
+        c /= 2.0;
+        assert_eq!(c, b);
+    }
 
-pub fn max_vec3(a: &Vec3, b: &Vec3) -> Vec3 {
-    Vec3::new(f64::max(a.x, b.x), f64::max(a.y, b.y), f64::max(a.z, b.z))
+    #[test]
+    fn dot_cross_and_length() {
+        assert_eq!(Vec3::X.cross(Vec3::Y), Vec3::Z);
+        assert_eq!(Vec3::Y.cross(Vec3::X), -Vec3::Z);
+        assert_eq!(Vec3::new(1.0, 2.0, 3.0).dot(Vec3::new(4.0, 5.0, 6.0)), 32.0);
+
+        let v = Vec3::new(3.0, 4.0, 0.0);
+        assert_eq!(v.length(), 5.0);
+        assert_eq!(v.length_squared(), 25.0);
+        assert_near(v.normalize(), Vec3::new(0.6, 0.8, 0.0));
+    }
+
+    #[test]
+    fn helpers() {
+        assert_eq!(Vec3::new(4.0, -1.0, 9.0).sqrt(), Vec3::new(2.0, 0.0, 3.0));
+        assert_eq!(
+            Vec3::new(-1.0, 0.5, 2.0).clamp(0.0, 1.0),
+            Vec3::new(0.0, 0.5, 1.0)
+        );
+        assert!(Vec3::splat(1e-9).is_near_zero());
+        assert!(!Vec3::new(0.0, 0.1, 0.0).is_near_zero());
+        assert_eq!(Vec3::new(1.0, -7.0, 3.0).min_component(), -7.0);
+        assert_eq!(Vec3::new(1.0, -7.0, 3.0).max_component(), 3.0);
+        assert!(!Vec3::new(1.0, Float::NAN, 0.0).is_finite());
+        assert_eq!(luminance(&Color::ONE), 1.0);
+    }
+
+    #[test]
+    fn indexing_by_axis() {
+        let mut v = Vec3::new(1.0, 2.0, 3.0);
+        v[1] = 5.0;
+
+        assert_eq!([v[0], v[1], v[2]], [1.0, 5.0, 3.0]);
+        assert_eq!(v.to_array(), [1.0, 5.0, 3.0]);
+        assert_eq!(Vec3::from([1.0, 5.0, 3.0]), v);
+    }
+
+    #[test]
+    #[should_panic]
+    fn indexing_past_z_panics() {
+        let _ = Point3::ORIGIN[3];
+    }
+
+    #[test]
+    fn reflect_and_refract() {
+        let down = Vec3::new(1.0, -1.0, 0.0).normalize();
+
+        assert_near(
+            down.reflect(Normal3::new(0.0, 1.0, 0.0)),
+            Vec3::new(1.0, 1.0, 0.0).normalize(),
+        );
+
+        // Straight through when both sides bend light the same
+        assert_near(down.refract(Vec3::Y, 1.0), down);
+
+        // Too shallow to leave a denser medium
+        let grazing = Vec3::new(1.0, 0.1, 0.0).normalize();
+        assert_eq!(grazing.refract(-Vec3::Y, 1.5), Vec3::ZERO);
+    }
+
+    #[test]
+    fn points_and_offsets() {
+        let a = Point3::new(1.0, 2.0, 3.0);
+        let b = Point3::new(4.0, 6.0, 3.0);
+
+        assert_eq!(b - a, Vec3::new(3.0, 4.0, 0.0));
+        assert_eq!(a + (b - a), b);
+        assert_eq!(b - (b - a), a);
+        assert_eq!(a.distance(b), 5.0);
+        assert_eq!(a.distance_squared(b), 25.0);
+        assert_eq!(a.lerp(b, 0.5), Point3::new(2.5, 4.0, 3.0));
+        assert_eq!(a.to_vec3().to_point3(), a);
+    }
+
+    #[test]
+    fn normals() {
+        let n = Normal3::new(0.0, 2.0, 0.0);
+
+        assert_eq!(n.normalize(), Normal3::new(0.0, 1.0, 0.0));
+        assert_eq!(n.length(), 2.0);
+        assert_eq!(n.face_forward(-Vec3::Y), -n);
+        assert_eq!(n.face_forward(Vec3::Y), n);
+        assert_eq!(Vec3::from(n), Vec3::new(0.0, 2.0, 0.0));
+    }
+
+    #[test]
+    fn translation_moves_points_but_not_vectors() {
+        let m = Mat4::translation(Vec3::new(1.0, 2.0, 3.0));
+
+        assert_eq!(
+            m.transform_point(Point3::ORIGIN),
+            Point3::new(1.0, 2.0, 3.0)
+        );
+        assert_eq!(m.transform_vector(Vec3::X), Vec3::X);
+    }
+
+    #[test]
+    fn rotation_is_counterclockwise() {
+        let m = Mat4::rotation(Vec3::Z, 90.0);
+
+        assert_near(m.transform_vector(Vec3::X), Vec3::Y);
+        assert_near(
+            m.transform_point(Point3::new(0.0, 1.0, 0.0)).to_vec3(),
+            -Vec3::X,
+        );
+    }
+
+    #[test]
+    fn normals_stay_perpendicular_under_uneven_scaling() {
+        let m = Mat4::scaling(Vec3::new(4.0, 1.0, 1.0));
+        let tangent = Vec3::new(1.0, -1.0, 0.0);
+        let normal = Normal3::new(1.0, 1.0, 0.0);
+
+        let tangent = m.transform_vector(tangent);
+        let normal = m.inverse().unwrap().transform_normal(normal);
+
+        assert!(normal.dot(tangent).abs() < 1e-6);
+    }
+
+    #[test]
+    fn products_apply_right_to_left() {
+        let translate = Mat4::translation(Vec3::X);
+        let scale = Mat4::scaling(Vec3::splat(2.0));
+        let point = Point3::new(1.0, 0.0, 0.0);
+
+        assert_eq!(
+            (translate * scale).transform_point(point),
+            Point3::new(3.0, 0.0, 0.0)
+        );
+        assert_eq!(
+            (scale * translate).transform_point(point),
+            Point3::new(4.0, 0.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn inverse_undoes_the_transform() {
+        let m = Mat4::translation(Vec3::new(5.0, -2.0, 1.0))
+            * Mat4::rotation(Vec3::new(1.0, 1.0, 0.0), 30.0)
+            * Mat4::scaling(Vec3::new(2.0, 3.0, 0.5));
+        let inverse = m.inverse().unwrap();
+        let identity = m * inverse;
+
+        for i in 0..4 {
+            for j in 0..4 {
+                assert!((identity[(i, j)] - Mat4::IDENTITY[(i, j)]).abs() < 1e-5);
+            }
+        }
+
+        let point = Point3::new(1.0, 2.0, 3.0);
+        assert_near(
+            inverse.transform_point(m.transform_point(point)).to_vec3(),
+            point.to_vec3(),
+        );
+        assert_eq!(m.transpose().transpose(), m);
+    }
+
+    #[test]
+    fn flat_matrices_have_no_inverse() {
+        assert!(Mat4::scaling(Vec3::new(1.0, 0.0, 1.0)).inverse().is_none());
+    }
+
+    #[test]
+    fn random_vectors_land_where_asked() {
+        for _ in 0..100 {
+            assert!(random_sphere_vec().length_squared() < 1.0);
+            assert!(random_unit_disk_vec().length_squared() < 1.0);
+            assert!((random_unit_sphere_vec().length() - 1.0).abs() < 1e-5);
+            assert!(random_hemisphere_vec(Normal3::new(0.0, 1.0, 0.0)).y >= 0.0);
+        }
+    }
 }